@@ -0,0 +1,220 @@
+// the simplest possible ide disk driver: pio mode, 28 bit lba, polling.
+// every sector goes through the data port one word at a time, which is slow, but we only ever move save files.
+
+use x86_64::instructions::port::Port;
+
+use crate::serial_println;
+
+pub const SECTOR_SIZE: usize = 512;
+
+// register offsets from the bus's io base.
+const DATA: u16 = 0;
+const SECTOR_COUNT: u16 = 2;
+const LBA_LOW: u16 = 3;
+const LBA_MID: u16 = 4;
+const LBA_HIGH: u16 = 5;
+const DRIVE_SELECT: u16 = 6;
+const COMMAND: u16 = 7;
+const STATUS: u16 = 7;
+
+const CMD_READ_SECTORS: u8 = 0x20;
+const CMD_WRITE_SECTORS: u8 = 0x30;
+const CMD_CACHE_FLUSH: u8 = 0xE7;
+const CMD_IDENTIFY: u8 = 0xEC;
+
+const STATUS_ERR: u8 = 1 << 0;
+const STATUS_DRQ: u8 = 1 << 3;
+const STATUS_DF: u8 = 1 << 5;
+const STATUS_BSY: u8 = 1 << 7;
+
+// how many times to read status before giving up on a drive that stays busy. each read is an io port access, about
+// a microsecond, so this is a second or so, far longer than any command should take.
+const BUSY_POLLS: u32 = 1_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bus
+{
+    Primary = 0x1F0,
+    Secondary = 0x170,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Drive
+{
+    Master = 0xE0,
+    Slave = 0xF0,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Disk
+{
+    bus: Bus,
+    drive: Drive,
+    pub sectors: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AtaError
+{
+    DeviceFault,
+    Error,
+    OutOfRange,
+    // still busy after BUSY_POLLS, or never asked for data.
+    Timeout,
+}
+
+impl Disk
+{
+    fn port<T: x86_64::instructions::port::PortRead + x86_64::instructions::port::PortWrite>(&self, register: u16) -> Port<T>
+    {
+        Port::new(self.bus as u16 + register)
+    }
+
+    fn status(&self) -> u8
+    {
+        unsafe { self.port::<u8>(STATUS).read() }
+    }
+
+    // reading status four times gives the drive the 400ns it needs after a drive select.
+    fn delay(&self)
+    {
+        for _ in 0..4
+        {
+            self.status();
+        }
+    }
+
+    fn wait_ready(&self) -> Result<(), AtaError>
+    {
+        for _ in 0..BUSY_POLLS
+        {
+            let status = self.status();
+            if status & STATUS_BSY != 0 {
+                continue;
+            }
+            if status & STATUS_DF != 0 {
+                return Err(AtaError::DeviceFault);
+            }
+            if status & STATUS_ERR != 0 {
+                return Err(AtaError::Error);
+            }
+            return Ok(());
+        }
+        Err(AtaError::Timeout)
+    }
+
+    fn wait_drq(&self) -> Result<(), AtaError>
+    {
+        for _ in 0..BUSY_POLLS
+        {
+            self.wait_ready()?;
+            if self.status() & STATUS_DRQ != 0 {
+                return Ok(());
+            }
+        }
+        Err(AtaError::Timeout)
+    }
+
+    // ask a drive to identify itself. no answer means nothing's plugged in there (or it's an atapi cdrom).
+    pub fn identify(bus: Bus, drive: Drive) -> Option<Disk>
+    {
+        let mut disk = Disk { bus, drive, sectors: 0 };
+
+        x86_64::instructions::interrupts::without_interrupts(|| unsafe {
+            // 0xff on the status port means the bus is floating, nothing's there at all.
+            if disk.status() == 0xFF {
+                return None;
+            }
+
+            disk.port::<u8>(DRIVE_SELECT).write(drive as u8 & 0xF0 | 0xA0);
+            disk.delay();
+            disk.port::<u8>(SECTOR_COUNT).write(0);
+            disk.port::<u8>(LBA_LOW).write(0);
+            disk.port::<u8>(LBA_MID).write(0);
+            disk.port::<u8>(LBA_HIGH).write(0);
+            disk.port::<u8>(COMMAND).write(CMD_IDENTIFY);
+
+            if disk.status() == 0 {
+                return None;
+            }
+            // a packet device answers with an error, which is no disk for us either.
+            disk.wait_ready().ok()?;
+            // packet devices put a signature in the lba registers instead of answering.
+            if disk.port::<u8>(LBA_MID).read() != 0 || disk.port::<u8>(LBA_HIGH).read() != 0 {
+                return None;
+            }
+            disk.wait_drq().ok()?;
+
+            let mut identify = [0u16; 256];
+            for word in identify.iter_mut()
+            {
+                *word = disk.port::<u16>(DATA).read();
+            }
+            // words 60-61 are the number of lba28 addressable sectors.
+            disk.sectors = identify[60] as u32 | (identify[61] as u32) << 16;
+            Some(())
+        })?;
+
+        serial_println!("ata: {:?} {:?}, {} sectors", bus, drive, disk.sectors);
+        Some(disk)
+    }
+
+    fn select(&self, lba: u32, count: u8)
+    {
+        unsafe {
+            self.port::<u8>(DRIVE_SELECT).write(self.drive as u8 | ((lba >> 24) & 0x0F) as u8);
+            self.port::<u8>(SECTOR_COUNT).write(count);
+            self.port::<u8>(LBA_LOW).write(lba as u8);
+            self.port::<u8>(LBA_MID).write((lba >> 8) as u8);
+            self.port::<u8>(LBA_HIGH).write((lba >> 16) as u8);
+        }
+    }
+
+    pub fn read_sector(&self, lba: u32, buf: &mut [u8; SECTOR_SIZE]) -> Result<(), AtaError>
+    {
+        if lba >= self.sectors {
+            return Err(AtaError::OutOfRange);
+        }
+
+        x86_64::instructions::interrupts::without_interrupts(|| {
+            self.wait_ready()?;
+            self.select(lba, 1);
+            unsafe { self.port::<u8>(COMMAND).write(CMD_READ_SECTORS) };
+            self.wait_drq()?;
+
+            let mut data = self.port::<u16>(DATA);
+            for i in 0..SECTOR_SIZE / 2
+            {
+                let word = unsafe { data.read() };
+                buf[i * 2] = word as u8;
+                buf[i * 2 + 1] = (word >> 8) as u8;
+            }
+            Ok(())
+        })
+    }
+
+    pub fn write_sector(&self, lba: u32, buf: &[u8; SECTOR_SIZE]) -> Result<(), AtaError>
+    {
+        if lba >= self.sectors {
+            return Err(AtaError::OutOfRange);
+        }
+
+        x86_64::instructions::interrupts::without_interrupts(|| {
+            self.wait_ready()?;
+            self.select(lba, 1);
+            unsafe { self.port::<u8>(COMMAND).write(CMD_WRITE_SECTORS) };
+            self.wait_drq()?;
+
+            let mut data = self.port::<u16>(DATA);
+            for i in 0..SECTOR_SIZE / 2
+            {
+                let word = buf[i * 2] as u16 | (buf[i * 2 + 1] as u16) << 8;
+                unsafe { data.write(word) };
+            }
+
+            // make sure it's actually on the platter before we say it's saved.
+            unsafe { self.port::<u8>(COMMAND).write(CMD_CACHE_FLUSH) };
+            self.wait_ready()
+        })
+    }
+}
@@ -0,0 +1,44 @@
+// boot options. the bootloader doesn't give us a command line, so qemu passes one in through fw_cfg instead:
+//   -fw_cfg name=opt/nesos/cmdline,string="audio=sb16 region=pal"
+// space separated key=value pairs, a bare key means "key=1". anything not given falls back to the caller's default.
+extern crate alloc;
+
+use alloc::string::String;
+use lazy_static::lazy_static;
+
+use crate::{fw_cfg, serial_println};
+
+const CMDLINE_FILE: &str = "opt/nesos/cmdline";
+
+lazy_static! {
+    // read once, the first time anyone asks for an option. needs the heap.
+    static ref CMDLINE: String = {
+        let line = fw_cfg::read_file(CMDLINE_FILE)
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+            .unwrap_or_default();
+        serial_println!("boot options: {:?}", line);
+        line
+    };
+}
+
+pub fn get(key: &str) -> Option<&'static str>
+{
+    CMDLINE.split_whitespace().find_map(|option| {
+        let mut parts = option.splitn(2, '=');
+        if parts.next() == Some(key) {
+            Some(parts.next().unwrap_or("1"))
+        } else {
+            None
+        }
+    })
+}
+
+pub fn get_or<'a>(key: &str, default: &'a str) -> &'a str
+{
+    get(key).unwrap_or(default)
+}
+
+pub fn flag(key: &str) -> bool
+{
+    matches!(get(key), Some(v) if v != "0")
+}
@@ -0,0 +1,184 @@
+// records exactly what the apu hands to Speaker::queue, as a 16 bit mono pcm wav.
+// there's nowhere to put a file yet, so it's streamed out over SERIAL1 as base64, one line at a time, every line
+// tagged so it can be picked out of the rest of the serial log:
+//
+//   WAV BEGIN <rate>
+//   WAV <base64>
+//   ...
+//   WAV END <samples>
+//
+// strip the prefixes, concatenate and decode, and you have a wav file. the header is written before we know how
+// long the recording is, so its sizes are 0xffffffff like any other streamed wav. most tools are fine with that,
+// and the END line has the real sample count if something isn't.
+// diffing two of these is how you tell whether a kernel build changed what a rom sounds like.
+//
+// what turns it on and off:
+//   capture=<frames>   from power-on for that many frames, then END. what a headless runner wants
+//   capture=on         from power-on until scroll lock or the emulator stops
+//   scroll lock        starts or ends one whenever
+// building with --features audio-capture is the same as capture=on. whatever's still going when the emulator stops
+// gets its END line then.
+use core::sync::atomic::{AtomicBool, Ordering};
+use pc_keyboard::KeyCode;
+
+use crate::{cmdline, serial_println};
+
+// whether there should be a capture going. TerminalAudio starts and finishes one on its next sample to match.
+static WANTED: AtomicBool = AtomicBool::new(false);
+
+pub fn wanted() -> bool
+{
+    WANTED.load(Ordering::Relaxed)
+}
+
+pub fn handle_key(key: KeyCode) -> bool
+{
+    if key != KeyCode::ScrollLock {
+        return false;
+    }
+    WANTED.fetch_xor(true, Ordering::Relaxed);
+    true
+}
+
+// the boot option's side of it, ticked once a frame by the run loop.
+pub struct Trigger
+{
+    frames_left: Option<u64>,
+}
+
+impl Trigger
+{
+    pub fn new() -> Trigger
+    {
+        // a bare "capture" comes through as 1, which means on rather than one frame.
+        let frames_left = cmdline::get("capture").and_then(|o| o.parse().ok()).filter(|&frames| frames > 1);
+        WANTED.store(cmdline::flag("capture") || cfg!(feature = "audio-capture"), Ordering::Relaxed);
+        Trigger { frames_left }
+    }
+
+    pub fn frame(&mut self)
+    {
+        if let Some(frames) = &mut self.frames_left {
+            *frames -= 1;
+            if *frames == 0 {
+                WANTED.store(false, Ordering::Relaxed);
+                self.frames_left = None;
+            }
+        }
+    }
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// 57 bytes of input is exactly 76 characters of base64, the traditional line length.
+const LINE_BYTES: usize = 57;
+const LINE_CHARS: usize = LINE_BYTES / 3 * 4;
+
+pub struct WavCapture
+{
+    pending: [u8; LINE_BYTES],
+    len: usize,
+    samples: u32,
+}
+
+// encode whole groups of three bytes. the caller handles padding the last group.
+fn encode(input: &[u8], output: &mut [u8]) -> usize
+{
+    let mut out = 0;
+    for chunk in input.chunks(3)
+    {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = ((b[0] as u32) << 16) | ((b[1] as u32) << 8) | b[2] as u32;
+        output[out] = BASE64[(n >> 18) as usize & 0x3F];
+        output[out + 1] = BASE64[(n >> 12) as usize & 0x3F];
+        output[out + 2] = if chunk.len() > 1 { BASE64[(n >> 6) as usize & 0x3F] } else { b'=' };
+        output[out + 3] = if chunk.len() > 2 { BASE64[n as usize & 0x3F] } else { b'=' };
+        out += 4;
+    }
+    out
+}
+
+impl WavCapture
+{
+    pub fn start(sample_rate: u32) -> WavCapture
+    {
+        serial_println!("WAV BEGIN {}", sample_rate);
+
+        let mut capture = WavCapture { pending: [0; LINE_BYTES], len: 0, samples: 0 };
+
+        let byte_rate = sample_rate * 2;
+        capture.write(b"RIFF");
+        capture.write(&u32::MAX.to_le_bytes());
+        capture.write(b"WAVE");
+        capture.write(b"fmt ");
+        capture.write(&16u32.to_le_bytes());
+        // pcm, mono.
+        capture.write(&1u16.to_le_bytes());
+        capture.write(&1u16.to_le_bytes());
+        capture.write(&sample_rate.to_le_bytes());
+        capture.write(&byte_rate.to_le_bytes());
+        // block align, bits per sample.
+        capture.write(&2u16.to_le_bytes());
+        capture.write(&16u16.to_le_bytes());
+        capture.write(b"data");
+        capture.write(&u32::MAX.to_le_bytes());
+
+        capture
+    }
+
+    fn write(&mut self, bytes: &[u8])
+    {
+        for &b in bytes
+        {
+            self.pending[self.len] = b;
+            self.len += 1;
+            if self.len == LINE_BYTES
+            {
+                self.flush();
+            }
+        }
+    }
+
+    fn flush(&mut self)
+    {
+        if self.len == 0 {
+            return;
+        }
+
+        let mut line = [0u8; LINE_CHARS];
+        let n = encode(&self.pending[..self.len], &mut line);
+        // it's all ascii out of the table above.
+        serial_println!("WAV {}", core::str::from_utf8(&line[..n]).unwrap());
+        self.len = 0;
+    }
+
+    pub fn push(&mut self, sample: i16)
+    {
+        self.write(&sample.to_le_bytes());
+        self.samples += 1;
+    }
+
+    pub fn samples(&self) -> u32
+    {
+        self.samples
+    }
+
+    // write out whatever's left, padded, and close the stream.
+    pub fn finish(mut self)
+    {
+        self.flush();
+        serial_println!("WAV END {}", self.samples);
+    }
+}
+
+#[test_case]
+fn test_base64_padding()
+{
+    let mut out = [0u8; 8];
+    let n = encode(b"NES", &mut out);
+    assert_eq!(&out[..n], b"TkVT");
+    let n = encode(b"NE", &mut out);
+    assert_eq!(&out[..n], b"TkU=");
+    let n = encode(b"N", &mut out);
+    assert_eq!(&out[..n], b"Tg==");
+}
@@ -0,0 +1,406 @@
+// game genie and pro action replay codes.
+// a game genie sat between the cart and the console and swapped out bytes as the cpu read them from prg rom, so
+// genie codes get applied by GenieMapper wrapping the real mapper. a pro action replay patched ram instead, so those
+// just get stored again every frame.
+//   genie codes are 6 or 8 letters out of APZLGITYEOXUKSVN, like SXIOPO.
+//   par codes are 6 hex digits, the ram address then the value, like 007509. ram ends at $07FF so they always
+//   start with a 0, which is how they're told apart from genie codes.
+//
+// F10 pauses the game and opens the menu in the side panel:
+//   type a code and Enter to add it, Up/Down to pick one, Space to turn it on or off, Delete to drop it,
+//   Escape or F10 to go back to the game.
+// a game's codes are kept on the rom disk as <crc32 of the rom>.cht, next to its .sav, one code per line with a
+// "-" in front if it's turned off.
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use pc_keyboard::KeyCode;
+use runes::cartridge::Cartridge;
+use runes::mapper::Mapper;
+use runes::utils;
+use vga::colors::Color16;
+
+use crate::emulation::machine::Machine;
+use crate::emulation::panel::{self, Panel, TEXT_ROWS};
+use crate::{romdisk, serial_println};
+
+const GENIE_LETTERS: &[u8; 16] = b"APZLGITYEOXUKSVN";
+const RAM_SIZE: u16 = 0x0800;
+const LIST_ROW: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Code
+{
+    // replace what the cpu reads from prg at addr with value, but only if it would have read compare, when there is
+    // one. 8 letter codes have a compare so they only hit the right bank on bank switching carts.
+    Genie { addr: u16, value: u8, compare: Option<u8> },
+    // store value to ram at addr every frame.
+    Par { addr: u16, value: u8 },
+}
+
+fn genie_letter(c: u8) -> Option<u8>
+{
+    GENIE_LETTERS.iter().position(|&l| l == c.to_ascii_uppercase()).map(|n| n as u8)
+}
+
+pub fn decode(text: &str) -> Option<Code>
+{
+    let text = text.trim();
+    if text.len() == 6 && text.starts_with('0') {
+        let n = u32::from_str_radix(text, 16).ok()?;
+        let addr = (n >> 8) as u16;
+        if addr >= RAM_SIZE {
+            return None;
+        }
+        return Some(Code::Par { addr, value: n as u8 });
+    }
+    if text.len() != 6 && text.len() != 8 {
+        return None;
+    }
+    let n: Vec<u8> = text.bytes().map(genie_letter).collect::<Option<_>>()?;
+
+    // the bits are scattered across the letters, see the nesdev wiki's game genie page.
+    let addr = 0x8000
+        | ((n[3] as u16 & 7) << 12)
+        | ((n[5] as u16 & 7) << 8)
+        | ((n[4] as u16 & 8) << 8)
+        | ((n[2] as u16 & 7) << 4)
+        | ((n[1] as u16 & 8) << 4)
+        | (n[4] as u16 & 7)
+        | (n[3] as u16 & 8);
+    let high = ((n[1] & 7) << 4) | ((n[0] & 8) << 4) | (n[0] & 7);
+    if n.len() == 6 {
+        return Some(Code::Genie { addr, value: high | (n[5] & 8), compare: None });
+    }
+    let compare = ((n[7] & 7) << 4) | ((n[6] & 8) << 4) | (n[6] & 7) | (n[5] & 8);
+    Some(Code::Genie { addr, value: high | (n[7] & 8), compare: Some(compare) })
+}
+
+// the genie codes that are on right now, shared between the menu and the mapper doing the reads.
+pub struct GeniePatches
+{
+    patches: RefCell<Vec<(u16, u8, Option<u8>)>>,
+}
+
+impl GeniePatches
+{
+    pub fn new() -> Rc<GeniePatches>
+    {
+        Rc::new(GeniePatches { patches: RefCell::new(Vec::new()) })
+    }
+
+    fn apply(&self, addr: u16, read: u8) -> u8
+    {
+        let patches = self.patches.borrow();
+        for &(a, value, compare) in patches.iter()
+        {
+            if a == addr && compare.is_none_or(|c| c == read) {
+                return value;
+            }
+        }
+        read
+    }
+}
+
+// any mapper, with the genie in front of it.
+pub struct GenieMapper<'a>
+{
+    inner: Box<dyn Mapper + 'a>,
+    patches: Rc<GeniePatches>,
+}
+
+impl<'a> GenieMapper<'a>
+{
+    pub fn new(inner: Box<dyn Mapper + 'a>, patches: Rc<GeniePatches>) -> GenieMapper<'a>
+    {
+        GenieMapper { inner, patches }
+    }
+}
+
+impl<'a> Mapper for GenieMapper<'a>
+{
+    fn read(&self, addr: u16) -> u8
+    {
+        let data = self.inner.read(addr);
+        if addr < 0x8000 {
+            return data;
+        }
+        self.patches.apply(addr, data)
+    }
+
+    fn write(&mut self, addr: u16, data: u8)
+    {
+        self.inner.write(addr, data)
+    }
+
+    fn get_cart(&self) -> &dyn Cartridge
+    {
+        self.inner.get_cart()
+    }
+
+    fn tick(&mut self)
+    {
+        self.inner.tick()
+    }
+
+    fn load(&mut self, reader: &mut dyn utils::Read) -> bool
+    {
+        self.inner.load(reader)
+    }
+
+    fn save(&self, writer: &mut dyn utils::Write) -> bool
+    {
+        self.inner.save(writer)
+    }
+}
+
+struct Entry
+{
+    text: String,
+    code: Code,
+    enabled: bool,
+}
+
+fn key_char(key: KeyCode) -> Option<char>
+{
+    const KEYS: [(KeyCode, char); 36] = [
+        (KeyCode::A, 'A'), (KeyCode::B, 'B'), (KeyCode::C, 'C'), (KeyCode::D, 'D'), (KeyCode::E, 'E'),
+        (KeyCode::F, 'F'), (KeyCode::G, 'G'), (KeyCode::H, 'H'), (KeyCode::I, 'I'), (KeyCode::J, 'J'),
+        (KeyCode::K, 'K'), (KeyCode::L, 'L'), (KeyCode::M, 'M'), (KeyCode::N, 'N'), (KeyCode::O, 'O'),
+        (KeyCode::P, 'P'), (KeyCode::Q, 'Q'), (KeyCode::R, 'R'), (KeyCode::S, 'S'), (KeyCode::T, 'T'),
+        (KeyCode::U, 'U'), (KeyCode::V, 'V'), (KeyCode::W, 'W'), (KeyCode::X, 'X'), (KeyCode::Y, 'Y'),
+        (KeyCode::Z, 'Z'), (KeyCode::Key0, '0'), (KeyCode::Key1, '1'), (KeyCode::Key2, '2'),
+        (KeyCode::Key3, '3'), (KeyCode::Key4, '4'), (KeyCode::Key5, '5'), (KeyCode::Key6, '6'),
+        (KeyCode::Key7, '7'), (KeyCode::Key8, '8'), (KeyCode::Key9, '9'),
+    ];
+    KEYS.iter().find(|&&(k, _)| k == key).map(|&(_, c)| c)
+}
+
+pub fn cheat_file(rom_crc: u32) -> String
+{
+    format!("{:08x}.cht", rom_crc)
+}
+
+pub struct Cheats
+{
+    file: String,
+    entries: Vec<Entry>,
+    genie: Rc<GeniePatches>,
+    selected: usize,
+    typing: String,
+    message: &'static str,
+    panel: Panel,
+}
+
+impl Cheats
+{
+    pub fn new(rom_crc: u32) -> Cheats
+    {
+        let mut cheats = Cheats {
+            file: cheat_file(rom_crc),
+            entries: Vec::new(),
+            genie: GeniePatches::new(),
+            selected: 0,
+            typing: String::new(),
+            message: "",
+            panel: Panel::new(),
+        };
+        if let Some(data) = romdisk::read(&cheats.file) {
+            for line in String::from_utf8_lossy(&data).lines()
+            {
+                let (enabled, text) = match line.trim().strip_prefix('-') {
+                    Some(text) => (false, text),
+                    None => (true, line.trim()),
+                };
+                match decode(text) {
+                    Some(code) => cheats.entries.push(Entry { text: String::from(text), code, enabled }),
+                    None if text.is_empty() => {}
+                    None => serial_println!("{}: skipping bad code {}", cheats.file, text),
+                }
+            }
+            serial_println!("loaded {} cheat codes from {}", cheats.entries.len(), cheats.file);
+        }
+        cheats.update_genie();
+        cheats
+    }
+
+    // for emulate() to put in front of the mapper.
+    pub fn genie(&self) -> Rc<GeniePatches>
+    {
+        self.genie.clone()
+    }
+
+    fn update_genie(&self)
+    {
+        let mut patches = self.genie.patches.borrow_mut();
+        patches.clear();
+        for entry in self.entries.iter().filter(|e| e.enabled)
+        {
+            if let Code::Genie { addr, value, compare } = entry.code {
+                patches.push((addr, value, compare));
+            }
+        }
+    }
+
+    fn save(&self)
+    {
+        let mut out = String::new();
+        for entry in self.entries.iter()
+        {
+            if !entry.enabled {
+                out.push('-');
+            }
+            out.push_str(&entry.text);
+            out.push('\n');
+        }
+        if !romdisk::write(&self.file, out.as_bytes()) {
+            serial_println!("couldn't write {}", self.file);
+        }
+    }
+
+    // called once a frame.
+    pub fn frame(&self, machine: &Machine)
+    {
+        for entry in self.entries.iter().filter(|e| e.enabled)
+        {
+            if let Code::Par { addr, value } = entry.code {
+                machine.poke(addr, value);
+            }
+        }
+    }
+
+    // F10 runs the menu until it's closed again, with the game stopped underneath.
+    pub fn handle_key(&mut self, key: KeyCode) -> bool
+    {
+        if key != KeyCode::F10 {
+            return false;
+        }
+        self.menu();
+        true
+    }
+
+    fn menu(&mut self)
+    {
+        self.panel.claim(panel::CHEATS);
+        self.message = "";
+        self.draw();
+        loop
+        {
+            let key = match crate::keyboard::pop_key() {
+                Some(key) => key,
+                None => {
+                    x86_64::instructions::hlt();
+                    continue;
+                }
+            };
+            match key {
+                KeyCode::Escape | KeyCode::F10 => break,
+                KeyCode::Enter => self.add(),
+                KeyCode::Backspace => {
+                    self.typing.pop();
+                }
+                KeyCode::ArrowUp => self.selected = self.selected.saturating_sub(1),
+                KeyCode::ArrowDown => self.selected = (self.selected + 1).min(self.entries.len().saturating_sub(1)),
+                KeyCode::Spacebar => {
+                    if let Some(entry) = self.entries.get_mut(self.selected) {
+                        entry.enabled = !entry.enabled;
+                        self.changed();
+                    }
+                }
+                KeyCode::Delete => {
+                    if self.selected < self.entries.len() {
+                        self.entries.remove(self.selected);
+                        self.selected = self.selected.min(self.entries.len().saturating_sub(1));
+                        self.changed();
+                    }
+                }
+                key => {
+                    if let Some(c) = key_char(key) {
+                        if self.typing.len() < 8 {
+                            self.typing.push(c);
+                        }
+                    }
+                }
+            }
+            self.draw();
+        }
+        self.panel.release(panel::CHEATS);
+    }
+
+    fn add(&mut self)
+    {
+        match decode(&self.typing) {
+            Some(code) => {
+                if self.entries.iter().any(|e| e.code == code) {
+                    self.message = "ALREADY HAVE THAT ONE";
+                } else {
+                    self.entries.push(Entry { text: self.typing.clone(), code, enabled: true });
+                    self.selected = self.entries.len() - 1;
+                    self.message = "ADDED";
+                    self.changed();
+                }
+                self.typing.clear();
+            }
+            None => self.message = "NOT A GENIE OR PAR CODE",
+        }
+    }
+
+    fn changed(&mut self)
+    {
+        self.update_genie();
+        self.save();
+    }
+
+    fn draw(&mut self)
+    {
+        self.panel.text(0, "CHEATS", Color16::White);
+        let typing = format!("CODE: {}_", self.typing);
+        self.panel.text(1, &typing, Color16::Yellow);
+        self.panel.text(2, self.message, Color16::LightRed);
+
+        for row in 0..TEXT_ROWS - LIST_ROW - 1
+        {
+            let (line, color) = match self.entries.get(row) {
+                Some(entry) => {
+                    let marker = if row == self.selected { '>' } else { ' ' };
+                    let what = match entry.code {
+                        Code::Genie { addr, value, compare: Some(c) } => format!("${:04X}={:02X} IF {:02X}", addr, value, c),
+                        Code::Genie { addr, value, compare: None } => format!("${:04X}={:02X}", addr, value),
+                        Code::Par { addr, value } => format!("RAM ${:04X}={:02X}", addr, value),
+                    };
+                    let state = if entry.enabled { "ON " } else { "OFF" };
+                    let color = if !entry.enabled {
+                        Color16::DarkGrey
+                    } else if row == self.selected {
+                        Color16::Yellow
+                    } else {
+                        Color16::LightGrey
+                    };
+                    (format!("{}{} {:8} {}", marker, state, entry.text, what), color)
+                }
+                None => (String::new(), Color16::LightGrey),
+            };
+            self.panel.text(LIST_ROW + row, &line, color);
+        }
+        self.panel.text(TEXT_ROWS - 1, "ENTER ADD  SPACE ON/OFF  DEL DROP  ESC BACK", Color16::LightCyan);
+    }
+}
+
+#[test_case]
+fn test_cheat_codes()
+{
+    // smb's infinite lives.
+    assert_eq!(decode("SXIOPO"), Some(Code::Genie { addr: 0x91D9, value: 0xAD, compare: None }));
+    assert_eq!(decode("007509"), Some(Code::Par { addr: 0x0075, value: 0x09 }));
+    assert_eq!(decode("081234"), None);
+    assert_eq!(decode("SXIOP"), None);
+    match decode("AAAAAAAA") {
+        Some(Code::Genie { compare: Some(_), .. }) => {}
+        other => panic!("{:?}", other),
+    }
+}
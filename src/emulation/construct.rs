@@ -1,13 +1,26 @@
-use pc_keyboard::{Keyboard, layouts, ScancodeSet1};
+extern crate alloc;
+
+use alloc::rc::Rc;
+use pc_keyboard::{Keyboard, KeyCode, layouts, ScancodeSet1};
 use runes::{ppu::Screen, apu::Speaker, controller::InputPoller};
 use spin::Mutex;
+use core::cell::Cell;
 use vga::{writers::{Graphics640x480x16, GraphicsWriter}, colors::Color16};
 
 use crate::vga_buffer::Color;
+use crate::emulation::crc32::Crc32;
+use crate::emulation::{RGB_COLORS, FRAME_COUNT, LAST_FRAME_HASH, AUDIO_BUFFER, AUDIO_SAMPLES, AUDIO_EXTRA_SAMPLES, AUDIO_ALL_SAMPLES};
+use crate::emulation::capture::{self, WavCapture};
+use crate::emulation::mappers::ExpansionAudio;
+use crate::emulation::KEYBOARD_MAPPING;
+use crate::keyboard;
+use crate::sound::AudioOutput;
 
 pub struct TerminalScreen
 {
     mode: Graphics640x480x16,
+    // every pixel of the frame in progress goes through this, so movies can check they're drawing the same thing.
+    hash: Crc32,
 }
 
 //// EXAMPLE IMPLEMENTATION
@@ -43,7 +56,8 @@ impl TerminalScreen
     {
         let m = Graphics640x480x16::new();
         m.set_mode();
-        TerminalScreen { mode: m }
+        m.clear_screen(Color16::Black);
+        TerminalScreen { mode: m, hash: Crc32::new() }
     }
 }
 
@@ -51,6 +65,7 @@ impl Screen for TerminalScreen
 {
     fn put(&mut self, x: u8, y: u8, color: u8)
     {
+        self.hash.update(&[x, y, color]);
         self.mode.set_pixel(x.into(), y.into(), color16_from_u8(color));
     }
     fn render(&mut self)
@@ -58,37 +73,322 @@ impl Screen for TerminalScreen
     }
     fn frame(&mut self)
     {
-        self.mode.clear_screen(Color16::Black);
+        LAST_FRAME_HASH.store(self.hash.finish(), core::sync::atomic::Ordering::Relaxed);
+        self.hash = Crc32::new();
+        FRAME_COUNT.fetch_add(1, core::sync::atomic::Ordering::Relaxed);
+        // no clearing, every pixel of the game gets drawn again next frame anyway, and the right of the screen
+        // belongs to the ppu viewer.
+    }
+}
+
+// a screen for running without one. still hashes and counts frames, so the run loop and the test harness
+// can't tell the difference, but never touches the vga card.
+pub struct HeadlessScreen
+{
+    hash: Crc32,
+}
+
+impl HeadlessScreen
+{
+    pub fn new() -> HeadlessScreen
+    {
+        HeadlessScreen { hash: Crc32::new() }
+    }
+}
+
+impl Screen for HeadlessScreen
+{
+    fn put(&mut self, x: u8, y: u8, color: u8)
+    {
+        self.hash.update(&[x, y, color]);
+    }
+    fn render(&mut self)
+    {
+    }
+    fn frame(&mut self)
+    {
+        LAST_FRAME_HASH.store(self.hash.finish(), core::sync::atomic::Ordering::Relaxed);
+        self.hash = Crc32::new();
+        FRAME_COUNT.fetch_add(1, core::sync::atomic::Ordering::Relaxed);
+    }
+}
+
+// the 16 colors the 640x480 mode has, as rgb, in Color16 order.
+const EGA_COLORS: [(Color16, u32); 16] = [
+    (Color16::Black, 0x000000),
+    (Color16::Blue, 0x0000aa),
+    (Color16::Green, 0x00aa00),
+    (Color16::Cyan, 0x00aaaa),
+    (Color16::Red, 0xaa0000),
+    (Color16::Magenta, 0xaa00aa),
+    (Color16::Brown, 0xaa5500),
+    (Color16::LightGrey, 0xaaaaaa),
+    (Color16::DarkGrey, 0x555555),
+    (Color16::LightBlue, 0x5555ff),
+    (Color16::LightGreen, 0x55ff55),
+    (Color16::LightCyan, 0x55ffff),
+    (Color16::LightRed, 0xff5555),
+    (Color16::Pink, 0xff55ff),
+    (Color16::Yellow, 0xffff55),
+    (Color16::White, 0xffffff),
+];
+
+const fn channel_distance(a: u32, b: u32, shift: u32) -> u32
+{
+    let a = (a >> shift) & 0xff;
+    let b = (b >> shift) & 0xff;
+    let d = if a > b { a - b } else { b - a };
+    d * d
+}
+
+// closest of the 16 to every nes color, worked out at compile time.
+const fn nearest_colors() -> [Color16; 64]
+{
+    let mut table = [Color16::Black; 64];
+    let mut i = 0;
+    while i < 64
+    {
+        let rgb = RGB_COLORS[i];
+        let mut best = 0;
+        let mut best_distance = u32::MAX;
+        let mut j = 0;
+        while j < 16
+        {
+            let ega = EGA_COLORS[j].1;
+            let distance = channel_distance(rgb, ega, 16) + channel_distance(rgb, ega, 8) + channel_distance(rgb, ega, 0);
+            if distance < best_distance {
+                best = j;
+                best_distance = distance;
+            }
+            j += 1;
+        }
+        table[i] = EGA_COLORS[best].0;
+        i += 1;
     }
+    table
 }
 
-fn color16_from_u8(color: u8) -> Color16
+const COLOR16_FROM_NES: [Color16; 64] = nearest_colors();
+
+pub(crate) fn color16_from_u8(color: u8) -> Color16
 {
-    Color16::Blue
+    COLOR16_FROM_NES[(color & 0x3f) as usize]
 }
 
-pub struct TerminalAudio
+// resamples the apu's output for whatever sound hardware we found, and hands it over through AUDIO_BUFFER.
+// this is also what keeps the emulator running at the right speed: the sound card plays at a fixed rate, so if
+// the buffer is filling up we're running fast and we wait for it to drain.
+pub struct TerminalAudio<'a>
 {
+    output: &'a mut dyn AudioOutput,
+    resampler: Resampler,
+    // the backend's real rate. the resampler gets nudged around this.
+    rate: u32,
+    apu_rate: u32,
+    // apu samples since the last rate adjustment.
+    since_adjust: u16,
+    // tees every sample out to serial, when we're recording.
+    capture: Option<WavCapture>,
+    // the cart's own sound channel, added to every apu sample.
+    expansion: Option<Rc<dyn ExpansionAudio>>,
+}
+
+// past this, the emulator is too far ahead of the sound card and has to wait.
+// leaves AUDIO_SAMPLES of room so the resampler can never lap the buffer.
+const AUDIO_BLOCK_FILL: usize = AUDIO_EXTRA_SAMPLES as usize;
+
+// where the rate control tries to hold the buffer. half full gives the same slack both ways.
+const AUDIO_TARGET_FILL: usize = AUDIO_ALL_SAMPLES as usize / 2;
+
+// how far the output rate is allowed to stray from the real one, in thousandths. at 0.5% nobody hears the pitch
+// change, and it's more than enough to soak up the drift between the pit, the sound card, and the emulator.
+const AUDIO_MAX_SKEW: i64 = 5;
+
+impl<'a> TerminalAudio<'a>
+{
+    // apu_rate is how many samples a second the apu really makes, which depends on the region's cpu clock.
+    pub fn new(output: &'a mut dyn AudioOutput, apu_rate: u32) -> TerminalAudio<'a>
+    {
+        let rate = output.sample_rate();
+        let resampler = Resampler::new(apu_rate, rate);
+        TerminalAudio { output, resampler, rate, apu_rate, since_adjust: 0, capture: None, expansion: None }
+    }
+
+    pub fn set_expansion(&mut self, audio: Rc<dyn ExpansionAudio>)
+    {
+        self.expansion = Some(audio);
+    }
+
+    // record the raw apu stream, before any resampling, so captures match no matter what sound card played them.
+    // capture.rs decides when.
+    fn start_capture(&mut self)
+    {
+        self.capture = Some(WavCapture::start(self.apu_rate));
+    }
+
+    fn stop_capture(&mut self)
+    {
+        if let Some(capture) = self.capture.take() {
+            capture.finish();
+        }
+    }
+
+    fn fill(&self) -> usize
+    {
+        x86_64::instructions::interrupts::without_interrupts(|| AUDIO_BUFFER.lock().len())
+    }
 
+    // dynamic rate control. an emptier buffer than the target means make a few more samples than we should, a
+    // fuller one means make a few less, scaled by how far off we are. this keeps the buffer hovering around the
+    // target instead of running dry (crackles) or bumping into the block limit (latency creeping up).
+    fn adjust_rate(&mut self, fill: usize)
+    {
+        let target = AUDIO_TARGET_FILL as i64;
+        let off = (target - fill as i64).max(-target).min(target);
+        let skew = self.rate as i64 * AUDIO_MAX_SKEW * off / (target * 1000);
+        self.resampler.set_output_rate((self.rate as i64 + skew) as u32);
+    }
 }
 
-impl Speaker for TerminalAudio
+impl<'a> Speaker for TerminalAudio<'a>
 {
     fn queue(&mut self, sample: i16)
     {
+        let sample = match &self.expansion {
+            Some(audio) => sample.saturating_add(audio.sample()),
+            None => sample,
+        };
+        if capture::wanted() != self.capture.is_some() {
+            if capture::wanted() {
+                self.start_capture();
+            } else {
+                self.stop_capture();
+            }
+        }
+        if let Some(capture) = &mut self.capture {
+            capture.push(sample);
+        }
+
+        let resampler = &mut self.resampler;
+        // interrupt-driven backends drain this buffer, don't let them fire while we're holding the lock.
+        x86_64::instructions::interrupts::without_interrupts(|| {
+            let mut buffer = AUDIO_BUFFER.lock();
+            resampler.push(sample, |s| buffer.enque(s));
+        });
+        self.output.service();
+
+        self.since_adjust += 1;
+        if self.since_adjust >= AUDIO_SAMPLES
+        {
+            self.since_adjust = 0;
+            let fill = self.fill();
+            self.adjust_rate(fill);
+        }
+
+        // we're ahead of the sound card. sleep until it catches up, the next interrupt will have taken some
+        // samples out (or at least gives us a reason to look again). a card that doesn't interrupt gets watched
+        // instead, sleeping till the timer would pace us in 55ms lurches.
+        let wakes_cpu = self.output.wakes_cpu();
+        while self.fill() >= AUDIO_BLOCK_FILL
+        {
+            self.output.service();
+            if self.fill() >= AUDIO_BLOCK_FILL {
+                if wakes_cpu {
+                    x86_64::instructions::hlt();
+                } else {
+                    core::hint::spin_loop();
+                }
+            }
+        }
+    }
+}
+
+// the emulator's stopped, finish off any capture so it gets its END line.
+impl<'a> Drop for TerminalAudio<'a>
+{
+    fn drop(&mut self)
+    {
+        self.stop_capture();
+    }
+}
+
+// converts the apu's sample rate to whatever the sound hardware wants by repeating or dropping samples.
+// crude, but the pulse channels are square waves anyway.
+pub struct Resampler
+{
+    from: u32,
+    to: u32,
+    acc: u32,
+}
+
+impl Resampler
+{
+    pub fn new(from: u32, to: u32) -> Resampler
+    {
+        Resampler { from, to, acc: 0 }
+    }
+
+    pub fn set_output_rate(&mut self, to: u32)
+    {
+        self.to = to;
+    }
 
+    // every input sample is worth to/from output samples. carry the remainder over to the next one.
+    pub fn push(&mut self, sample: i16, mut out: impl FnMut(i16))
+    {
+        self.acc += self.to;
+        while self.acc >= self.from
+        {
+            self.acc -= self.from;
+            out(sample);
+        }
     }
 }
 
 // input actionstate wrapper around the Keyboard struct registered with the PIC ps2 interrupts.
+// the state is latched once a frame by the run loop instead of read live, so a frame always sees one consistent
+// controller state no matter how many times the game strobes it. that's also what lets movies replay exactly.
 pub struct TerminalKeyboard
 {
+    latched: Cell<u8>,
+    // which keys are which buttons.
+    mapping: &'static [(KeyCode, u8)],
 }
 
-impl InputPoller for TerminalKeyboard {
-    fn poll(&self) -> u8 {
-        let state: u8 = 0;
+impl TerminalKeyboard
+{
+    pub fn new() -> TerminalKeyboard
+    {
+        TerminalKeyboard::with_mapping(&KEYBOARD_MAPPING)
+    }
+
+    pub fn with_mapping(mapping: &'static [(KeyCode, u8)]) -> TerminalKeyboard
+    {
+        TerminalKeyboard { latched: Cell::new(0), mapping }
+    }
+
+    // what the held keys add up to on a controller right now.
+    pub fn read_keys(&self) -> u8
+    {
+        let mut state: u8 = 0;
+        for &(key, button) in self.mapping.iter()
+        {
+            if keyboard::is_pressed(key) {
+                state |= button;
+            }
+        }
 
         state
     }
+
+    pub fn latch(&self, state: u8)
+    {
+        self.latched.set(state);
+    }
+}
+
+impl InputPoller for TerminalKeyboard {
+    fn poll(&self) -> u8 {
+        self.latched.get()
+    }
 }
@@ -0,0 +1,61 @@
+// plain crc32 (the zip/png one), how roms are usually identified.
+// we hash the prg and chr data without the ines header, since headers are the part of a dump that's most often wrong.
+
+const POLY: u32 = 0xEDB88320;
+
+const fn make_table() -> [u32; 256]
+{
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256
+    {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8
+        {
+            c = if c & 1 != 0 { POLY ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+}
+
+static TABLE: [u32; 256] = make_table();
+
+pub struct Crc32(u32);
+
+impl Crc32
+{
+    pub fn new() -> Crc32
+    {
+        Crc32(0xFFFFFFFF)
+    }
+
+    pub fn update(&mut self, data: &[u8])
+    {
+        for &b in data
+        {
+            self.0 = TABLE[((self.0 ^ b as u32) & 0xFF) as usize] ^ (self.0 >> 8);
+        }
+    }
+
+    pub fn finish(&self) -> u32
+    {
+        !self.0
+    }
+}
+
+pub fn crc32(data: &[u8]) -> u32
+{
+    let mut c = Crc32::new();
+    c.update(data);
+    c.finish()
+}
+
+#[test_case]
+fn test_crc32_check_value()
+{
+    assert_eq!(crc32(b"123456789"), 0xCBF43926);
+}
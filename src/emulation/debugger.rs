@@ -0,0 +1,329 @@
+// a monitor for the emulated 6502 on SERIAL1, for debugging roms on the same bare metal we ship on.
+// boot with the "debug" option to stop before the first instruction, or "debug=run" to start running. either way,
+// send ctrl-c any time to break in.
+// every number is hex, with or without a $.
+//   c                       continue
+//   s [n]                   step n instructions, 1 if not given
+//   r                       registers, as a trace line
+//   r <reg> <value>         set a, x, y, p, sp or pc
+//   b [addr]                break when pc gets to addr, or list breakpoints
+//   bc <addr>               clear a breakpoint
+//   w [addr] [r|w|rw]       break before an instruction reads or writes addr, or list watchpoints
+//   wc <addr>               clear a watchpoint
+//   m <addr> [len]          dump the cpu bus
+//   m <addr> = <bytes..>    store to the cpu bus
+//   v <addr> [len]          dump the ppu bus, vram and the palette at $3F00
+//   v <addr> = <bytes..>    write the ppu bus
+//   o [index] [len]         dump oam
+//   o <index> = <bytes..>   write oam
+//   d [addr] [n]            disassemble n instructions, from pc if no addr
+// watchpoints go by the address the instruction is about to use, so the stack and interrupt vectors don't count.
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::emulation::disasm::{self, Access};
+use crate::emulation::machine::{Machine, Register};
+use crate::emulation::trace::{effective_address, trace_line};
+use crate::{cmdline, serial, serial_print, serial_println};
+
+// ctrl-c.
+const BREAK_CHAR: u8 = 0x03;
+// checking the uart costs a port read, so only look for a break every this many instructions.
+const POLL_INTERVAL: u32 = 4096;
+const DEFAULT_DUMP: u16 = 0x40;
+const DEFAULT_DISASM: u16 = 10;
+
+pub struct Debugger
+{
+    enabled: bool,
+    breakpoints: Vec<u16>,
+    watchpoints: Vec<(u16, Access)>,
+    // instructions left to run before stopping again.
+    steps_left: Option<u16>,
+    polls: u32,
+}
+
+fn parse_number(word: &str) -> Option<u16>
+{
+    let digits = word.trim_start_matches('$').trim_start_matches("0x");
+    u16::from_str_radix(digits, 16).ok()
+}
+
+fn parse_register(word: &str) -> Option<Register>
+{
+    match word {
+        "a" => Some(Register::A),
+        "x" => Some(Register::X),
+        "y" => Some(Register::Y),
+        "p" => Some(Register::P),
+        "sp" => Some(Register::SP),
+        "pc" => Some(Register::PC),
+        _ => None,
+    }
+}
+
+fn access_matches(watch: Access, access: Access) -> bool
+{
+    match (watch, access) {
+        (_, Access::None) => false,
+        (Access::ReadWrite, _) | (_, Access::ReadWrite) => true,
+        (watch, access) => watch == access,
+    }
+}
+
+// blocks until a whole line comes in, echoing it back since terminals usually don't.
+fn read_line() -> String
+{
+    let mut line = String::new();
+    loop
+    {
+        let byte = match serial::try_read() {
+            Some(b) => b,
+            None => {
+                core::hint::spin_loop();
+                continue;
+            }
+        };
+        match byte {
+            b'\r' | b'\n' => {
+                serial_print!("\n");
+                return line;
+            }
+            // backspace and delete
+            0x08 | 0x7F => {
+                if line.pop().is_some() {
+                    serial_print!("\x08 \x08");
+                }
+            }
+            0x20..=0x7E => {
+                line.push(byte as char);
+                serial_print!("{}", byte as char);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn dump(start: u16, len: u16, read: impl Fn(u16) -> u8)
+{
+    let mut addr = start;
+    let end = start as u32 + len as u32;
+    while (addr as u32) < end
+    {
+        serial_print!("{:04X}:", addr);
+        for _ in 0..16
+        {
+            if addr as u32 >= end {
+                break;
+            }
+            serial_print!(" {:02X}", read(addr));
+            addr = match addr.checked_add(1) {
+                Some(a) => a,
+                None => {
+                    serial_print!("\n");
+                    return;
+                }
+            };
+        }
+        serial_print!("\n");
+    }
+}
+
+fn parse_bytes<'w>(words: impl Iterator<Item = &'w str>) -> Option<Vec<u8>>
+{
+    words.map(|w| parse_number(w).filter(|&n| n <= 0xFF).map(|n| n as u8)).collect()
+}
+
+impl Debugger
+{
+    pub fn new() -> Debugger
+    {
+        let enabled = cmdline::flag("debug");
+        let stop_at_start = enabled && cmdline::get("debug") != Some("run");
+        Debugger {
+            enabled,
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            steps_left: if stop_at_start { Some(0) } else { None },
+            polls: 0,
+        }
+    }
+
+    pub fn enabled(&self) -> bool
+    {
+        self.enabled
+    }
+
+    // called before every instruction. always lets the machine keep going, it just might take a while.
+    pub fn step(&mut self, machine: &Machine, cycles: u64) -> bool
+    {
+        let mut reason = None;
+
+        match self.steps_left {
+            Some(0) => reason = Some("step"),
+            Some(n) => self.steps_left = Some(n - 1),
+            None => {}
+        }
+
+        let pc = machine.register(Register::PC);
+        if self.breakpoints.contains(&pc) {
+            reason = Some("breakpoint");
+        }
+
+        if !self.watchpoints.is_empty() {
+            let access = disasm::op(machine.peek(pc)).access();
+            if let Some(addr) = effective_address(machine, pc) {
+                if self.watchpoints.iter().any(|&(a, watch)| a == addr && access_matches(watch, access)) {
+                    reason = Some("watchpoint");
+                }
+            }
+        }
+
+        self.polls += 1;
+        if self.polls >= POLL_INTERVAL {
+            self.polls = 0;
+            if serial::try_read() == Some(BREAK_CHAR) {
+                reason = Some("interrupted");
+            }
+        }
+
+        if let Some(reason) = reason {
+            serial_println!("break: {}", reason);
+            self.prompt(machine, cycles);
+        }
+        true
+    }
+
+    // take commands until one of them resumes the machine.
+    fn prompt(&mut self, machine: &Machine, cycles: u64)
+    {
+        self.steps_left = None;
+        serial_println!("{}", trace_line(machine, cycles));
+        loop
+        {
+            serial_print!("> ");
+            let line = read_line();
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let resume = match words.split_first() {
+                Some((&command, args)) => self.command(machine, cycles, command, args),
+                None => false,
+            };
+            if resume {
+                return;
+            }
+        }
+    }
+
+    // returns true if the machine should start running again.
+    fn command(&mut self, machine: &Machine, cycles: u64, command: &str, args: &[&str]) -> bool
+    {
+        let arg = |i: usize| args.get(i).and_then(|w| parse_number(w));
+        match command {
+            "c" => return true,
+            "s" => {
+                self.steps_left = Some(arg(0).unwrap_or(1).max(1) - 1);
+                return true;
+            }
+            "r" => match (args.get(0).and_then(|w| parse_register(w)), arg(1)) {
+                (Some(register), Some(value)) => machine.set_register(register, value),
+                (None, _) if args.is_empty() => serial_println!("{}", trace_line(machine, cycles)),
+                _ => serial_println!("r <a|x|y|p|sp|pc> <value>"),
+            },
+            "b" => match arg(0) {
+                Some(addr) => {
+                    if !self.breakpoints.contains(&addr) {
+                        self.breakpoints.push(addr);
+                    }
+                }
+                None => {
+                    for addr in self.breakpoints.iter()
+                    {
+                        serial_println!("  {:04X}", addr);
+                    }
+                }
+            },
+            "bc" => {
+                if let Some(addr) = arg(0) {
+                    self.breakpoints.retain(|&a| a != addr);
+                }
+            }
+            "w" => match arg(0) {
+                Some(addr) => {
+                    let access = match args.get(1).copied() {
+                        Some("r") => Access::Read,
+                        Some("w") => Access::Write,
+                        _ => Access::ReadWrite,
+                    };
+                    self.watchpoints.retain(|&(a, _)| a != addr);
+                    self.watchpoints.push((addr, access));
+                }
+                None => {
+                    for (addr, access) in self.watchpoints.iter()
+                    {
+                        serial_println!("  {:04X} {:?}", addr, access);
+                    }
+                }
+            },
+            "wc" => {
+                if let Some(addr) = arg(0) {
+                    self.watchpoints.retain(|&(a, _)| a != addr);
+                }
+            }
+            "m" | "v" | "o" => {
+                let start = arg(0).unwrap_or(0);
+                if args.get(1) == Some(&"=") {
+                    match parse_bytes(args[2..].iter().copied()) {
+                        Some(bytes) => {
+                            for (i, &b) in bytes.iter().enumerate()
+                            {
+                                let addr = start.wrapping_add(i as u16);
+                                match command {
+                                    "m" => machine.poke(addr, b),
+                                    "v" => machine.ppu_poke(addr, b),
+                                    _ => machine.oam_poke(addr as u8, b),
+                                }
+                            }
+                        }
+                        None => serial_println!("bytes are 00 to FF"),
+                    }
+                } else {
+                    let len = arg(1).unwrap_or(DEFAULT_DUMP);
+                    match command {
+                        "m" => dump(start, len, |a| machine.peek(a)),
+                        "v" => dump(start, len, |a| machine.ppu_peek(a)),
+                        _ => {
+                            let oam = machine.oam();
+                            dump(start, len.min(0x100 - (start & 0xFF)), |a| oam[(a & 0xFF) as usize])
+                        }
+                    }
+                }
+            }
+            "d" => {
+                let mut pc = arg(0).unwrap_or_else(|| machine.register(Register::PC));
+                for _ in 0..arg(1).unwrap_or(DEFAULT_DISASM)
+                {
+                    let opcode = machine.peek(pc);
+                    let operands = [machine.peek(pc.wrapping_add(1)), machine.peek(pc.wrapping_add(2))];
+                    serial_println!("  {:04X}  {}", pc, disasm::parse(pc, opcode, &operands));
+                    pc = pc.wrapping_add(disasm::length(opcode) as u16);
+                }
+            }
+            "h" | "?" => serial_println!("c, s [n], r [reg value], b/bc, w/wc, m/v/o <addr> [len | = bytes], d [addr] [n]"),
+            _ => serial_println!("unknown command {}, h for help", command),
+        }
+        false
+    }
+}
+
+#[test_case]
+fn test_debugger_numbers()
+{
+    assert_eq!(parse_number("$C000"), Some(0xC000));
+    assert_eq!(parse_number("0x2002"), Some(0x2002));
+    assert_eq!(parse_number("ff"), Some(0xFF));
+    assert_eq!(parse_number("zz"), None);
+    assert!(access_matches(Access::Write, Access::ReadWrite));
+    assert!(!access_matches(Access::Read, Access::Write));
+}
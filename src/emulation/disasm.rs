@@ -0,0 +1,411 @@
+// a 6502 disassembler, every opcode including the unofficial ones, named the way nestest.log names them.
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode
+{
+    Implied,
+    Accumulator,
+    Immediate,
+    ZeroPage,
+    ZeroPageX,
+    ZeroPageY,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    Indirect,
+    IndirectX,
+    IndirectY,
+    Relative,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Op
+{
+    pub name: &'static str,
+    pub mode: Mode,
+    // the unofficial ones get a * in front in traces.
+    pub official: bool,
+}
+
+// what an instruction does with its operand's address, for watchpoints.
+// stack pushes and pulls and the vector fetches aren't counted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access
+{
+    None,
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl Op
+{
+    pub fn access(&self) -> Access
+    {
+        match self.mode {
+            Mode::Implied | Mode::Accumulator | Mode::Immediate | Mode::Relative | Mode::Indirect => return Access::None,
+            _ => {}
+        }
+        match self.name {
+            "JMP" | "JSR" => Access::None,
+            "STA" | "STX" | "STY" | "SAX" | "AHX" | "SHX" | "SHY" | "TAS" => Access::Write,
+            "ASL" | "LSR" | "ROL" | "ROR" | "INC" | "DEC" | "SLO" | "RLA" | "SRE" | "RRA" | "DCP" | "ISB" => {
+                Access::ReadWrite
+            }
+            _ => Access::Read,
+        }
+    }
+}
+
+impl Mode
+{
+    // how many bytes the instruction takes, opcode included.
+    pub fn length(self) -> usize
+    {
+        match self {
+            Mode::Implied | Mode::Accumulator => 1,
+            Mode::Absolute | Mode::AbsoluteX | Mode::AbsoluteY | Mode::Indirect => 3,
+            _ => 2,
+        }
+    }
+}
+
+pub const OPCODES: [Op; 256] = [
+    // $0x
+    Op { name: "BRK", mode: Mode::Implied, official: true },
+    Op { name: "ORA", mode: Mode::IndirectX, official: true },
+    Op { name: "KIL", mode: Mode::Implied, official: false },
+    Op { name: "SLO", mode: Mode::IndirectX, official: false },
+    Op { name: "NOP", mode: Mode::ZeroPage, official: false },
+    Op { name: "ORA", mode: Mode::ZeroPage, official: true },
+    Op { name: "ASL", mode: Mode::ZeroPage, official: true },
+    Op { name: "SLO", mode: Mode::ZeroPage, official: false },
+    Op { name: "PHP", mode: Mode::Implied, official: true },
+    Op { name: "ORA", mode: Mode::Immediate, official: true },
+    Op { name: "ASL", mode: Mode::Accumulator, official: true },
+    Op { name: "ANC", mode: Mode::Immediate, official: false },
+    Op { name: "NOP", mode: Mode::Absolute, official: false },
+    Op { name: "ORA", mode: Mode::Absolute, official: true },
+    Op { name: "ASL", mode: Mode::Absolute, official: true },
+    Op { name: "SLO", mode: Mode::Absolute, official: false },
+    // $1x
+    Op { name: "BPL", mode: Mode::Relative, official: true },
+    Op { name: "ORA", mode: Mode::IndirectY, official: true },
+    Op { name: "KIL", mode: Mode::Implied, official: false },
+    Op { name: "SLO", mode: Mode::IndirectY, official: false },
+    Op { name: "NOP", mode: Mode::ZeroPageX, official: false },
+    Op { name: "ORA", mode: Mode::ZeroPageX, official: true },
+    Op { name: "ASL", mode: Mode::ZeroPageX, official: true },
+    Op { name: "SLO", mode: Mode::ZeroPageX, official: false },
+    Op { name: "CLC", mode: Mode::Implied, official: true },
+    Op { name: "ORA", mode: Mode::AbsoluteY, official: true },
+    Op { name: "NOP", mode: Mode::Implied, official: false },
+    Op { name: "SLO", mode: Mode::AbsoluteY, official: false },
+    Op { name: "NOP", mode: Mode::AbsoluteX, official: false },
+    Op { name: "ORA", mode: Mode::AbsoluteX, official: true },
+    Op { name: "ASL", mode: Mode::AbsoluteX, official: true },
+    Op { name: "SLO", mode: Mode::AbsoluteX, official: false },
+    // $2x
+    Op { name: "JSR", mode: Mode::Absolute, official: true },
+    Op { name: "AND", mode: Mode::IndirectX, official: true },
+    Op { name: "KIL", mode: Mode::Implied, official: false },
+    Op { name: "RLA", mode: Mode::IndirectX, official: false },
+    Op { name: "BIT", mode: Mode::ZeroPage, official: true },
+    Op { name: "AND", mode: Mode::ZeroPage, official: true },
+    Op { name: "ROL", mode: Mode::ZeroPage, official: true },
+    Op { name: "RLA", mode: Mode::ZeroPage, official: false },
+    Op { name: "PLP", mode: Mode::Implied, official: true },
+    Op { name: "AND", mode: Mode::Immediate, official: true },
+    Op { name: "ROL", mode: Mode::Accumulator, official: true },
+    Op { name: "ANC", mode: Mode::Immediate, official: false },
+    Op { name: "BIT", mode: Mode::Absolute, official: true },
+    Op { name: "AND", mode: Mode::Absolute, official: true },
+    Op { name: "ROL", mode: Mode::Absolute, official: true },
+    Op { name: "RLA", mode: Mode::Absolute, official: false },
+    // $3x
+    Op { name: "BMI", mode: Mode::Relative, official: true },
+    Op { name: "AND", mode: Mode::IndirectY, official: true },
+    Op { name: "KIL", mode: Mode::Implied, official: false },
+    Op { name: "RLA", mode: Mode::IndirectY, official: false },
+    Op { name: "NOP", mode: Mode::ZeroPageX, official: false },
+    Op { name: "AND", mode: Mode::ZeroPageX, official: true },
+    Op { name: "ROL", mode: Mode::ZeroPageX, official: true },
+    Op { name: "RLA", mode: Mode::ZeroPageX, official: false },
+    Op { name: "SEC", mode: Mode::Implied, official: true },
+    Op { name: "AND", mode: Mode::AbsoluteY, official: true },
+    Op { name: "NOP", mode: Mode::Implied, official: false },
+    Op { name: "RLA", mode: Mode::AbsoluteY, official: false },
+    Op { name: "NOP", mode: Mode::AbsoluteX, official: false },
+    Op { name: "AND", mode: Mode::AbsoluteX, official: true },
+    Op { name: "ROL", mode: Mode::AbsoluteX, official: true },
+    Op { name: "RLA", mode: Mode::AbsoluteX, official: false },
+    // $4x
+    Op { name: "RTI", mode: Mode::Implied, official: true },
+    Op { name: "EOR", mode: Mode::IndirectX, official: true },
+    Op { name: "KIL", mode: Mode::Implied, official: false },
+    Op { name: "SRE", mode: Mode::IndirectX, official: false },
+    Op { name: "NOP", mode: Mode::ZeroPage, official: false },
+    Op { name: "EOR", mode: Mode::ZeroPage, official: true },
+    Op { name: "LSR", mode: Mode::ZeroPage, official: true },
+    Op { name: "SRE", mode: Mode::ZeroPage, official: false },
+    Op { name: "PHA", mode: Mode::Implied, official: true },
+    Op { name: "EOR", mode: Mode::Immediate, official: true },
+    Op { name: "LSR", mode: Mode::Accumulator, official: true },
+    Op { name: "ALR", mode: Mode::Immediate, official: false },
+    Op { name: "JMP", mode: Mode::Absolute, official: true },
+    Op { name: "EOR", mode: Mode::Absolute, official: true },
+    Op { name: "LSR", mode: Mode::Absolute, official: true },
+    Op { name: "SRE", mode: Mode::Absolute, official: false },
+    // $5x
+    Op { name: "BVC", mode: Mode::Relative, official: true },
+    Op { name: "EOR", mode: Mode::IndirectY, official: true },
+    Op { name: "KIL", mode: Mode::Implied, official: false },
+    Op { name: "SRE", mode: Mode::IndirectY, official: false },
+    Op { name: "NOP", mode: Mode::ZeroPageX, official: false },
+    Op { name: "EOR", mode: Mode::ZeroPageX, official: true },
+    Op { name: "LSR", mode: Mode::ZeroPageX, official: true },
+    Op { name: "SRE", mode: Mode::ZeroPageX, official: false },
+    Op { name: "CLI", mode: Mode::Implied, official: true },
+    Op { name: "EOR", mode: Mode::AbsoluteY, official: true },
+    Op { name: "NOP", mode: Mode::Implied, official: false },
+    Op { name: "SRE", mode: Mode::AbsoluteY, official: false },
+    Op { name: "NOP", mode: Mode::AbsoluteX, official: false },
+    Op { name: "EOR", mode: Mode::AbsoluteX, official: true },
+    Op { name: "LSR", mode: Mode::AbsoluteX, official: true },
+    Op { name: "SRE", mode: Mode::AbsoluteX, official: false },
+    // $6x
+    Op { name: "RTS", mode: Mode::Implied, official: true },
+    Op { name: "ADC", mode: Mode::IndirectX, official: true },
+    Op { name: "KIL", mode: Mode::Implied, official: false },
+    Op { name: "RRA", mode: Mode::IndirectX, official: false },
+    Op { name: "NOP", mode: Mode::ZeroPage, official: false },
+    Op { name: "ADC", mode: Mode::ZeroPage, official: true },
+    Op { name: "ROR", mode: Mode::ZeroPage, official: true },
+    Op { name: "RRA", mode: Mode::ZeroPage, official: false },
+    Op { name: "PLA", mode: Mode::Implied, official: true },
+    Op { name: "ADC", mode: Mode::Immediate, official: true },
+    Op { name: "ROR", mode: Mode::Accumulator, official: true },
+    Op { name: "ARR", mode: Mode::Immediate, official: false },
+    Op { name: "JMP", mode: Mode::Indirect, official: true },
+    Op { name: "ADC", mode: Mode::Absolute, official: true },
+    Op { name: "ROR", mode: Mode::Absolute, official: true },
+    Op { name: "RRA", mode: Mode::Absolute, official: false },
+    // $7x
+    Op { name: "BVS", mode: Mode::Relative, official: true },
+    Op { name: "ADC", mode: Mode::IndirectY, official: true },
+    Op { name: "KIL", mode: Mode::Implied, official: false },
+    Op { name: "RRA", mode: Mode::IndirectY, official: false },
+    Op { name: "NOP", mode: Mode::ZeroPageX, official: false },
+    Op { name: "ADC", mode: Mode::ZeroPageX, official: true },
+    Op { name: "ROR", mode: Mode::ZeroPageX, official: true },
+    Op { name: "RRA", mode: Mode::ZeroPageX, official: false },
+    Op { name: "SEI", mode: Mode::Implied, official: true },
+    Op { name: "ADC", mode: Mode::AbsoluteY, official: true },
+    Op { name: "NOP", mode: Mode::Implied, official: false },
+    Op { name: "RRA", mode: Mode::AbsoluteY, official: false },
+    Op { name: "NOP", mode: Mode::AbsoluteX, official: false },
+    Op { name: "ADC", mode: Mode::AbsoluteX, official: true },
+    Op { name: "ROR", mode: Mode::AbsoluteX, official: true },
+    Op { name: "RRA", mode: Mode::AbsoluteX, official: false },
+    // $8x
+    Op { name: "NOP", mode: Mode::Immediate, official: false },
+    Op { name: "STA", mode: Mode::IndirectX, official: true },
+    Op { name: "NOP", mode: Mode::Immediate, official: false },
+    Op { name: "SAX", mode: Mode::IndirectX, official: false },
+    Op { name: "STY", mode: Mode::ZeroPage, official: true },
+    Op { name: "STA", mode: Mode::ZeroPage, official: true },
+    Op { name: "STX", mode: Mode::ZeroPage, official: true },
+    Op { name: "SAX", mode: Mode::ZeroPage, official: false },
+    Op { name: "DEY", mode: Mode::Implied, official: true },
+    Op { name: "NOP", mode: Mode::Immediate, official: false },
+    Op { name: "TXA", mode: Mode::Implied, official: true },
+    Op { name: "XAA", mode: Mode::Immediate, official: false },
+    Op { name: "STY", mode: Mode::Absolute, official: true },
+    Op { name: "STA", mode: Mode::Absolute, official: true },
+    Op { name: "STX", mode: Mode::Absolute, official: true },
+    Op { name: "SAX", mode: Mode::Absolute, official: false },
+    // $9x
+    Op { name: "BCC", mode: Mode::Relative, official: true },
+    Op { name: "STA", mode: Mode::IndirectY, official: true },
+    Op { name: "KIL", mode: Mode::Implied, official: false },
+    Op { name: "AHX", mode: Mode::IndirectY, official: false },
+    Op { name: "STY", mode: Mode::ZeroPageX, official: true },
+    Op { name: "STA", mode: Mode::ZeroPageX, official: true },
+    Op { name: "STX", mode: Mode::ZeroPageY, official: true },
+    Op { name: "SAX", mode: Mode::ZeroPageY, official: false },
+    Op { name: "TYA", mode: Mode::Implied, official: true },
+    Op { name: "STA", mode: Mode::AbsoluteY, official: true },
+    Op { name: "TXS", mode: Mode::Implied, official: true },
+    Op { name: "TAS", mode: Mode::AbsoluteY, official: false },
+    Op { name: "SHY", mode: Mode::AbsoluteX, official: false },
+    Op { name: "STA", mode: Mode::AbsoluteX, official: true },
+    Op { name: "SHX", mode: Mode::AbsoluteY, official: false },
+    Op { name: "AHX", mode: Mode::AbsoluteY, official: false },
+    // $Ax
+    Op { name: "LDY", mode: Mode::Immediate, official: true },
+    Op { name: "LDA", mode: Mode::IndirectX, official: true },
+    Op { name: "LDX", mode: Mode::Immediate, official: true },
+    Op { name: "LAX", mode: Mode::IndirectX, official: false },
+    Op { name: "LDY", mode: Mode::ZeroPage, official: true },
+    Op { name: "LDA", mode: Mode::ZeroPage, official: true },
+    Op { name: "LDX", mode: Mode::ZeroPage, official: true },
+    Op { name: "LAX", mode: Mode::ZeroPage, official: false },
+    Op { name: "TAY", mode: Mode::Implied, official: true },
+    Op { name: "LDA", mode: Mode::Immediate, official: true },
+    Op { name: "TAX", mode: Mode::Implied, official: true },
+    Op { name: "LAX", mode: Mode::Immediate, official: false },
+    Op { name: "LDY", mode: Mode::Absolute, official: true },
+    Op { name: "LDA", mode: Mode::Absolute, official: true },
+    Op { name: "LDX", mode: Mode::Absolute, official: true },
+    Op { name: "LAX", mode: Mode::Absolute, official: false },
+    // $Bx
+    Op { name: "BCS", mode: Mode::Relative, official: true },
+    Op { name: "LDA", mode: Mode::IndirectY, official: true },
+    Op { name: "KIL", mode: Mode::Implied, official: false },
+    Op { name: "LAX", mode: Mode::IndirectY, official: false },
+    Op { name: "LDY", mode: Mode::ZeroPageX, official: true },
+    Op { name: "LDA", mode: Mode::ZeroPageX, official: true },
+    Op { name: "LDX", mode: Mode::ZeroPageY, official: true },
+    Op { name: "LAX", mode: Mode::ZeroPageY, official: false },
+    Op { name: "CLV", mode: Mode::Implied, official: true },
+    Op { name: "LDA", mode: Mode::AbsoluteY, official: true },
+    Op { name: "TSX", mode: Mode::Implied, official: true },
+    Op { name: "LAS", mode: Mode::AbsoluteY, official: false },
+    Op { name: "LDY", mode: Mode::AbsoluteX, official: true },
+    Op { name: "LDA", mode: Mode::AbsoluteX, official: true },
+    Op { name: "LDX", mode: Mode::AbsoluteY, official: true },
+    Op { name: "LAX", mode: Mode::AbsoluteY, official: false },
+    // $Cx
+    Op { name: "CPY", mode: Mode::Immediate, official: true },
+    Op { name: "CMP", mode: Mode::IndirectX, official: true },
+    Op { name: "NOP", mode: Mode::Immediate, official: false },
+    Op { name: "DCP", mode: Mode::IndirectX, official: false },
+    Op { name: "CPY", mode: Mode::ZeroPage, official: true },
+    Op { name: "CMP", mode: Mode::ZeroPage, official: true },
+    Op { name: "DEC", mode: Mode::ZeroPage, official: true },
+    Op { name: "DCP", mode: Mode::ZeroPage, official: false },
+    Op { name: "INY", mode: Mode::Implied, official: true },
+    Op { name: "CMP", mode: Mode::Immediate, official: true },
+    Op { name: "DEX", mode: Mode::Implied, official: true },
+    Op { name: "AXS", mode: Mode::Immediate, official: false },
+    Op { name: "CPY", mode: Mode::Absolute, official: true },
+    Op { name: "CMP", mode: Mode::Absolute, official: true },
+    Op { name: "DEC", mode: Mode::Absolute, official: true },
+    Op { name: "DCP", mode: Mode::Absolute, official: false },
+    // $Dx
+    Op { name: "BNE", mode: Mode::Relative, official: true },
+    Op { name: "CMP", mode: Mode::IndirectY, official: true },
+    Op { name: "KIL", mode: Mode::Implied, official: false },
+    Op { name: "DCP", mode: Mode::IndirectY, official: false },
+    Op { name: "NOP", mode: Mode::ZeroPageX, official: false },
+    Op { name: "CMP", mode: Mode::ZeroPageX, official: true },
+    Op { name: "DEC", mode: Mode::ZeroPageX, official: true },
+    Op { name: "DCP", mode: Mode::ZeroPageX, official: false },
+    Op { name: "CLD", mode: Mode::Implied, official: true },
+    Op { name: "CMP", mode: Mode::AbsoluteY, official: true },
+    Op { name: "NOP", mode: Mode::Implied, official: false },
+    Op { name: "DCP", mode: Mode::AbsoluteY, official: false },
+    Op { name: "NOP", mode: Mode::AbsoluteX, official: false },
+    Op { name: "CMP", mode: Mode::AbsoluteX, official: true },
+    Op { name: "DEC", mode: Mode::AbsoluteX, official: true },
+    Op { name: "DCP", mode: Mode::AbsoluteX, official: false },
+    // $Ex
+    Op { name: "CPX", mode: Mode::Immediate, official: true },
+    Op { name: "SBC", mode: Mode::IndirectX, official: true },
+    Op { name: "NOP", mode: Mode::Immediate, official: false },
+    Op { name: "ISB", mode: Mode::IndirectX, official: false },
+    Op { name: "CPX", mode: Mode::ZeroPage, official: true },
+    Op { name: "SBC", mode: Mode::ZeroPage, official: true },
+    Op { name: "INC", mode: Mode::ZeroPage, official: true },
+    Op { name: "ISB", mode: Mode::ZeroPage, official: false },
+    Op { name: "INX", mode: Mode::Implied, official: true },
+    Op { name: "SBC", mode: Mode::Immediate, official: true },
+    Op { name: "NOP", mode: Mode::Implied, official: true },
+    Op { name: "SBC", mode: Mode::Immediate, official: false },
+    Op { name: "CPX", mode: Mode::Absolute, official: true },
+    Op { name: "SBC", mode: Mode::Absolute, official: true },
+    Op { name: "INC", mode: Mode::Absolute, official: true },
+    Op { name: "ISB", mode: Mode::Absolute, official: false },
+    // $Fx
+    Op { name: "BEQ", mode: Mode::Relative, official: true },
+    Op { name: "SBC", mode: Mode::IndirectY, official: true },
+    Op { name: "KIL", mode: Mode::Implied, official: false },
+    Op { name: "ISB", mode: Mode::IndirectY, official: false },
+    Op { name: "NOP", mode: Mode::ZeroPageX, official: false },
+    Op { name: "SBC", mode: Mode::ZeroPageX, official: true },
+    Op { name: "INC", mode: Mode::ZeroPageX, official: true },
+    Op { name: "ISB", mode: Mode::ZeroPageX, official: false },
+    Op { name: "SED", mode: Mode::Implied, official: true },
+    Op { name: "SBC", mode: Mode::AbsoluteY, official: true },
+    Op { name: "NOP", mode: Mode::Implied, official: false },
+    Op { name: "ISB", mode: Mode::AbsoluteY, official: false },
+    Op { name: "NOP", mode: Mode::AbsoluteX, official: false },
+    Op { name: "SBC", mode: Mode::AbsoluteX, official: true },
+    Op { name: "INC", mode: Mode::AbsoluteX, official: true },
+    Op { name: "ISB", mode: Mode::AbsoluteX, official: false },
+];
+
+pub fn op(opcode: u8) -> Op
+{
+    OPCODES[opcode as usize]
+}
+
+pub fn length(opcode: u8) -> usize
+{
+    op(opcode).mode.length()
+}
+
+// where a branch at pc goes.
+pub fn branch_target(pc: u16, offset: u8) -> u16
+{
+    pc.wrapping_add(2).wrapping_add(offset as i8 as u16)
+}
+
+// just the operand, "#$10", "($80),Y" and so on. operands are the bytes after the opcode, missing ones read as 0.
+pub fn operand(pc: u16, opcode: u8, operands: &[u8]) -> String
+{
+    let lo = operands.get(0).copied().unwrap_or(0);
+    let hi = operands.get(1).copied().unwrap_or(0);
+    let word = lo as u16 | (hi as u16) << 8;
+    match op(opcode).mode {
+        Mode::Implied => String::new(),
+        Mode::Accumulator => String::from("A"),
+        Mode::Immediate => format!("#${:02X}", lo),
+        Mode::ZeroPage => format!("${:02X}", lo),
+        Mode::ZeroPageX => format!("${:02X},X", lo),
+        Mode::ZeroPageY => format!("${:02X},Y", lo),
+        Mode::Absolute => format!("${:04X}", word),
+        Mode::AbsoluteX => format!("${:04X},X", word),
+        Mode::AbsoluteY => format!("${:04X},Y", word),
+        Mode::Indirect => format!("(${:04X})", word),
+        Mode::IndirectX => format!("(${:02X},X)", lo),
+        Mode::IndirectY => format!("(${:02X}),Y", lo),
+        Mode::Relative => format!("${:04X}", branch_target(pc, lo)),
+    }
+}
+
+// one instruction, "LDA $0300,X".
+pub fn parse(pc: u16, opcode: u8, operands: &[u8]) -> String
+{
+    let name = op(opcode).name;
+    let operand = operand(pc, opcode, operands);
+    if operand.is_empty() {
+        String::from(name)
+    } else {
+        format!("{} {}", name, operand)
+    }
+}
+
+#[test_case]
+fn test_disasm_modes()
+{
+    assert_eq!(parse(0xC000, 0x4C, &[0xF5, 0xC5]).as_str(), "JMP $C5F5");
+    assert_eq!(parse(0xC000, 0xB1, &[0x89]).as_str(), "LDA ($89),Y");
+    assert_eq!(parse(0xC72A, 0xB0, &[0x01]).as_str(), "BCS $C72D");
+    assert_eq!(parse(0xC000, 0x4A, &[]).as_str(), "LSR A");
+    assert!(!op(0xA7).official && op(0xEA).official && !op(0xEB).official);
+}
@@ -0,0 +1,232 @@
+// famicom disk system images, and the disk that's in the drive.
+// an .fds file is every side of every disk one after the other, 65500 bytes each, with or without a 16 byte "FDS\x1a"
+// header in front. a side is a list of blocks, each starting with its type:
+//   1  disk info, 56 bytes        3  file header, 16 bytes, with the file's size at 13
+//   2  file count, 2 bytes        4  the file, a byte then however long the header said
+// the files leave out what's between the blocks on a real disk, so side_from_image puts it back: a long gap of
+// zeros before the first block, a short one after each, a $80 mark where a block starts and two crc bytes where it
+// ends. the ram adapter (mappers/fds.rs) reads that back a byte at a time the way the drive would.
+// the crcs are just placeholders, the adapter never reports a crc error.
+//
+// there's no cart, so the bios that knows how to load games from disk has to come from somewhere: disksys.rom, 8k,
+// on the rom disk. F11 ejects the disk and puts in the next side a second later, wrapping round to the first.
+extern crate alloc;
+
+use alloc::rc::Rc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::{Cell, RefCell};
+use pc_keyboard::KeyCode;
+use runes::cartridge::MirrorType;
+
+use crate::emulation::crc32::Crc32;
+use crate::emulation::{Region, Rom};
+use crate::{println, romdisk, serial_println};
+
+pub const BIOS_FILE: &str = "disksys.rom";
+const BIOS_SIZE: usize = 0x2000;
+// the famicom's ram adapter has 32k of ram where a cart's prg would be, and 8k of chr ram.
+const RAM_SIZE: usize = 0x8000;
+const CHR_SIZE: usize = 0x2000;
+
+const HEADER: &[u8; 4] = b"FDS\x1a";
+const SIDE_SIZE: usize = 65500;
+// gaps in bytes. the drive is slow enough to start that the first one is long.
+const LEAD_IN: usize = 28300 / 8;
+const BLOCK_GAP: usize = 976 / 8;
+const BLOCK_MARK: u8 = 0x80;
+
+// frames the disk stays out when swapping sides. the bios wants to see it gone before it looks for the next one.
+const SWAP_FRAMES: u8 = 60;
+
+// the mapper number nes 2.0 set aside for the disk system, so the rest of the emulator can tell.
+pub const MAPPER_ID: u8 = 20;
+
+pub fn is_fds(data: &[u8]) -> bool
+{
+    data.starts_with(HEADER) || data.starts_with(b"\x01*NINTENDO-HVC*")
+}
+
+// one side, gaps and all.
+fn side_from_image(image: &[u8]) -> Vec<u8>
+{
+    let mut side = vec![0; LEAD_IN];
+    let mut i = 0;
+    while i < image.len()
+    {
+        let len = match image[i] {
+            1 => 56,
+            2 => 2,
+            3 => 16,
+            // the size is in the file header that came just before.
+            4 if i >= 3 => 1 + image[i - 3] as usize + ((image[i - 2] as usize) << 8),
+            // anything else is the unused end of the side.
+            _ => break,
+        };
+        let end = (i + len).min(image.len());
+        side.push(BLOCK_MARK);
+        side.extend_from_slice(&image[i..end]);
+        side.extend_from_slice(&[0x4D, 0x62]);
+        side.resize(side.len() + BLOCK_GAP, 0);
+        i = end;
+    }
+    // blank after the last file, with room for the gaps too, so games saving new files have somewhere to put them.
+    side.resize(side.len().max(LEAD_IN + SIDE_SIZE + SIDE_SIZE / 8), 0);
+    side
+}
+
+pub struct Disk
+{
+    sides: RefCell<Vec<Vec<u8>>>,
+    // which side is in the drive, None while it's out.
+    side: Cell<Option<usize>>,
+    // the one going in next, and how many frames until it does.
+    next: Cell<usize>,
+    swap_in: Cell<u8>,
+}
+
+impl Disk
+{
+    pub fn new(sides: Vec<Vec<u8>>) -> Rc<Disk>
+    {
+        let sides = RefCell::new(sides);
+        Rc::new(Disk { sides, side: Cell::new(Some(0)), next: Cell::new(0), swap_in: Cell::new(0) })
+    }
+
+    pub fn inserted(&self) -> bool
+    {
+        self.side.get().is_some()
+    }
+
+    // how long the side in the drive is, 0 with nothing in it.
+    pub fn len(&self) -> usize
+    {
+        self.side.get().map_or(0, |s| self.sides.borrow()[s].len())
+    }
+
+    pub fn read(&self, pos: usize) -> u8
+    {
+        match self.side.get() {
+            Some(s) => self.sides.borrow()[s].get(pos).copied().unwrap_or(0),
+            None => 0,
+        }
+    }
+
+    // writes only last until the machine is turned off, nothing goes back to the rom disk.
+    pub fn write(&self, pos: usize, data: u8)
+    {
+        if let Some(s) = self.side.get() {
+            if let Some(byte) = self.sides.borrow_mut()[s].get_mut(pos) {
+                *byte = data;
+            }
+        }
+    }
+
+    // returns whether the key was one of ours.
+    pub fn handle_key(&self, key: KeyCode) -> bool
+    {
+        if key != KeyCode::F11 {
+            return false;
+        }
+        let count = self.sides.borrow().len();
+        let next = match self.side.get() {
+            Some(s) => (s + 1) % count,
+            // pressed again while it's out, skip along another side.
+            None => (self.next.get() + 1) % count,
+        };
+        self.side.set(None);
+        self.next.set(next);
+        self.swap_in.set(SWAP_FRAMES);
+        serial_println!("fds: ejected, disk {} side {} next", next / 2 + 1, if next % 2 == 0 { 'A' } else { 'B' });
+        true
+    }
+
+    pub fn frame(&self)
+    {
+        match self.swap_in.get() {
+            0 => {}
+            1 => {
+                self.swap_in.set(0);
+                self.side.set(Some(self.next.get()));
+                serial_println!("fds: inserted");
+            }
+            n => self.swap_in.set(n - 1),
+        }
+    }
+}
+
+// an .fds image as something emulate can run, with the bios as its prg.
+pub fn load(data: &[u8]) -> Option<Rom>
+{
+    let bios = match romdisk::read(BIOS_FILE) {
+        Some(bios) if bios.len() == BIOS_SIZE => bios,
+        Some(_) => {
+            println!("{} should be 8KiB, cannot run disks.", BIOS_FILE);
+            return None;
+        }
+        None => {
+            println!("no {} on the rom disk, cannot run disks.", BIOS_FILE);
+            return None;
+        }
+    };
+
+    let image = if data.starts_with(HEADER) { &data[16..] } else { data };
+    let sides: Vec<Vec<u8>> = image.chunks(SIDE_SIZE).filter(|s| s.len() == SIDE_SIZE).map(side_from_image).collect();
+    if sides.is_empty() {
+        println!("disk image is truncated, cannot run.");
+        return None;
+    }
+    println!("fds: {} sides", sides.len());
+
+    let mut crc = Crc32::new();
+    crc.update(image);
+    let crc = crc.finish();
+    println!("rom crc32: {:08x}", crc);
+
+    Some(Rom {
+        prg_rom: bios,
+        chr_rom: vec![0; CHR_SIZE],
+        chr_ram: true,
+        sram: vec![0; RAM_SIZE],
+        mirror: MirrorType::Horizontal,
+        mapper_id: MAPPER_ID,
+        submapper: 0,
+        battery: false,
+        region: Region::Ntsc,
+        prg_ram: true,
+        crc,
+        disk: Some(Disk::new(sides)),
+    })
+}
+
+#[test_case]
+fn test_fds_side_gaps()
+{
+    let mut image = vec![0u8; SIDE_SIZE];
+    image[0] = 1;
+    // a file count, then a 3 byte file.
+    image[56] = 2;
+    image[58] = 3;
+    image[58 + 13] = 3;
+    image[74] = 4;
+    image[75..78].copy_from_slice(&[0xAA, 0xBB, 0xCC]);
+    let side = side_from_image(&image);
+
+    assert!(side[..LEAD_IN].iter().all(|&b| b == 0));
+    assert_eq!(&side[LEAD_IN..LEAD_IN + 2], &[BLOCK_MARK, 1]);
+    let count = LEAD_IN + 1 + 56 + 2 + BLOCK_GAP;
+    assert_eq!(&side[count..count + 2], &[BLOCK_MARK, 2]);
+    let file = count + 1 + 2 + 2 + BLOCK_GAP + 1 + 16 + 2 + BLOCK_GAP;
+    assert_eq!(&side[file..file + 5], &[BLOCK_MARK, 4, 0xAA, 0xBB, 0xCC]);
+    assert!(side.len() >= SIDE_SIZE);
+
+    let disk = Disk::new(vec![side.clone(), side]);
+    assert_eq!(disk.read(LEAD_IN), BLOCK_MARK);
+    assert!(disk.handle_key(KeyCode::F11));
+    assert!(!disk.inserted());
+    for _ in 0..SWAP_FRAMES
+    {
+        disk.frame();
+    }
+    assert!(disk.inserted());
+}
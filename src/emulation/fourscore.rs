@@ -0,0 +1,162 @@
+// the four score, the hub that takes up both controller ports and has four pads plugged into it. after a strobe each
+// port shifts out 24 bits instead of 8, in the order they're read:
+//   $4016  pad 1, then pad 3, then 0,0,0,1,0,0,0,0
+//   $4017  pad 2, then pad 4, then 0,0,1,0,0,0,0,0
+// the last 8 are how games tell it's there, and after them it's 1s, same as a lone pad past its 8 buttons.
+// the pads are anything that can be polled, so players 3 and 4 can be a gamepad once there's a driver for one. for
+// now everybody's on the keyboard, each in their own corner of it.
+use core::cell::Cell;
+use pc_keyboard::KeyCode;
+use runes::controller::{stdctl, Controller, InputPoller};
+
+use crate::emulation::construct::TerminalKeyboard;
+
+// the signature, as it comes out first bit first.
+const SIGNATURE_4016: u8 = 0x08;
+const SIGNATURE_4017: u8 = 0x04;
+
+// player 1 is the usual keys minus IJKL, which go to player 2.
+pub(crate) const PLAYER_1_KEYS: [(KeyCode, u8); 8] = [
+    (KeyCode::ArrowUp, stdctl::UP),
+    (KeyCode::ArrowDown, stdctl::DOWN),
+    (KeyCode::ArrowLeft, stdctl::LEFT),
+    (KeyCode::ArrowRight, stdctl::RIGHT),
+    (KeyCode::Z, stdctl::A),
+    (KeyCode::X, stdctl::B),
+    (KeyCode::Enter, stdctl::START),
+    (KeyCode::S, stdctl::SELECT),
+];
+
+// players 2 to 4.
+const PLAYER_KEYS: [[(KeyCode, u8); 8]; 3] = [
+    [
+        (KeyCode::I, stdctl::UP),
+        (KeyCode::K, stdctl::DOWN),
+        (KeyCode::J, stdctl::LEFT),
+        (KeyCode::L, stdctl::RIGHT),
+        (KeyCode::O, stdctl::A),
+        (KeyCode::U, stdctl::B),
+        (KeyCode::M, stdctl::START),
+        (KeyCode::N, stdctl::SELECT),
+    ],
+    [
+        (KeyCode::T, stdctl::UP),
+        (KeyCode::G, stdctl::DOWN),
+        (KeyCode::F, stdctl::LEFT),
+        (KeyCode::H, stdctl::RIGHT),
+        (KeyCode::Y, stdctl::A),
+        (KeyCode::R, stdctl::B),
+        (KeyCode::B, stdctl::START),
+        (KeyCode::V, stdctl::SELECT),
+    ],
+    [
+        (KeyCode::Numpad8, stdctl::UP),
+        (KeyCode::Numpad5, stdctl::DOWN),
+        (KeyCode::Numpad4, stdctl::LEFT),
+        (KeyCode::Numpad6, stdctl::RIGHT),
+        (KeyCode::Numpad9, stdctl::A),
+        (KeyCode::Numpad7, stdctl::B),
+        (KeyCode::Numpad3, stdctl::START),
+        (KeyCode::Numpad1, stdctl::SELECT),
+    ],
+];
+
+// keyboard pads for players 2 to 4. latch them once a frame like player 1's.
+pub fn keyboard_pads() -> [TerminalKeyboard; 3]
+{
+    [
+        TerminalKeyboard::with_mapping(&PLAYER_KEYS[0]),
+        TerminalKeyboard::with_mapping(&PLAYER_KEYS[1]),
+        TerminalKeyboard::with_mapping(&PLAYER_KEYS[2]),
+    ]
+}
+
+// one side of the hub.
+pub struct FourScorePort<'a>
+{
+    pads: [&'a dyn InputPoller; 2],
+    signature: u8,
+    shift: Cell<u32>,
+    strobe: Cell<bool>,
+}
+
+impl<'a> FourScorePort<'a>
+{
+    fn reload(&self)
+    {
+        let bits = self.pads[0].poll() as u32 | (self.pads[1].poll() as u32) << 8 | (self.signature as u32) << 16;
+        self.shift.set(bits);
+    }
+}
+
+impl<'a> Controller for FourScorePort<'a>
+{
+    fn read(&self) -> u8
+    {
+        // while the strobe's held it keeps reloading, so it's always the first pad's A.
+        if self.strobe.get() {
+            self.reload();
+        }
+        let bits = self.shift.get();
+        self.shift.set(bits >> 1 | 0x80_0000);
+        (bits & 1) as u8
+    }
+
+    fn write(&self, data: u8)
+    {
+        self.strobe.set(data & 1 == 1);
+        if self.strobe.get() {
+            self.reload();
+        }
+    }
+}
+
+pub struct FourScore<'a>
+{
+    ports: [FourScorePort<'a>; 2],
+}
+
+impl<'a> FourScore<'a>
+{
+    // pads in player order.
+    pub fn new(pads: [&'a dyn InputPoller; 4]) -> FourScore<'a>
+    {
+        let port = |first, second, signature| FourScorePort {
+            pads: [first, second],
+            signature,
+            shift: Cell::new(0),
+            strobe: Cell::new(false),
+        };
+        FourScore { ports: [port(pads[0], pads[2], SIGNATURE_4016), port(pads[1], pads[3], SIGNATURE_4017)] }
+    }
+
+    // 0 for $4016, 1 for $4017.
+    pub fn port(&self, which: usize) -> &FourScorePort<'a>
+    {
+        &self.ports[which]
+    }
+}
+
+#[test_case]
+fn test_four_score()
+{
+    let pads = [TerminalKeyboard::new(), TerminalKeyboard::new(), TerminalKeyboard::new(), TerminalKeyboard::new()];
+    pads[0].latch(stdctl::A);
+    pads[2].latch(stdctl::START | stdctl::RIGHT);
+    pads[3].latch(stdctl::B);
+    let hub = FourScore::new([&pads[0], &pads[1], &pads[2], &pads[3]]);
+
+    let read_all = |port: &FourScorePort| {
+        port.write(1);
+        port.write(0);
+        (0..32).fold(0u32, |bits, i| bits | (port.read() as u32) << i)
+    };
+    let bits = read_all(hub.port(0));
+    assert_eq!(bits & 0xFF, stdctl::A as u32);
+    assert_eq!(bits >> 8 & 0xFF, (stdctl::START | stdctl::RIGHT) as u32);
+    assert_eq!(bits >> 16 & 0xFF, SIGNATURE_4016 as u32);
+    assert_eq!(bits >> 24, 0xFF);
+    let bits = read_all(hub.port(1));
+    assert_eq!(bits & 0xFFFF, (stdctl::B as u32) << 8);
+    assert_eq!(bits >> 16 & 0xFF, SIGNATURE_4017 as u32);
+}
@@ -0,0 +1,256 @@
+// running test roms with nobody watching, for `cargo test`.
+// no vga, no sound card, no keyboard: the rom runs as fast as the cpu allows until it tells us how it went, or
+// until it's had max_frames and we give up on it.
+//
+// most test roms (blargg's cpu, ppu and apu suites, and everything written since in the same style) report through
+// cart ram:
+//   $6001-$6003  DE B0 61 once the rest is valid
+//   $6000        $80 while running, $81 if it wants the reset button pressed, otherwise the result. 0 is a pass.
+//   $6004-       a zero terminated message, usually the name of whatever failed
+// nestest doesn't, but jumping straight to $C000 runs it without a screen and it leaves its error codes in $02/$03.
+// for anything else, the crc32 of the last frame drawn gets compared against a known good one.
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::sync::atomic::Ordering;
+
+use crate::emulation::construct::HeadlessScreen;
+use crate::emulation::machine::Machine;
+use crate::emulation::trace::{first_difference, trace_line};
+use crate::emulation::{emulate, Plugged, Rom, LAST_FRAME_HASH};
+use crate::serial_println;
+use crate::sound::Silence;
+
+const STATUS: u16 = 0x6000;
+const SIGNATURE: u16 = 0x6001;
+const MESSAGE: u16 = 0x6004;
+const STATUS_RUNNING: u8 = 0x80;
+const STATUS_NEEDS_RESET: u8 = 0x81;
+
+// the roms want the button held for at least 100ms, so about six frames.
+const RESET_DELAY_FRAMES: u64 = 6;
+const MAX_MESSAGE: u16 = 0x400;
+
+// where nestest's automated mode starts.
+const NESTEST_ENTRY: u16 = 0xC000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol
+{
+    Blargg,
+    Nestest,
+    FrameHash(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict
+{
+    Passed,
+    // whatever result code the rom gave.
+    Failed(u8),
+    // never finished, or drew the wrong thing when the time was up.
+    TimedOut,
+    // couldn't even get it running.
+    BadRom,
+}
+
+pub struct Report
+{
+    pub verdict: Verdict,
+    pub frames: u64,
+    // crc32 of the last frame drawn, for filling in a FrameHash expectation.
+    pub frame_hash: u32,
+    pub message: String,
+}
+
+impl Report
+{
+    pub fn passed(&self) -> bool
+    {
+        self.verdict == Verdict::Passed
+    }
+
+    // one line a script can grep for, then whatever the rom had to say.
+    pub fn print(&self, name: &str)
+    {
+        let result = match self.verdict {
+            Verdict::Passed => "PASS",
+            _ => "FAIL",
+        };
+        serial_println!(
+            "ROMTEST {} {} {:?} frames={} hash={:08x}",
+            result, name, self.verdict, self.frames, self.frame_hash
+        );
+        for line in self.message.lines().filter(|l| !l.trim().is_empty())
+        {
+            serial_println!("  {}", line);
+        }
+    }
+}
+
+fn signature_valid(machine: &Machine) -> bool
+{
+    machine.peek(SIGNATURE) == 0xDE && machine.peek(SIGNATURE + 1) == 0xB0 && machine.peek(SIGNATURE + 2) == 0x61
+}
+
+fn read_message(machine: &Machine) -> String
+{
+    let mut message = String::new();
+    for addr in MESSAGE..MESSAGE + MAX_MESSAGE
+    {
+        match machine.peek(addr) {
+            0 => break,
+            c => message.push(c as char),
+        }
+    }
+    message
+}
+
+// point the reset vector at nestest's automated entry.
+fn patch_reset_vector(rom: &mut Rom, entry: u16)
+{
+    let len = rom.prg_rom.len();
+    if len < 4 {
+        return;
+    }
+    // $FFFC is four bytes from the end of the last bank.
+    rom.prg_rom[len - 4] = entry as u8;
+    rom.prg_rom[len - 3] = (entry >> 8) as u8;
+}
+
+pub fn run_test_rom(data: &[u8], protocol: Protocol, max_frames: u64) -> Report
+{
+    let mut report = Report { verdict: Verdict::BadRom, frames: 0, frame_hash: 0, message: String::new() };
+
+    let mut rom = match Rom::parse(data) {
+        Some(rom) => rom,
+        None => return report,
+    };
+    if protocol == Protocol::Nestest {
+        patch_reset_vector(&mut rom, NESTEST_ENTRY);
+    }
+
+    let mut screen = HeadlessScreen::new();
+    let mut output = Silence;
+    let mut reset_at: Option<u64> = None;
+    report.verdict = Verdict::TimedOut;
+
+    emulate(rom, &mut screen, &mut output, &mut |machine, _| {
+        report.frames += 1;
+        report.frame_hash = LAST_FRAME_HASH.load(Ordering::Relaxed);
+
+        if let Protocol::Blargg = protocol {
+            if reset_at == Some(report.frames) {
+                reset_at = None;
+                machine.cpu().reset();
+            }
+            if signature_valid(machine) {
+                match machine.peek(STATUS) {
+                    STATUS_RUNNING => {}
+                    STATUS_NEEDS_RESET => {
+                        if reset_at.is_none() {
+                            reset_at = Some(report.frames + RESET_DELAY_FRAMES);
+                        }
+                    }
+                    code => {
+                        report.verdict = if code == 0 { Verdict::Passed } else { Verdict::Failed(code) };
+                        report.message = read_message(machine);
+                        return false;
+                    }
+                }
+            }
+        }
+
+        if report.frames < max_frames {
+            return true;
+        }
+
+        // out of time. for these two that's how they're supposed to end.
+        match protocol {
+            Protocol::Blargg => {
+                if signature_valid(machine) {
+                    report.message = read_message(machine);
+                }
+            }
+            Protocol::Nestest => {
+                let (official, unofficial) = (machine.peek(0x02), machine.peek(0x03));
+                report.verdict = match (official, unofficial) {
+                    (0, 0) => Verdict::Passed,
+                    (0, code) | (code, _) => Verdict::Failed(code),
+                };
+            }
+            Protocol::FrameHash(expected) => {
+                if report.frame_hash == expected {
+                    report.verdict = Verdict::Passed;
+                }
+            }
+        }
+        false
+    }, None, None, Plugged::Nothing);
+
+    report
+}
+
+// where a trace first went wrong, lines counting from 1.
+pub struct Divergence
+{
+    pub line: usize,
+    pub field: &'static str,
+    pub expected: String,
+    pub got: String,
+}
+
+// run nestest in its automated mode and check every instruction against a known good trace (nestest.log),
+// stopping at the first line that doesn't match. returns how many lines matched.
+pub fn run_nestest_trace(data: &[u8], golden: &str) -> Result<usize, Divergence>
+{
+    let mut rom = match Rom::parse(data) {
+        Some(rom) => rom,
+        None => {
+            return Err(Divergence { line: 0, field: "rom", expected: String::from("an ines file"), got: String::new() });
+        }
+    };
+    patch_reset_vector(&mut rom, NESTEST_ENTRY);
+
+    let mut expected_lines = golden.lines().filter(|l| !l.trim().is_empty());
+    let mut matched = 0;
+    let mut divergence = None;
+
+    let mut screen = HeadlessScreen::new();
+    let mut output = Silence;
+    let mut compare = |machine: &Machine, cycles: u64| {
+        let expected = match expected_lines.next() {
+            Some(line) => line.trim_end(),
+            None => return false,
+        };
+        let got = trace_line(machine, cycles);
+        if let Some(field) = first_difference(&got, expected) {
+            divergence = Some(Divergence { line: matched + 1, field, expected: String::from(expected), got });
+            return false;
+        }
+        matched += 1;
+        true
+    };
+    emulate(rom, &mut screen, &mut output, &mut |_, _| true, Some(&mut compare), None, Plugged::Nothing);
+
+    match divergence {
+        Some(d) => Err(d),
+        None => Ok(matched),
+    }
+}
+
+// the trace of the first count instructions, and the crc32 of the last frame drawn by then.
+pub fn run_trace(data: &[u8], count: usize) -> Option<(Vec<String>, u32)>
+{
+    let rom = Rom::parse(data)?;
+    let mut lines = Vec::new();
+    let mut screen = HeadlessScreen::new();
+    let mut output = Silence;
+    let mut record = |machine: &Machine, cycles: u64| {
+        lines.push(trace_line(machine, cycles));
+        lines.len() < count
+    };
+    emulate(rom, &mut screen, &mut output, &mut |_, _| true, Some(&mut record), None, Plugged::Nothing);
+    Some((lines, LAST_FRAME_HASH.load(Ordering::Relaxed)))
+}
@@ -0,0 +1,91 @@
+// the 6502's irq pin, as we wire it up. it's open collector on the real board: anything can pull it low, it stays
+// low until every source lets go, and the cpu takes an interrupt before any instruction that starts with it low and
+// the i flag clear. so each source here gets a bit, and the run loop checks the whole line before every instruction.
+//
+// the apu's frame counter and dmc irqs come out of runes' apu tick as one, and go on the line from the Clock (see
+// region.rs) instead of straight to the cpu the way runes' own bus tick does it. the apu holds them until $4015 is
+// read or $4017 written, and observe lets go of the line on the same accesses.
+extern crate alloc;
+
+use alloc::rc::Rc;
+use core::cell::Cell;
+
+use crate::emulation::disasm::{self, Access};
+use crate::emulation::machine::{Machine, Register};
+use crate::emulation::trace::effective_address;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Source
+{
+    // the cart's counter: mmc3 scanlines, vrc cycles.
+    Mapper = 0x01,
+    // the disk system's ram adapter has two of its own, see mappers/fds.rs.
+    FdsTimer = 0x02,
+    FdsDisk = 0x04,
+    // the frame counter and the dmc, see observe.
+    Apu = 0x08,
+}
+
+pub struct IrqLine
+{
+    // a bit per source holding the line low.
+    asserted: Cell<u8>,
+}
+
+impl IrqLine
+{
+    pub fn new() -> Rc<IrqLine>
+    {
+        Rc::new(IrqLine { asserted: Cell::new(0) })
+    }
+
+    pub fn set(&self, source: Source, low: bool)
+    {
+        let asserted = self.asserted.get() & !(source as u8);
+        self.asserted.set(if low { asserted | source as u8 } else { asserted });
+    }
+
+    pub fn is_asserted_by(&self, source: Source) -> bool
+    {
+        self.asserted.get() & source as u8 != 0
+    }
+
+    // whether anyone's holding the line low.
+    pub fn asserted(&self) -> bool
+    {
+        self.asserted.get() != 0
+    }
+
+    // called before every instruction. reading the apu's status or writing its frame counter acknowledges its irq.
+    pub fn observe(&self, machine: &Machine)
+    {
+        if !self.is_asserted_by(Source::Apu) {
+            return;
+        }
+        let pc = machine.register(Register::PC);
+        let access = disasm::op(machine.peek(pc)).access();
+        match (effective_address(machine, pc), access) {
+            (Some(0x4015), Access::Read | Access::ReadWrite) | (Some(0x4017), Access::Write | Access::ReadWrite) => {
+                self.set(Source::Apu, false)
+            }
+            _ => {}
+        }
+    }
+}
+
+#[test_case]
+fn test_irq_line()
+{
+    let line = IrqLine::new();
+    assert!(!line.asserted());
+    line.set(Source::Mapper, true);
+    line.set(Source::Mapper, true);
+    assert!(line.asserted() && line.is_asserted_by(Source::Mapper));
+    line.set(Source::Mapper, false);
+    assert!(!line.asserted());
+    line.set(Source::Apu, true);
+    line.set(Source::FdsTimer, true);
+    line.set(Source::Apu, false);
+    assert!(line.asserted() && !line.is_asserted_by(Source::Apu));
+}
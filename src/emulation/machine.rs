@@ -0,0 +1,260 @@
+// the emulated hardware, held the same way the bus holds it: as raw pointers.
+// the cpu, ppu and apu all point at each other and at the mapper, so once the bus is attached rust won't let us
+// borrow any of them normally. everything in here lives on run_rom's stack for as long as the emulator runs.
+use core::cell::Cell;
+use runes::apu::APU;
+use runes::mapper::Mapper;
+use runes::memory::VMem;
+use runes::mos6502::CPU;
+use runes::ppu::PPU;
+use runes::utils;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register
+{
+    A,
+    X,
+    Y,
+    P,
+    SP,
+    PC,
+}
+
+// the ppu's oam registers, for getting at sprite memory from the cpu side the way a game would.
+const OAMADDR: u16 = 0x2003;
+const OAMDATA: u16 = 0x2004;
+
+// $2000-$5FFF: the ppu, the apu and pads, and whatever registers the mapper keeps below $6000 (the disk system's
+// drive, mmc5). anything up there might do something when read.
+pub fn is_register(addr: u16) -> bool
+{
+    (0x2000..0x6000).contains(&addr)
+}
+
+pub struct Machine<'a>
+{
+    cpu: *mut CPU<'a>,
+    ppu: *mut PPU<'a>,
+    apu: *mut APU<'a>,
+    mapper: *mut (dyn Mapper + 'a),
+    // the cart's battery ram, see SimpleCart::sram_view.
+    sram: &'static [u8],
+    // whether the run loop calls the step hook before every instruction. see set_stepping.
+    stepping: Cell<bool>,
+}
+
+impl<'a> Machine<'a>
+{
+    pub fn new(
+        cpu: *mut CPU<'a>,
+        ppu: *mut PPU<'a>,
+        apu: *mut APU<'a>,
+        mapper: *mut (dyn Mapper + 'a),
+        sram: &'static [u8],
+    ) -> Machine<'a>
+    {
+        Machine { cpu, ppu, apu, mapper, sram, stepping: Cell::new(true) }
+    }
+
+    pub fn cpu(&self) -> &mut CPU<'a>
+    {
+        unsafe { &mut *self.cpu }
+    }
+
+    pub fn ppu(&self) -> &mut PPU<'a>
+    {
+        unsafe { &mut *self.ppu }
+    }
+
+    pub fn apu(&self) -> &mut APU<'a>
+    {
+        unsafe { &mut *self.apu }
+    }
+
+    pub fn mapper(&self) -> &mut (dyn Mapper + 'a)
+    {
+        unsafe { &mut *self.mapper }
+    }
+
+    pub fn sram(&self) -> &'static [u8]
+    {
+        self.sram
+    }
+
+    // a cpu bus read for looking, not playing: ram, battery ram and prg only. read_without_tick still goes through the
+    // ppu, apu, controller and mapper registers in between, and reading those clears vblank, moves the vram address,
+    // shifts the pads and so on, so that whole range reads as 0 here instead. use read for the real thing.
+    pub fn peek(&self, addr: u16) -> u8
+    {
+        if is_register(addr) {
+            0
+        } else {
+            self.cpu().get_mem().read_without_tick(addr)
+        }
+    }
+
+    // a load on the cpu bus, side effects and all, like the game had done it. doesn't tick.
+    pub fn read(&self, addr: u16) -> u8
+    {
+        self.cpu().get_mem().read_without_tick(addr)
+    }
+
+    // a store on the cpu bus, side effects and all, like the game had done it.
+    pub fn poke(&self, addr: u16, value: u8)
+    {
+        self.cpu().mem.write(addr, value)
+    }
+
+    // the ppu's own bus: pattern tables from the cart, nametables, and the palette at $3F00.
+    pub fn ppu_peek(&self, addr: u16) -> u8
+    {
+        self.ppu().mem.read(addr & 0x3FFF)
+    }
+
+    pub fn ppu_poke(&self, addr: u16, value: u8)
+    {
+        self.ppu().mem.write(addr & 0x3FFF, value)
+    }
+
+    // all of sprite memory. the only way in is through $2003/$2004, and $2003 can't be read back to put it where the
+    // game left it, so the ppu gets saved first and loaded back after. games that set OAMADDR once and leave it for
+    // their dma never notice anyone was here.
+    pub fn oam(&self) -> [u8; 256]
+    {
+        let mut before = VecWriter(alloc::vec::Vec::new());
+        let saved = self.ppu().save(&mut before);
+        let mut oam = [0u8; 256];
+        for (index, byte) in oam.iter_mut().enumerate()
+        {
+            self.poke(OAMADDR, index as u8);
+            *byte = self.read(OAMDATA);
+        }
+        if saved {
+            self.ppu().load(&mut SliceReader::new(&before.0));
+        }
+        oam
+    }
+
+    // leaves OAMADDR just past the byte, the same as a game writing $2004 would. only the debugger does this, with the
+    // game stopped.
+    pub fn oam_poke(&self, index: u8, value: u8)
+    {
+        self.poke(OAMADDR, index);
+        self.poke(OAMDATA, value);
+    }
+
+    // the step hook is for tracing, the debugger and the viewers, and costs a call on every instruction. whoever's
+    // using it turns it off while nobody's looking. on to start with.
+    pub fn stepping(&self) -> bool
+    {
+        self.stepping.get()
+    }
+
+    pub fn set_stepping(&self, on: bool)
+    {
+        self.stepping.set(on);
+    }
+
+    pub fn register(&self, register: Register) -> u16
+    {
+        let cpu = self.cpu();
+        match register {
+            Register::A => cpu.get_a() as u16,
+            Register::X => cpu.get_x() as u16,
+            Register::Y => cpu.get_y() as u16,
+            Register::P => cpu.get_status() as u16,
+            Register::SP => cpu.get_sp() as u16,
+            Register::PC => cpu.get_pc(),
+        }
+    }
+
+    pub fn set_register(&self, register: Register, value: u16)
+    {
+        let cpu = self.cpu();
+        match register {
+            Register::A => cpu.set_a(value as u8),
+            Register::X => cpu.set_x(value as u8),
+            Register::Y => cpu.set_y(value as u8),
+            Register::P => cpu.set_status(value as u8),
+            Register::SP => cpu.set_sp(value as u8),
+            Register::PC => cpu.set_pc(value),
+        }
+    }
+
+    // one cpu cycle done by hand, the way runes' bus tick does it but with any number of ppu dots, since pal doesn't
+    // run 3 to a cycle. see region.rs. the bus tick raises the apu's irq on the cpu itself, here it's
+    // handed back instead, so it can go on the irq line with everything else.
+    pub fn tick(&self, dots: u32) -> bool
+    {
+        let cpu = self.cpu();
+        for _ in 0..dots
+        {
+            if self.ppu().tick() {
+                cpu.trigger_nmi();
+            }
+        }
+        let irq = self.apu().tick();
+        cpu.tick();
+        irq
+    }
+
+    // everything, in the same order runes saves it in: cpu (with its ram), ppu, apu, then the cart.
+    pub fn save(&self, writer: &mut dyn utils::Write) -> bool
+    {
+        self.cpu().save(writer) &&
+            self.ppu().save(writer) &&
+            self.apu().save(writer) &&
+            self.mapper().save(writer)
+    }
+
+    pub fn load(&self, reader: &mut dyn utils::Read) -> bool
+    {
+        self.cpu().load(reader) &&
+            self.ppu().load(reader) &&
+            self.apu().load(reader) &&
+            self.mapper().load(reader)
+    }
+}
+
+// utils::Write into a growable buffer.
+pub struct VecWriter(pub alloc::vec::Vec<u8>);
+
+impl utils::Write for VecWriter
+{
+    fn write(&mut self, buf: &[u8]) -> Option<usize>
+    {
+        self.0.extend_from_slice(buf);
+        Some(buf.len())
+    }
+}
+
+// utils::Read out of a byte slice.
+pub struct SliceReader<'b>
+{
+    data: &'b [u8],
+    pos: usize,
+}
+
+impl<'b> SliceReader<'b>
+{
+    pub fn new(data: &'b [u8]) -> SliceReader<'b>
+    {
+        SliceReader { data, pos: 0 }
+    }
+
+    pub fn remaining(&self) -> usize
+    {
+        self.data.len() - self.pos
+    }
+}
+
+impl<'b> utils::Read for SliceReader<'b>
+{
+    fn read(&mut self, buf: &mut [u8]) -> Option<usize>
+    {
+        let n = buf.len().min(self.remaining());
+        buf[..n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+        self.pos += n;
+        Some(n)
+    }
+}
@@ -0,0 +1,76 @@
+// mapper 7. a 32k prg bank in the low bits of any write to $8000-$FFFF, and bit 4 picks which nametable fills the
+// whole screen. chr is 8k of ram.
+use runes::cartridge::{Cartridge, MirrorType};
+use runes::mapper::Mapper;
+use runes::utils;
+
+use crate::emulation::mappers::Memory;
+
+pub struct Axrom<C: Cartridge>
+{
+    cart: C,
+    mem: Memory,
+    bank: u8,
+}
+
+impl<C: Cartridge> Axrom<C>
+{
+    pub fn new(mut cart: C) -> Axrom<C>
+    {
+        let mem = Memory::new(&mut cart);
+        cart.set_mirror_type(MirrorType::Single0);
+        Axrom { cart, mem, bank: 0 }
+    }
+}
+
+impl<C: Cartridge> Mapper for Axrom<C>
+{
+    fn read(&self, addr: u16) -> u8
+    {
+        match addr {
+            0x0000..=0x1FFF => self.mem.chr(0x2000, 0, addr),
+            0x6000..=0x7FFF => self.mem.sram(addr),
+            0x8000..=0xFFFF => self.mem.prg(0x8000, (self.bank & 0x07) as usize, addr),
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8)
+    {
+        match addr {
+            0x0000..=0x1FFF => self.mem.write_chr(0x2000, 0, addr, data),
+            0x6000..=0x7FFF => self.mem.write_sram(addr, data),
+            0x8000..=0xFFFF => {
+                self.bank = data;
+                self.cart.set_mirror_type(if data & 0x10 != 0 { MirrorType::Single1 } else { MirrorType::Single0 });
+            }
+            _ => {}
+        }
+    }
+
+    fn get_cart(&self) -> &dyn Cartridge
+    {
+        &self.cart
+    }
+
+    fn load(&mut self, reader: &mut dyn utils::Read) -> bool
+    {
+        self.cart.load(reader) && utils::load_prefix(&mut self.bank, 0, reader)
+    }
+
+    fn save(&self, writer: &mut dyn utils::Write) -> bool
+    {
+        self.cart.save(writer) && utils::save_prefix(&self.bank, 0, writer)
+    }
+}
+
+#[test_case]
+fn test_axrom_banks()
+{
+    let mut m = Axrom::new(crate::emulation::mappers::test_cart::cart(32, 8));
+    assert_eq!(m.read(0x8000), 0);
+    m.write(0x8000, 0x13);
+    assert_eq!(m.read(0x8000), 12);
+    assert_eq!(m.read(0xE000), 15);
+    assert_eq!(m.get_cart().get_mirror_type() as u8, MirrorType::Single1 as u8);
+}
@@ -0,0 +1,72 @@
+// mapper 3. fixed prg, 16k of it mirrored or 32k, and any write to $8000-$FFFF picks the 8k chr bank.
+use runes::cartridge::Cartridge;
+use runes::mapper::Mapper;
+use runes::utils;
+
+use crate::emulation::mappers::Memory;
+
+pub struct Cnrom<C: Cartridge>
+{
+    cart: C,
+    mem: Memory,
+    chr_bank: u8,
+}
+
+impl<C: Cartridge> Cnrom<C>
+{
+    pub fn new(mut cart: C) -> Cnrom<C>
+    {
+        let mem = Memory::new(&mut cart);
+        Cnrom { cart, mem, chr_bank: 0 }
+    }
+}
+
+impl<C: Cartridge> Mapper for Cnrom<C>
+{
+    fn read(&self, addr: u16) -> u8
+    {
+        match addr {
+            0x0000..=0x1FFF => self.mem.chr(0x2000, self.chr_bank as usize, addr),
+            0x6000..=0x7FFF => self.mem.sram(addr),
+            0x8000..=0xFFFF => self.mem.prg(0x8000, 0, addr),
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8)
+    {
+        match addr {
+            0x0000..=0x1FFF => self.mem.write_chr(0x2000, self.chr_bank as usize, addr, data),
+            0x6000..=0x7FFF => self.mem.write_sram(addr, data),
+            0x8000..=0xFFFF => self.chr_bank = data,
+            _ => {}
+        }
+    }
+
+    fn get_cart(&self) -> &dyn Cartridge
+    {
+        &self.cart
+    }
+
+    fn load(&mut self, reader: &mut dyn utils::Read) -> bool
+    {
+        self.cart.load(reader) && utils::load_prefix(&mut self.chr_bank, 0, reader)
+    }
+
+    fn save(&self, writer: &mut dyn utils::Write) -> bool
+    {
+        self.cart.save(writer) && utils::save_prefix(&self.chr_bank, 0, writer)
+    }
+}
+
+#[test_case]
+fn test_cnrom_banks()
+{
+    let mut m = Cnrom::new(crate::emulation::mappers::test_cart::cart(2, 32));
+    assert_eq!(m.read(0x0000), 0);
+    m.write(0x8000, 2);
+    assert_eq!(m.read(0x0000), 16);
+    assert_eq!(m.read(0x1C00), 23);
+    // 16k of prg shows up twice.
+    assert_eq!(m.read(0x8000), m.read(0xC000));
+}
@@ -0,0 +1,71 @@
+// mapper 11, color dreams and the unlicensed carts that copied it. the gxrom idea with the nibbles the other way
+// round: bits 0-1 pick the 32k prg bank and bits 4-7 the 8k chr bank.
+use runes::cartridge::Cartridge;
+use runes::mapper::Mapper;
+use runes::utils;
+
+use crate::emulation::mappers::Memory;
+
+pub struct ColorDreams<C: Cartridge>
+{
+    cart: C,
+    mem: Memory,
+    bank: u8,
+}
+
+impl<C: Cartridge> ColorDreams<C>
+{
+    pub fn new(mut cart: C) -> ColorDreams<C>
+    {
+        let mem = Memory::new(&mut cart);
+        ColorDreams { cart, mem, bank: 0 }
+    }
+}
+
+impl<C: Cartridge> Mapper for ColorDreams<C>
+{
+    fn read(&self, addr: u16) -> u8
+    {
+        match addr {
+            0x0000..=0x1FFF => self.mem.chr(0x2000, (self.bank >> 4) as usize, addr),
+            0x6000..=0x7FFF => self.mem.sram(addr),
+            0x8000..=0xFFFF => self.mem.prg(0x8000, (self.bank & 0x03) as usize, addr),
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8)
+    {
+        match addr {
+            0x0000..=0x1FFF => self.mem.write_chr(0x2000, (self.bank >> 4) as usize, addr, data),
+            0x6000..=0x7FFF => self.mem.write_sram(addr, data),
+            0x8000..=0xFFFF => self.bank = data,
+            _ => {}
+        }
+    }
+
+    fn get_cart(&self) -> &dyn Cartridge
+    {
+        &self.cart
+    }
+
+    fn load(&mut self, reader: &mut dyn utils::Read) -> bool
+    {
+        self.cart.load(reader) && utils::load_prefix(&mut self.bank, 0, reader)
+    }
+
+    fn save(&self, writer: &mut dyn utils::Write) -> bool
+    {
+        self.cart.save(writer) && utils::save_prefix(&self.bank, 0, writer)
+    }
+}
+
+#[test_case]
+fn test_color_dreams_banks()
+{
+    let mut m = ColorDreams::new(crate::emulation::mappers::test_cart::cart(16, 64));
+    m.write(0x8000, 0x21);
+    assert_eq!(m.read(0x8000), 4);
+    assert_eq!(m.read(0x0000), 16);
+    assert_eq!(m.read(0x1FFF), 23);
+}
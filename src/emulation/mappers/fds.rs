@@ -0,0 +1,404 @@
+// the disk system's ram adapter, standing in for a cart as mapper 20. it has 32k of ram at $6000-$DFFF, the bios at
+// $E000, 8k of chr ram, and its registers down at $4020-$4092 (runes hands the mapper everything from $4020 up):
+//   $4020/$4021  timer reload         $4030  status, reading it acknowledges both irqs
+//   $4022        timer control        $4031  the byte the drive just read
+//   $4023        disk and sound on    $4032  drive status: no disk, not ready, write protected
+//   $4024        byte to write        $4033  battery good
+//   $4025        drive control: motor, transfer reset, read or write, mirroring, crc, ready, irq on each byte
+// the sound registers from $4040 are fds_audio.rs.
+//
+// the drive: once the motor's on it spends a while getting to the start of the disk, then a byte goes past the
+// head every 150 cpu cycles or so, which is about what a real one does. while the bios hasn't said it's ready the
+// drive just skips gap, and once it is, the first nonzero byte (a block's $80 mark) starts the transfer. each byte
+// after that sets the transfer flag and, if the bios asked, pulls the irq line. at the end of the side the head goes
+// back to the start.
+extern crate alloc;
+
+use alloc::rc::Rc;
+use core::cell::RefCell;
+use runes::cartridge::{Cartridge, MirrorType};
+use runes::mapper::Mapper;
+use runes::utils;
+
+use crate::emulation::fds::Disk;
+use crate::emulation::irq::{IrqLine, Source};
+use crate::emulation::mappers::fds_audio::FdsAudio;
+use crate::emulation::mappers::{ExpansionAudio, Memory};
+
+// cpu cycles from the motor starting to the first byte, and between bytes after that.
+const SPIN_UP: u32 = 50000;
+const BYTE_CYCLES: u32 = 150;
+
+// $4025
+const MOTOR: u8 = 0x01;
+const TRANSFER_RESET: u8 = 0x02;
+const READ_MODE: u8 = 0x04;
+const HORIZONTAL: u8 = 0x08;
+const CRC_CONTROL: u8 = 0x10;
+const READY: u8 = 0x40;
+const DISK_IRQ: u8 = 0x80;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Registers
+{
+    timer_reload: u16,
+    timer_counter: u16,
+    timer_repeat: bool,
+    timer_enabled: bool,
+    disk_io: bool,
+    sound_io: bool,
+    control: u8,
+    write_data: u8,
+    read_data: u8,
+    // the status bits.
+    timer_irq: bool,
+    transferred: bool,
+    end_of_head: bool,
+    // the drive's own state.
+    scanning: bool,
+    gap_ended: bool,
+    position: u32,
+    delay: u32,
+}
+
+pub struct Adapter
+{
+    regs: RefCell<Registers>,
+    disk: Rc<Disk>,
+    line: Rc<IrqLine>,
+    audio: FdsAudio,
+}
+
+impl Adapter
+{
+    pub fn new(disk: Rc<Disk>, line: Rc<IrqLine>) -> Rc<Adapter>
+    {
+        Rc::new(Adapter {
+            regs: RefCell::new(Registers {
+                timer_reload: 0,
+                timer_counter: 0,
+                timer_repeat: false,
+                timer_enabled: false,
+                disk_io: false,
+                sound_io: false,
+                control: 0,
+                write_data: 0,
+                read_data: 0,
+                timer_irq: false,
+                transferred: false,
+                end_of_head: true,
+                scanning: false,
+                gap_ended: false,
+                position: 0,
+                delay: 0,
+            }),
+            disk,
+            line,
+            audio: FdsAudio::new(),
+        })
+    }
+
+    // every cpu cycle.
+    pub fn clock(&self)
+    {
+        let mut regs = self.regs.borrow_mut();
+        if regs.timer_enabled && regs.disk_io {
+            if regs.timer_counter == 0 {
+                regs.timer_irq = true;
+                self.line.set(Source::FdsTimer, true);
+                regs.timer_counter = regs.timer_reload;
+                regs.timer_enabled = regs.timer_repeat;
+            } else {
+                regs.timer_counter -= 1;
+            }
+        }
+        self.drive(&mut regs);
+        drop(regs);
+        self.audio.clock();
+    }
+
+    fn drive(&self, regs: &mut Registers)
+    {
+        if !self.disk.inserted() || regs.control & MOTOR == 0 {
+            regs.end_of_head = true;
+            regs.scanning = false;
+            return;
+        }
+        if regs.control & TRANSFER_RESET != 0 && !regs.scanning {
+            return;
+        }
+        if regs.end_of_head {
+            regs.delay = SPIN_UP;
+            regs.end_of_head = false;
+            regs.position = 0;
+            regs.gap_ended = false;
+            return;
+        }
+        if regs.delay > 0 {
+            regs.delay -= 1;
+            return;
+        }
+
+        regs.scanning = true;
+        let irq = regs.control & DISK_IRQ != 0;
+        let ready = regs.control & READY != 0;
+        let position = regs.position as usize;
+        if regs.control & READ_MODE != 0 {
+            let data = self.disk.read(position);
+            // the mark lands in $4031 like any other byte, but without an irq.
+            let mut irq = irq;
+            if !ready {
+                regs.gap_ended = false;
+            } else if data != 0 && !regs.gap_ended {
+                regs.gap_ended = true;
+                irq = false;
+            }
+            if regs.gap_ended {
+                regs.transferred = true;
+                regs.read_data = data;
+                if irq {
+                    self.line.set(Source::FdsDisk, true);
+                }
+            }
+        } else {
+            // the crc bytes are written as zeros, nothing checks them.
+            let mut data = 0;
+            if regs.control & CRC_CONTROL == 0 {
+                regs.transferred = true;
+                data = regs.write_data;
+                if irq {
+                    self.line.set(Source::FdsDisk, true);
+                }
+            }
+            self.disk.write(position, if ready { data } else { 0 });
+            regs.gap_ended = false;
+        }
+
+        regs.position += 1;
+        if regs.position as usize >= self.disk.len() {
+            regs.end_of_head = true;
+            regs.scanning = false;
+        } else {
+            regs.delay = BYTE_CYCLES;
+        }
+    }
+
+    fn read(&self, addr: u16) -> u8
+    {
+        let mut regs = self.regs.borrow_mut();
+        if !regs.disk_io {
+            return 0x40;
+        }
+        match addr {
+            0x4030 => {
+                let status = regs.timer_irq as u8
+                    | (regs.transferred as u8) << 1
+                    | (regs.end_of_head as u8) << 6;
+                regs.timer_irq = false;
+                regs.transferred = false;
+                self.line.set(Source::FdsTimer, false);
+                self.line.set(Source::FdsDisk, false);
+                status
+            }
+            0x4031 => {
+                regs.transferred = false;
+                self.line.set(Source::FdsDisk, false);
+                regs.read_data
+            }
+            0x4032 => {
+                let inserted = self.disk.inserted();
+                0x40 | !inserted as u8
+                    | ((!inserted || !regs.scanning) as u8) << 1
+                    | (!inserted as u8) << 2
+            }
+            // bit 7 is the battery, which is always fine.
+            0x4033 => 0x80,
+            _ => 0x40,
+        }
+    }
+
+    // true when mirroring needs to follow $4025.
+    fn write(&self, addr: u16, data: u8) -> bool
+    {
+        let mut regs = self.regs.borrow_mut();
+        if addr == 0x4023 {
+            regs.disk_io = data & 0x01 != 0;
+            regs.sound_io = data & 0x02 != 0;
+            if !regs.disk_io {
+                regs.timer_enabled = false;
+                regs.timer_irq = false;
+                self.line.set(Source::FdsTimer, false);
+                self.line.set(Source::FdsDisk, false);
+            }
+            return false;
+        }
+        if !regs.disk_io {
+            return false;
+        }
+        match addr {
+            0x4020 => regs.timer_reload = (regs.timer_reload & 0xFF00) | data as u16,
+            0x4021 => regs.timer_reload = (regs.timer_reload & 0x00FF) | (data as u16) << 8,
+            0x4022 => {
+                regs.timer_repeat = data & 0x01 != 0;
+                regs.timer_enabled = data & 0x02 != 0;
+                if regs.timer_enabled {
+                    regs.timer_counter = regs.timer_reload;
+                } else {
+                    regs.timer_irq = false;
+                    self.line.set(Source::FdsTimer, false);
+                }
+            }
+            0x4024 => {
+                regs.write_data = data;
+                regs.transferred = false;
+                self.line.set(Source::FdsDisk, false);
+            }
+            0x4025 => {
+                regs.control = data;
+                self.line.set(Source::FdsDisk, false);
+                return true;
+            }
+            _ => {}
+        }
+        false
+    }
+
+    fn save(&self, writer: &mut dyn utils::Write) -> bool
+    {
+        let line = [self.line.is_asserted_by(Source::FdsTimer) as u8, self.line.is_asserted_by(Source::FdsDisk) as u8];
+        utils::save_prefix(&*self.regs.borrow(), 0, writer)
+            && writer.write(&line) == Some(line.len())
+            && self.audio.save(writer)
+    }
+
+    fn load(&self, reader: &mut dyn utils::Read) -> bool
+    {
+        let mut line = [0u8; 2];
+        if !utils::load_prefix(&mut *self.regs.borrow_mut(), 0, reader) || reader.read(&mut line) != Some(line.len()) {
+            return false;
+        }
+        self.line.set(Source::FdsTimer, line[0] != 0);
+        self.line.set(Source::FdsDisk, line[1] != 0);
+        self.audio.load(reader)
+    }
+}
+
+impl ExpansionAudio for Adapter
+{
+    fn sample(&self) -> i16
+    {
+        self.audio.sample()
+    }
+}
+
+pub struct Fds<C: Cartridge>
+{
+    cart: C,
+    mem: Memory,
+    adapter: Rc<Adapter>,
+}
+
+impl<C: Cartridge> Fds<C>
+{
+    // the cart's prg is the bios and its sram is the adapter's 32k, see fds::load.
+    pub fn new(mut cart: C, adapter: Rc<Adapter>) -> Fds<C>
+    {
+        let mem = Memory::new(&mut cart);
+        Fds { cart, mem, adapter }
+    }
+}
+
+impl<C: Cartridge> Mapper for Fds<C>
+{
+    fn read(&self, addr: u16) -> u8
+    {
+        match addr {
+            0x0000..=0x1FFF => self.mem.chr(0x2000, 0, addr),
+            0x4030..=0x403F => self.adapter.read(addr),
+            0x4040..=0x409F => self.adapter.audio.read(addr),
+            0x6000..=0xDFFF => self.mem.sram(addr),
+            0xE000..=0xFFFF => self.mem.prg(0x2000, 0, addr),
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8)
+    {
+        match addr {
+            0x0000..=0x1FFF => self.mem.write_chr(0x2000, 0, addr, data),
+            0x4020..=0x403F => {
+                if self.adapter.write(addr, data) {
+                    self.cart.set_mirror_type(if data & HORIZONTAL != 0 {
+                        MirrorType::Horizontal
+                    } else {
+                        MirrorType::Vertical
+                    });
+                }
+            }
+            0x4040..=0x409F => {
+                if self.adapter.regs.borrow().sound_io {
+                    self.adapter.audio.write(addr, data);
+                }
+            }
+            0x6000..=0xDFFF => self.mem.write_sram(addr, data),
+            _ => {}
+        }
+    }
+
+    fn get_cart(&self) -> &dyn Cartridge
+    {
+        &self.cart
+    }
+
+    // the ram goes in with the cart's sram. the disk doesn't go in at all, so a state loaded after a game saved to
+    // disk sees the newer disk.
+    fn load(&mut self, reader: &mut dyn utils::Read) -> bool
+    {
+        self.cart.load(reader) && self.adapter.load(reader)
+    }
+
+    fn save(&self, writer: &mut dyn utils::Write) -> bool
+    {
+        self.cart.save(writer) && self.adapter.save(writer)
+    }
+}
+
+#[test_case]
+fn test_fds_timer_and_drive()
+{
+    extern crate alloc;
+    use alloc::vec;
+
+    let line = IrqLine::new();
+    let mut side = vec![0u8; 64];
+    side[10] = 0x80;
+    side[11] = 0x01;
+    let adapter = Adapter::new(Disk::new(vec![side]), line.clone());
+    let mut m = Fds::new(crate::emulation::mappers::test_cart::cart(1, 8), adapter.clone());
+
+    // one shot timer, 3 cycles.
+    m.write(0x4023, 0x01);
+    m.write(0x4020, 3);
+    m.write(0x4022, 0x02);
+    for _ in 0..4
+    {
+        adapter.clock();
+    }
+    assert!(line.is_asserted_by(Source::FdsTimer));
+    assert_eq!(m.read(0x4030) & 0x01, 0x01);
+    assert!(!line.asserted());
+
+    // motor on, reading, ready: the $80 mark at 10 starts it and the byte after is the first one handed over.
+    m.write(0x4025, MOTOR | READ_MODE | READY | DISK_IRQ);
+    let mut first = None;
+    for _ in 0..SPIN_UP + 13 * (BYTE_CYCLES + 1)
+    {
+        adapter.clock();
+        if first.is_none() && line.is_asserted_by(Source::FdsDisk) {
+            first = Some(m.read(0x4031));
+        }
+    }
+    assert_eq!(first, Some(0x01));
+    assert_eq!(m.get_cart().get_mirror_type() as u8, MirrorType::Vertical as u8);
+}
@@ -0,0 +1,324 @@
+// the disk system's extra sound channel: one voice playing a 64 step wave the game draws itself, with a volume
+// envelope and a second wave (the modulator) bending its pitch for vibrato. registers at $4040-$408A:
+//   $4040-$407F  the wave, 6 bits a step, only writable while $4089 bit 7 is set
+//   $4080        volume envelope: bit 7 off (then bits 0-5 are the volume), bit 6 up rather than down, 0-5 speed
+//   $4082/$4083  12 bit pitch. $4083 bit 7 stops the wave, bit 6 stops both envelopes
+//   $4084        modulator envelope, same as $4080
+//   $4085        modulator position, 7 bits signed
+//   $4086/$4087  12 bit modulator speed, $4087 bit 7 stops it
+//   $4088        the modulator's steps, 3 bits each, written while it's stopped
+//   $4089        bit 7 lets the wave be written, bits 0-1 master volume, full down to 2/5
+//   $408A        how fast both envelopes go
+// runes' apu doesn't know about any of this, so TerminalAudio adds sample() to every sample the apu makes.
+use core::cell::RefCell;
+use runes::utils;
+
+// the modulator's steps: how far each moves the position. 4 sends it back to 0.
+const MOD_STEPS: [i8; 8] = [0, 1, 2, 4, 0, -4, -2, -1];
+const MOD_RESET: u8 = 4;
+// the master volume as fractions of 1152, the biggest a step times the volume gets.
+const MASTER_VOLUME: [i32; 4] = [36, 24, 17, 14];
+// from the wave's -32..31 up to something you can hear next to the apu.
+const OUTPUT_SCALE: i32 = 200;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Envelope
+{
+    speed: u8,
+    gain: u8,
+    up: bool,
+    off: bool,
+    timer: u32,
+}
+
+impl Envelope
+{
+    fn write(&mut self, data: u8, master: u8)
+    {
+        self.speed = data & 0x3F;
+        self.up = data & 0x40 != 0;
+        self.off = data & 0x80 != 0;
+        if self.off {
+            self.gain = self.speed;
+        }
+        self.reset(master);
+    }
+
+    fn reset(&mut self, master: u8)
+    {
+        self.timer = 8 * (self.speed as u32 + 1) * master as u32;
+    }
+
+    // true when the gain moved.
+    fn clock(&mut self, master: u8) -> bool
+    {
+        if self.off || master == 0 {
+            return false;
+        }
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer > 0 {
+            return false;
+        }
+        self.reset(master);
+        if self.up && self.gain < 32 {
+            self.gain += 1;
+        } else if !self.up && self.gain > 0 {
+            self.gain -= 1;
+        }
+        true
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct State
+{
+    wave: [u8; 64],
+    wave_writable: bool,
+    master_volume: u8,
+    envelope_speed: u8,
+    volume: Envelope,
+    pitch: u16,
+    wave_halted: bool,
+    envelopes_halted: bool,
+    wave_acc: u16,
+    wave_pos: u8,
+    mod_env: Envelope,
+    mod_table: [u8; 64],
+    mod_pos: u8,
+    mod_speed: u16,
+    mod_halted: bool,
+    mod_acc: u16,
+    mod_counter: i8,
+    // how far the modulator bends the pitch right now.
+    mod_bend: i32,
+}
+
+pub struct FdsAudio
+{
+    state: RefCell<State>,
+}
+
+// a 7 bit signed position, wrapping round.
+fn wrap_counter(value: i32) -> i8
+{
+    (((value + 64) & 0x7F) - 64) as i8
+}
+
+impl State
+{
+    // the pitch change for the modulator's position, worked out the way the chip does it, rounding and all.
+    fn bend(&mut self)
+    {
+        let counter = self.mod_counter as i32;
+        let mut temp = counter * self.mod_env.gain as i32;
+        let remainder = temp & 0x0F;
+        temp >>= 4;
+        if remainder > 0 && temp & 0x80 == 0 {
+            temp += if counter < 0 { -1 } else { 2 };
+        }
+        if temp >= 192 {
+            temp -= 256;
+        } else if temp < -64 {
+            temp += 256;
+        }
+        temp *= self.pitch as i32;
+        let remainder = temp & 0x3F;
+        temp >>= 6;
+        if remainder >= 32 {
+            temp += 1;
+        }
+        self.mod_bend = temp;
+    }
+}
+
+impl FdsAudio
+{
+    pub fn new() -> FdsAudio
+    {
+        let envelope = Envelope { speed: 0, gain: 0, up: false, off: true, timer: 0 };
+        FdsAudio {
+            state: RefCell::new(State {
+                wave: [0; 64],
+                wave_writable: false,
+                master_volume: 0,
+                envelope_speed: 0xE8,
+                volume: envelope,
+                pitch: 0,
+                wave_halted: true,
+                envelopes_halted: false,
+                wave_acc: 0,
+                wave_pos: 0,
+                mod_env: envelope,
+                mod_table: [0; 64],
+                mod_pos: 0,
+                mod_speed: 0,
+                mod_halted: true,
+                mod_acc: 0,
+                mod_counter: 0,
+                mod_bend: 0,
+            }),
+        }
+    }
+
+    // every cpu cycle.
+    pub fn clock(&self)
+    {
+        let mut s = self.state.borrow_mut();
+        let master = s.envelope_speed;
+        if !s.wave_halted && !s.envelopes_halted {
+            s.volume.clock(master);
+            if s.mod_env.clock(master) {
+                s.bend();
+            }
+        }
+
+        if !s.mod_halted && s.mod_speed > 0 {
+            let (acc, overflow) = s.mod_acc.overflowing_add(s.mod_speed);
+            s.mod_acc = acc;
+            if overflow {
+                let step = s.mod_table[s.mod_pos as usize];
+                s.mod_counter = if step == MOD_RESET {
+                    0
+                } else {
+                    wrap_counter(s.mod_counter as i32 + MOD_STEPS[step as usize] as i32)
+                };
+                s.mod_pos = (s.mod_pos + 1) & 0x3F;
+                s.bend();
+            }
+        }
+
+        if s.wave_halted {
+            s.wave_pos = 0;
+            return;
+        }
+        let pitch = s.pitch as i32 + s.mod_bend;
+        if pitch > 0 && !s.wave_writable {
+            let (acc, overflow) = s.wave_acc.overflowing_add(pitch as u16);
+            s.wave_acc = acc;
+            if overflow {
+                s.wave_pos = (s.wave_pos + 1) & 0x3F;
+            }
+        }
+    }
+
+    // what the channel's putting out, for mixing in with the apu.
+    pub fn sample(&self) -> i16
+    {
+        let s = self.state.borrow();
+        let step = s.wave[s.wave_pos as usize] as i32 - 32;
+        let level = step * s.volume.gain.min(32) as i32 * MASTER_VOLUME[s.master_volume as usize] / 1152;
+        (level * OUTPUT_SCALE) as i16
+    }
+
+    pub fn read(&self, addr: u16) -> u8
+    {
+        let s = self.state.borrow();
+        match addr {
+            0x4040..=0x407F => s.wave[(addr & 0x3F) as usize] | 0x40,
+            0x4090 => s.volume.gain | 0x40,
+            0x4092 => s.mod_env.gain | 0x40,
+            _ => 0x40,
+        }
+    }
+
+    pub fn write(&self, addr: u16, data: u8)
+    {
+        let mut s = self.state.borrow_mut();
+        let master = s.envelope_speed;
+        match addr {
+            0x4040..=0x407F => {
+                if s.wave_writable {
+                    s.wave[(addr & 0x3F) as usize] = data & 0x3F;
+                }
+            }
+            0x4080 => s.volume.write(data, master),
+            0x4082 => {
+                s.pitch = (s.pitch & 0x0F00) | data as u16;
+                s.bend();
+            }
+            0x4083 => {
+                s.pitch = (s.pitch & 0x00FF) | ((data as u16 & 0x0F) << 8);
+                s.wave_halted = data & 0x80 != 0;
+                s.envelopes_halted = data & 0x40 != 0;
+                if s.wave_halted {
+                    s.wave_pos = 0;
+                    s.wave_acc = 0;
+                }
+                if s.envelopes_halted {
+                    s.volume.reset(master);
+                    s.mod_env.reset(master);
+                }
+                s.bend();
+            }
+            0x4084 => {
+                s.mod_env.write(data, master);
+                s.bend();
+            }
+            0x4085 => {
+                s.mod_counter = wrap_counter((data & 0x7F) as i32);
+                s.bend();
+            }
+            0x4086 => s.mod_speed = (s.mod_speed & 0x0F00) | data as u16,
+            0x4087 => {
+                s.mod_speed = (s.mod_speed & 0x00FF) | ((data as u16 & 0x0F) << 8);
+                s.mod_halted = data & 0x80 != 0;
+                if s.mod_halted {
+                    s.mod_acc = 0;
+                }
+            }
+            0x4088 => {
+                // each write fills two steps.
+                if s.mod_halted {
+                    let pos = s.mod_pos as usize;
+                    s.mod_table[pos] = data & 0x07;
+                    s.mod_table[(pos + 1) & 0x3F] = data & 0x07;
+                    s.mod_pos = ((pos + 2) & 0x3F) as u8;
+                }
+            }
+            0x4089 => {
+                s.master_volume = data & 0x03;
+                s.wave_writable = data & 0x80 != 0;
+            }
+            0x408A => s.envelope_speed = data,
+            _ => {}
+        }
+    }
+
+    pub fn save(&self, writer: &mut dyn utils::Write) -> bool
+    {
+        utils::save_prefix(&*self.state.borrow(), 0, writer)
+    }
+
+    pub fn load(&self, reader: &mut dyn utils::Read) -> bool
+    {
+        utils::load_prefix(&mut *self.state.borrow_mut(), 0, reader)
+    }
+}
+
+#[test_case]
+fn test_fds_audio_wave()
+{
+    let audio = FdsAudio::new();
+    audio.write(0x4089, 0x80);
+    for i in 0..64u16
+    {
+        audio.write(0x4040 + i, if i < 32 { 63 } else { 0 });
+    }
+    audio.write(0x4089, 0x00);
+    assert_eq!(audio.read(0x4040), 63 | 0x40);
+    // full volume, no envelope.
+    audio.write(0x4080, 0x80 | 32);
+    assert_eq!(audio.read(0x4090), 32 | 0x40);
+    assert!(audio.sample() > 0);
+
+    // a pitch of $800 steps the wave every 32 cycles, so 32 steps later it's on the low half.
+    audio.write(0x4082, 0x00);
+    audio.write(0x4083, 0x08);
+    for _ in 0..32 * 32
+    {
+        audio.clock();
+    }
+    assert!(audio.sample() < 0);
+}
@@ -0,0 +1,70 @@
+// mapper 66. any write to $8000-$FFFF picks a 32k prg bank in bits 4-5 and an 8k chr bank in bits 0-1.
+use runes::cartridge::Cartridge;
+use runes::mapper::Mapper;
+use runes::utils;
+
+use crate::emulation::mappers::Memory;
+
+pub struct Gxrom<C: Cartridge>
+{
+    cart: C,
+    mem: Memory,
+    bank: u8,
+}
+
+impl<C: Cartridge> Gxrom<C>
+{
+    pub fn new(mut cart: C) -> Gxrom<C>
+    {
+        let mem = Memory::new(&mut cart);
+        Gxrom { cart, mem, bank: 0 }
+    }
+}
+
+impl<C: Cartridge> Mapper for Gxrom<C>
+{
+    fn read(&self, addr: u16) -> u8
+    {
+        match addr {
+            0x0000..=0x1FFF => self.mem.chr(0x2000, (self.bank & 0x03) as usize, addr),
+            0x6000..=0x7FFF => self.mem.sram(addr),
+            0x8000..=0xFFFF => self.mem.prg(0x8000, ((self.bank >> 4) & 0x03) as usize, addr),
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8)
+    {
+        match addr {
+            0x0000..=0x1FFF => self.mem.write_chr(0x2000, (self.bank & 0x03) as usize, addr, data),
+            0x6000..=0x7FFF => self.mem.write_sram(addr, data),
+            0x8000..=0xFFFF => self.bank = data,
+            _ => {}
+        }
+    }
+
+    fn get_cart(&self) -> &dyn Cartridge
+    {
+        &self.cart
+    }
+
+    fn load(&mut self, reader: &mut dyn utils::Read) -> bool
+    {
+        self.cart.load(reader) && utils::load_prefix(&mut self.bank, 0, reader)
+    }
+
+    fn save(&self, writer: &mut dyn utils::Write) -> bool
+    {
+        self.cart.save(writer) && utils::save_prefix(&self.bank, 0, writer)
+    }
+}
+
+#[test_case]
+fn test_gxrom_banks()
+{
+    let mut m = Gxrom::new(crate::emulation::mappers::test_cart::cart(16, 32));
+    m.write(0x8000, 0x21);
+    assert_eq!(m.read(0x8000), 8);
+    assert_eq!(m.read(0x0000), 8);
+    assert_eq!(m.read(0x1FFF), 15);
+}
@@ -0,0 +1,161 @@
+// mappers 9 and 10, nintendo's mmc2 (punch-out!!) and mmc4 (fire emblem, famicom wars).
+// each 4k half of chr has two banks and a latch picking between them. the ppu fetching tile $FD or $FE flips the
+// latch, so a game can put a marker tile in its picture and have the chr switch partway down the screen.
+// mmc2 switches 8k of prg at $8000 with the last 24k fixed, mmc4 switches 16k with the last 16k fixed and has sram.
+use core::cell::Cell;
+use runes::cartridge::{Cartridge, MirrorType};
+use runes::mapper::Mapper;
+use runes::utils;
+
+use crate::emulation::mappers::Memory;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chip
+{
+    Mmc2,
+    Mmc4,
+}
+
+#[repr(C)]
+struct Registers
+{
+    prg: u8,
+    // [half][latch], the latch being 0 for $FD and 1 for $FE.
+    chr: [[u8; 2]; 2],
+    mirror: u8,
+    // the ppu reads through &self, and these change on reads.
+    latch: [Cell<u8>; 2],
+}
+
+pub struct Mmc2<C: Cartridge>
+{
+    cart: C,
+    mem: Memory,
+    chip: Chip,
+    regs: Registers,
+}
+
+impl<C: Cartridge> Mmc2<C>
+{
+    pub fn new(mut cart: C, chip: Chip) -> Mmc2<C>
+    {
+        let mem = Memory::new(&mut cart);
+        let regs = Registers { prg: 0, chr: [[0; 2]; 2], mirror: 0, latch: [Cell::new(0), Cell::new(0)] };
+        Mmc2 { cart, mem, chip, regs }
+    }
+
+    fn chr_bank(&self, addr: u16) -> usize
+    {
+        let half = (addr >> 12) as usize & 1;
+        self.regs.chr[half][self.regs.latch[half].get() as usize] as usize
+    }
+
+    // the latch flips after the fetch that tripped it, so the marker tile itself still comes from the old bank.
+    fn watch(&self, addr: u16)
+    {
+        let half = (addr >> 12) as usize & 1;
+        // the mmc2 only looks at one exact address in the low half, the mmc4 at the whole tile, like the high half.
+        let whole_tile = half == 1 || self.chip == Chip::Mmc4;
+        let tile = addr & 0x0FF8;
+        let hit = |marker: u16| if whole_tile { tile == marker } else { addr & 0x0FFF == marker };
+        if hit(0x0FD8) {
+            self.regs.latch[half].set(0);
+        } else if hit(0x0FE8) {
+            self.regs.latch[half].set(1);
+        }
+    }
+
+    fn set_mirror(&mut self, data: u8)
+    {
+        self.regs.mirror = data;
+        self.cart.set_mirror_type(if data & 1 != 0 { MirrorType::Horizontal } else { MirrorType::Vertical });
+    }
+}
+
+impl<C: Cartridge> Mapper for Mmc2<C>
+{
+    fn read(&self, addr: u16) -> u8
+    {
+        match addr {
+            0x0000..=0x1FFF => {
+                let data = self.mem.chr(0x1000, self.chr_bank(addr), addr);
+                self.watch(addr);
+                data
+            }
+            0x6000..=0x7FFF => self.mem.sram(addr),
+            0x8000..=0xFFFF => match self.chip {
+                Chip::Mmc2 => {
+                    let last = self.mem.prg_banks(0x2000);
+                    let bank = match addr {
+                        0x8000..=0x9FFF => self.regs.prg as usize,
+                        0xA000..=0xBFFF => last - 3,
+                        0xC000..=0xDFFF => last - 2,
+                        _ => last - 1,
+                    };
+                    self.mem.prg(0x2000, bank, addr)
+                }
+                Chip::Mmc4 => {
+                    let bank = if addr < 0xC000 { self.regs.prg as usize } else { self.mem.prg_banks(0x4000) - 1 };
+                    self.mem.prg(0x4000, bank, addr)
+                }
+            },
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8)
+    {
+        match addr {
+            0x0000..=0x1FFF => {
+                let bank = self.chr_bank(addr);
+                self.mem.write_chr(0x1000, bank, addr, data)
+            }
+            0x6000..=0x7FFF => self.mem.write_sram(addr, data),
+            0xA000..=0xAFFF => self.regs.prg = data & 0x0F,
+            0xB000..=0xBFFF => self.regs.chr[0][0] = data & 0x1F,
+            0xC000..=0xCFFF => self.regs.chr[0][1] = data & 0x1F,
+            0xD000..=0xDFFF => self.regs.chr[1][0] = data & 0x1F,
+            0xE000..=0xEFFF => self.regs.chr[1][1] = data & 0x1F,
+            0xF000..=0xFFFF => self.set_mirror(data),
+            _ => {}
+        }
+    }
+
+    fn get_cart(&self) -> &dyn Cartridge
+    {
+        &self.cart
+    }
+
+    fn load(&mut self, reader: &mut dyn utils::Read) -> bool
+    {
+        self.cart.load(reader) && utils::load_prefix(&mut self.regs, 0, reader)
+    }
+
+    fn save(&self, writer: &mut dyn utils::Write) -> bool
+    {
+        self.cart.save(writer) && utils::save_prefix(&self.regs, 0, writer)
+    }
+}
+
+#[test_case]
+fn test_mmc2_latches()
+{
+    let mut m = Mmc2::new(crate::emulation::mappers::test_cart::cart(16, 128), Chip::Mmc2);
+    m.write(0xA000, 5);
+    assert_eq!(m.read(0x8000), 5);
+    assert_eq!(m.read(0xA000), 13);
+    assert_eq!(m.read(0xE000), 15);
+
+    // 4k banks, so bank n starts at 1k bank 4n.
+    m.write(0xB000, 1);
+    m.write(0xC000, 2);
+    assert_eq!(m.read(0x0000), 4);
+    // the fetch that flips the latch still comes from $FD's bank.
+    assert_eq!(m.read(0x0FE8), 7);
+    assert_eq!(m.read(0x0000), 8);
+    // mmc2's low half only flips on the exact address.
+    m.read(0x0FD9);
+    assert_eq!(m.read(0x0000), 8);
+    m.read(0x0FD8);
+    assert_eq!(m.read(0x0000), 4);
+}
@@ -0,0 +1,321 @@
+// mapper 4, nintendo's mmc3 (smb3, kirby, mega man 3-6), replacing runes' so the scanline irq goes through our
+// IrqLine where we can see it reach the cpu.
+// two switchable 8k prg banks and two fixed, two 2k and four 1k chr banks, and a counter clocked by the ppu's a12
+// address line going high. with the background at $0000 and sprites at $1000 that happens once a scanline, when the
+// ppu starts on the sprite patterns, so games count scanlines with it to split the screen for status bars.
+// a12 has to have been low for a while first, the chip filters out the quick wiggles from 8x16 sprites mixing both
+// pattern tables, so we only count a rise after a few fetches from the low table.
+//
+// the ppu fetches its patterns through us, so those rises we see directly. the cpu can also move a12 by pointing
+// $2006 at the upper table, which never reaches the mapper, so Mmc3Irq::observe watches for that the same way
+// ppuview's ScrollShadow does.
+extern crate alloc;
+
+use alloc::rc::Rc;
+use core::cell::Cell;
+use runes::cartridge::{Cartridge, MirrorType};
+use runes::mapper::Mapper;
+use runes::utils;
+
+use crate::emulation::disasm::{self, Access};
+use crate::emulation::irq::{IrqLine, Source};
+use crate::emulation::machine::{Machine, Register};
+use crate::emulation::mappers::Memory;
+use crate::emulation::trace::{effective_address, stored_value};
+
+// pattern fetches from the low table before a rise counts.
+const A12_FILTER: u8 = 3;
+
+pub struct Mmc3Irq
+{
+    latch: Cell<u8>,
+    counter: Cell<u8>,
+    reload: Cell<bool>,
+    enabled: Cell<bool>,
+    // fetches in a row with a12 low.
+    low: Cell<u8>,
+    // the cpu's half of $2006: the write toggle, and the high byte once it's been written.
+    w: Cell<bool>,
+    high: Cell<u8>,
+    line: Rc<IrqLine>,
+}
+
+impl Mmc3Irq
+{
+    pub fn new(line: Rc<IrqLine>) -> Rc<Mmc3Irq>
+    {
+        Rc::new(Mmc3Irq {
+            latch: Cell::new(0),
+            counter: Cell::new(0),
+            reload: Cell::new(false),
+            enabled: Cell::new(false),
+            low: Cell::new(0),
+            w: Cell::new(false),
+            high: Cell::new(0),
+            line,
+        })
+    }
+
+    // every pattern fetch, with the address it fetched.
+    fn a12(&self, addr: u16)
+    {
+        if addr & 0x1000 == 0 {
+            self.low.set(self.low.get().saturating_add(1));
+            return;
+        }
+        if self.low.get() >= A12_FILTER {
+            self.clock();
+        }
+        self.low.set(0);
+    }
+
+    fn clock(&self)
+    {
+        let counter = self.counter.get();
+        if counter == 0 || self.reload.get() {
+            self.counter.set(self.latch.get());
+            self.reload.set(false);
+        } else {
+            self.counter.set(counter - 1);
+        }
+        if self.counter.get() == 0 && self.enabled.get() {
+            self.line.set(Source::Mapper, true);
+        }
+    }
+
+    fn write(&self, addr: u16, data: u8)
+    {
+        match addr & 0xE001 {
+            0xC000 => self.latch.set(data),
+            0xC001 => {
+                self.counter.set(0);
+                self.reload.set(true);
+            }
+            0xE000 => {
+                self.enabled.set(false);
+                self.line.set(Source::Mapper, false);
+            }
+            _ => self.enabled.set(true),
+        }
+    }
+
+    // called before every instruction, for $2006 writes and the $2002 reads that reset its toggle.
+    pub fn observe(&self, machine: &Machine)
+    {
+        let pc = machine.register(Register::PC);
+        let access = disasm::op(machine.peek(pc)).access();
+        if access == Access::None {
+            return;
+        }
+        let addr = match effective_address(machine, pc) {
+            Some(a) if (0x2000..0x4000).contains(&a) => a & 0x2007,
+            _ => return,
+        };
+        let value = stored_value(machine, pc);
+        match (addr, value) {
+            (0x2002, None) => self.w.set(false),
+            (0x2005, Some(_)) => self.w.set(!self.w.get()),
+            (0x2006, Some(v)) if !self.w.get() => {
+                self.high.set(v);
+                self.w.set(true);
+            }
+            (0x2006, Some(_)) => {
+                self.w.set(false);
+                // an instruction is plenty of time for the filter, so going low counts as long enough.
+                if self.high.get() & 0x10 == 0 {
+                    self.low.set(A12_FILTER);
+                } else {
+                    self.a12(0x1000);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn save(&self, writer: &mut dyn utils::Write) -> bool
+    {
+        let state = [
+            self.latch.get(),
+            self.counter.get(),
+            self.reload.get() as u8,
+            self.enabled.get() as u8,
+            self.low.get(),
+            self.w.get() as u8,
+            self.high.get(),
+            self.line.is_asserted_by(Source::Mapper) as u8,
+        ];
+        writer.write(&state) == Some(state.len())
+    }
+
+    fn load(&self, reader: &mut dyn utils::Read) -> bool
+    {
+        let mut state = [0u8; 8];
+        if reader.read(&mut state) != Some(state.len()) {
+            return false;
+        }
+        self.latch.set(state[0]);
+        self.counter.set(state[1]);
+        self.reload.set(state[2] != 0);
+        self.enabled.set(state[3] != 0);
+        self.low.set(state[4]);
+        self.w.set(state[5] != 0);
+        self.high.set(state[6]);
+        self.line.set(Source::Mapper, state[7] != 0);
+        true
+    }
+}
+
+#[repr(C)]
+struct Registers
+{
+    // which of r0-r7 $8001 writes, plus the prg and chr mode bits.
+    select: u8,
+    r: [u8; 8],
+    mirror: u8,
+}
+
+pub struct Mmc3<C: Cartridge>
+{
+    cart: C,
+    mem: Memory,
+    four_screen: bool,
+    regs: Registers,
+    irq: Rc<Mmc3Irq>,
+}
+
+impl<C: Cartridge> Mmc3<C>
+{
+    pub fn new(mut cart: C, irq: Rc<Mmc3Irq>) -> Mmc3<C>
+    {
+        let mem = Memory::new(&mut cart);
+        let four_screen = cart.get_mirror_type() as u8 == MirrorType::Four as u8;
+        let regs = Registers { select: 0, r: [0, 2, 4, 5, 6, 7, 0, 1], mirror: 0 };
+        Mmc3 { cart, mem, four_screen, regs, irq }
+    }
+
+    // 1k chr bank for a ppu address.
+    fn chr_bank(&self, addr: u16) -> usize
+    {
+        // bit 7 swaps which half gets the 2k banks.
+        let addr = if self.regs.select & 0x80 != 0 { addr ^ 0x1000 } else { addr };
+        let slot = (addr >> 10) as usize;
+        match slot {
+            0..=3 => (self.regs.r[slot / 2] & 0xFE) as usize + (slot & 1),
+            _ => self.regs.r[slot - 2] as usize,
+        }
+    }
+
+    fn prg_bank(&self, addr: u16) -> usize
+    {
+        let second_last = self.mem.prg_banks(0x2000).saturating_sub(2);
+        let swapped = self.regs.select & 0x40 != 0;
+        match (addr, swapped) {
+            (0x8000..=0x9FFF, false) | (0xC000..=0xDFFF, true) => self.regs.r[6] as usize,
+            (0x8000..=0x9FFF, true) | (0xC000..=0xDFFF, false) => second_last,
+            (0xA000..=0xBFFF, _) => self.regs.r[7] as usize,
+            _ => second_last + 1,
+        }
+    }
+
+    fn set_mirror(&mut self, data: u8)
+    {
+        self.regs.mirror = data;
+        if !self.four_screen {
+            self.cart.set_mirror_type(if data & 1 != 0 { MirrorType::Horizontal } else { MirrorType::Vertical });
+        }
+    }
+}
+
+impl<C: Cartridge> Mapper for Mmc3<C>
+{
+    fn read(&self, addr: u16) -> u8
+    {
+        match addr {
+            0x0000..=0x1FFF => {
+                self.irq.a12(addr);
+                self.mem.chr(0x400, self.chr_bank(addr), addr)
+            }
+            0x6000..=0x7FFF => self.mem.sram(addr),
+            0x8000..=0xFFFF => self.mem.prg(0x2000, self.prg_bank(addr), addr),
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8)
+    {
+        match addr {
+            0x0000..=0x1FFF => {
+                self.irq.a12(addr);
+                let bank = self.chr_bank(addr);
+                self.mem.write_chr(0x400, bank, addr, data)
+            }
+            0x6000..=0x7FFF => self.mem.write_sram(addr, data),
+            0x8000..=0xFFFF => match addr & 0xE001 {
+                0x8000 => self.regs.select = data,
+                0x8001 => self.regs.r[(self.regs.select & 0x07) as usize] = data,
+                0xA000 => self.set_mirror(data),
+                // $A001 is prg ram protect, which nothing needs honoured.
+                0xA001 => {}
+                _ => self.irq.write(addr, data),
+            },
+            _ => {}
+        }
+    }
+
+    fn get_cart(&self) -> &dyn Cartridge
+    {
+        &self.cart
+    }
+
+    fn load(&mut self, reader: &mut dyn utils::Read) -> bool
+    {
+        self.cart.load(reader) && utils::load_prefix(&mut self.regs, 0, reader) && self.irq.load(reader)
+    }
+
+    fn save(&self, writer: &mut dyn utils::Write) -> bool
+    {
+        self.cart.save(writer) && utils::save_prefix(&self.regs, 0, writer) && self.irq.save(writer)
+    }
+}
+
+#[test_case]
+fn test_mmc3_banks_and_scanline_irq()
+{
+    let line = IrqLine::new();
+    let irq = Mmc3Irq::new(line.clone());
+    let mut m = Mmc3::new(crate::emulation::mappers::test_cart::cart(16, 256), irq);
+
+    m.write(0x8000, 0x06);
+    m.write(0x8001, 3);
+    assert_eq!(m.read(0x8000), 3);
+    assert_eq!(m.read(0xC000), 14);
+    m.write(0x8000, 0x46);
+    assert_eq!(m.read(0x8000), 14);
+    assert_eq!(m.read(0xC000), 3);
+
+    // r0 is 2k, so bank 9 reads as 8 and 9. with bit 7 set it moves up to $1000.
+    m.write(0x8000, 0x00);
+    m.write(0x8001, 9);
+    assert_eq!(m.read(0x0400), 9);
+    m.write(0x8000, 0x80);
+    assert_eq!(m.read(0x1000), 8);
+
+    // an irq every 2 scanlines: a background fetch from $0xxx, then the sprites from $1xxx.
+    m.write(0xC000, 1);
+    m.write(0xC001, 0);
+    m.write(0xE001, 0);
+    let scanline = |m: &Mmc3<_>| {
+        for _ in 0..A12_FILTER
+        {
+            m.read(0x0000);
+        }
+        m.read(0x1000);
+        m.read(0x1008);
+    };
+    scanline(&m);
+    assert!(!line.asserted());
+    scanline(&m);
+    assert!(line.asserted());
+    m.write(0xE000, 0);
+    assert!(!line.asserted());
+}
@@ -0,0 +1,171 @@
+// the mappers runes doesn't come with. between these and runes' 1, 2 and 4 that's most of the licensed library.
+//   0      nrom, no mapper at all
+//   3      cnrom, switchable chr
+//   4      mmc3, in place of runes' so its irq goes through irq.rs
+//   7      axrom, 32k prg and one screen mirroring
+//   9, 10  mmc2 and mmc4, chr that switches itself when the ppu fetches certain tiles (punch-out, fire emblem)
+//   11     color dreams
+//   31     nsf style 4k banks, which the nsf player uses too
+//   66     gxrom
+//   21-23, 25  konami's vrc2 and vrc4
+//   24, 26     konami's vrc6, without its extra sound channels
+//   20     the famicom disk system's ram adapter, with the bios in place of prg, see emulation/fds.rs
+//
+// they all keep their bank registers as plain numbers and work out where a read lands when it happens, so a save
+// state only has to hold the registers.
+pub mod axrom;
+pub mod cnrom;
+pub mod color_dreams;
+pub mod fds;
+pub mod fds_audio;
+pub mod gxrom;
+pub mod mmc2;
+pub mod mmc3;
+pub mod nrom;
+pub mod nsf_banks;
+pub mod vrc;
+pub mod vrc6;
+
+extern crate alloc;
+
+use alloc::rc::Rc;
+use runes::cartridge::{BankType, Cartridge};
+
+use crate::emulation::machine::Machine;
+use fds::Adapter;
+use mmc3::Mmc3Irq;
+use vrc::VrcIrq;
+
+// a cart's irq counter, for the run loop to drive. the counters pull on the IrqLine themselves.
+pub enum CartIrq
+{
+    None,
+    // counts cpu cycles.
+    Vrc(Rc<VrcIrq>),
+    // counts a12 rises, some of which only show up by watching the cpu.
+    Mmc3(Rc<Mmc3Irq>),
+    // the disk system's timer, and its drive and sound channel, which run off the cpu clock too.
+    Fds(Rc<Adapter>),
+}
+
+// a sound channel on the cart that runes' apu doesn't know about, for TerminalAudio to mix in.
+pub trait ExpansionAudio
+{
+    fn sample(&self) -> i16;
+}
+
+impl CartIrq
+{
+    pub fn cpu_cycle(&self)
+    {
+        match self {
+            CartIrq::Vrc(irq) => irq.clock(),
+            CartIrq::Fds(adapter) => adapter.clock(),
+            _ => {}
+        }
+    }
+
+    pub fn audio(&self) -> Option<Rc<dyn ExpansionAudio>>
+    {
+        match self {
+            CartIrq::Fds(adapter) => Some(adapter.clone()),
+            _ => None,
+        }
+    }
+
+    // before every instruction.
+    pub fn observe(&self, machine: &Machine)
+    {
+        if let CartIrq::Mmc3(irq) = self {
+            irq.observe(machine);
+        }
+    }
+}
+
+// the whole of the cart's prg, chr and sram, borrowed for as long as the cart lives. same trick as SimpleCart::get_bank,
+// the buffers don't move when the cart does.
+pub struct Memory
+{
+    prg: &'static [u8],
+    chr: &'static mut [u8],
+    sram: &'static mut [u8],
+}
+
+// where a read of addr lands in a bank of size bytes, wrapping round when the bank number is past the end, the
+// way the unconnected high bank lines do on a real board.
+fn index(len: usize, size: usize, bank: usize, addr: u16) -> usize
+{
+    (bank * size + (addr as usize & (size - 1))) % len
+}
+
+impl Memory
+{
+    pub fn new<C: Cartridge>(cart: &mut C) -> Memory
+    {
+        let (prg_len, chr_len, sram_len) =
+            (cart.get_size(BankType::PrgRom), cart.get_size(BankType::ChrRom), cart.get_size(BankType::Sram));
+        Memory {
+            prg: cart.get_bank(0, prg_len, BankType::PrgRom),
+            chr: cart.get_bank_mut(0, chr_len, BankType::ChrRom),
+            sram: cart.get_bank_mut(0, sram_len, BankType::Sram),
+        }
+    }
+
+    pub fn prg(&self, size: usize, bank: usize, addr: u16) -> u8
+    {
+        self.prg[index(self.prg.len(), size, bank, addr)]
+    }
+
+    // the number of size sized banks, for the ones fixed to the end.
+    pub fn prg_banks(&self, size: usize) -> usize
+    {
+        (self.prg.len() / size).max(1)
+    }
+
+    pub fn chr(&self, size: usize, bank: usize, addr: u16) -> u8
+    {
+        self.chr[index(self.chr.len(), size, bank, addr)]
+    }
+
+    // chr ram carts need this, and chr rom ones never try it.
+    pub fn write_chr(&mut self, size: usize, bank: usize, addr: u16, data: u8)
+    {
+        let i = index(self.chr.len(), size, bank, addr);
+        self.chr[i] = data;
+    }
+
+    pub fn sram(&self, addr: u16) -> u8
+    {
+        match self.sram.len() {
+            0 => 0,
+            len => self.sram[(addr as usize - 0x6000) % len],
+        }
+    }
+
+    pub fn write_sram(&mut self, addr: u16, data: u8)
+    {
+        let len = self.sram.len();
+        if len > 0 {
+            self.sram[(addr as usize - 0x6000) % len] = data;
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test_cart
+{
+    extern crate alloc;
+
+    use alloc::vec::Vec;
+    use runes::cartridge::MirrorType;
+
+    use crate::emulation::SimpleCart;
+
+    // every 1k of chr and every 8k of prg filled with its own bank number, so a read says which bank it came from.
+    pub fn cart(prg_8k_banks: usize, chr_1k_banks: usize) -> SimpleCart
+    {
+        let prg: Vec<u8> = (0..prg_8k_banks * 0x2000).map(|i| (i / 0x2000) as u8).collect();
+        let chr: Vec<u8> = (0..chr_1k_banks * 0x400).map(|i| (i / 0x400) as u8).collect();
+        SimpleCart::new(chr, true, prg, alloc::vec![0; 0x2000], MirrorType::Horizontal)
+    }
+}
@@ -0,0 +1,82 @@
+// mapper 0. no registers at all: 16k of prg shows up at both $8000 and $C000, 32k fills the whole window, and writes
+// to rom go nowhere. only a few boards (family basic) have 8k of ram at $6000, but ines 1.0 headers can't say a board
+// doesn't, and test roms report through it, so it's there unless a nes 2.0 header says otherwise.
+use runes::cartridge::Cartridge;
+use runes::mapper::Mapper;
+use runes::utils;
+
+use crate::emulation::mappers::Memory;
+
+pub struct Nrom<C: Cartridge>
+{
+    cart: C,
+    mem: Memory,
+    prg_ram: bool,
+}
+
+impl<C: Cartridge> Nrom<C>
+{
+    pub fn new(mut cart: C, prg_ram: bool) -> Nrom<C>
+    {
+        let mem = Memory::new(&mut cart);
+        Nrom { cart, mem, prg_ram }
+    }
+}
+
+impl<C: Cartridge> Mapper for Nrom<C>
+{
+    fn read(&self, addr: u16) -> u8
+    {
+        match addr {
+            0x0000..=0x1FFF => self.mem.chr(0x2000, 0, addr),
+            0x6000..=0x7FFF if self.prg_ram => self.mem.sram(addr),
+            0x8000..=0xFFFF => self.mem.prg(0x8000, 0, addr),
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8)
+    {
+        match addr {
+            // chr ram, for the homebrew that uses it. chr rom games never write here.
+            0x0000..=0x1FFF => self.mem.write_chr(0x2000, 0, addr, data),
+            0x6000..=0x7FFF if self.prg_ram => self.mem.write_sram(addr, data),
+            _ => {}
+        }
+    }
+
+    fn get_cart(&self) -> &dyn Cartridge
+    {
+        &self.cart
+    }
+
+    fn load(&mut self, reader: &mut dyn utils::Read) -> bool
+    {
+        self.cart.load(reader)
+    }
+
+    fn save(&self, writer: &mut dyn utils::Write) -> bool
+    {
+        self.cart.save(writer)
+    }
+}
+
+#[test_case]
+fn test_nrom_mirroring()
+{
+    let mut m = Nrom::new(crate::emulation::mappers::test_cart::cart(2, 8), false);
+    assert_eq!(m.read(0x8000), 0);
+    assert_eq!(m.read(0xBFFF), 1);
+    assert_eq!(m.read(0xC000), 0);
+    assert_eq!(m.read(0xFFFF), 1);
+    // no bank switching, and no ram when the header says there's none.
+    m.write(0x8000, 1);
+    assert_eq!(m.read(0xC000), 0);
+    m.write(0x6000, 0x55);
+    assert_eq!(m.read(0x6000), 0);
+
+    let mut m = Nrom::new(crate::emulation::mappers::test_cart::cart(4, 8), true);
+    assert_eq!(m.read(0xC000), 2);
+    m.write(0x6000, 0x55);
+    assert_eq!(m.read(0x6000), 0x55);
+}
@@ -0,0 +1,82 @@
+// mapper 31, the board homebrew made out of the nsf format's bankswitching: eight 4k prg banks at $8000-$FFFF, set
+// by writing $5FF8-$5FFF, and 8k of chr ram. the nsf player (emulation/nsf.rs) runs tunes on it too, with ram at $6000.
+//
+// nothing on the real board answers at $5FF0, so we put a jmp to itself there for the nsf player to park the cpu on
+// between calls into the tune.
+use runes::cartridge::Cartridge;
+use runes::mapper::Mapper;
+use runes::utils;
+
+use crate::emulation::mappers::Memory;
+
+pub const IDLE: u16 = 0x5FF0;
+const IDLE_LOOP: [u8; 3] = [0x4C, IDLE as u8, (IDLE >> 8) as u8];
+
+pub struct NsfBanks<C: Cartridge>
+{
+    cart: C,
+    mem: Memory,
+    banks: [u8; 8],
+}
+
+impl<C: Cartridge> NsfBanks<C>
+{
+    pub fn new(mut cart: C) -> NsfBanks<C>
+    {
+        let mem = Memory::new(&mut cart);
+        // only the last bank is set at power on, so the reset vector is in the last 4k.
+        NsfBanks { cart, mem, banks: [0, 0, 0, 0, 0, 0, 0, 0xFF] }
+    }
+}
+
+impl<C: Cartridge> Mapper for NsfBanks<C>
+{
+    fn read(&self, addr: u16) -> u8
+    {
+        match addr {
+            0x0000..=0x1FFF => self.mem.chr(0x2000, 0, addr),
+            0x5FF0..=0x5FF2 => IDLE_LOOP[(addr - IDLE) as usize],
+            0x6000..=0x7FFF => self.mem.sram(addr),
+            0x8000..=0xFFFF => self.mem.prg(0x1000, self.banks[((addr - 0x8000) >> 12) as usize] as usize, addr),
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8)
+    {
+        match addr {
+            0x0000..=0x1FFF => self.mem.write_chr(0x2000, 0, addr, data),
+            0x5FF8..=0x5FFF => self.banks[(addr - 0x5FF8) as usize] = data,
+            0x6000..=0x7FFF => self.mem.write_sram(addr, data),
+            _ => {}
+        }
+    }
+
+    fn get_cart(&self) -> &dyn Cartridge
+    {
+        &self.cart
+    }
+
+    fn load(&mut self, reader: &mut dyn utils::Read) -> bool
+    {
+        self.cart.load(reader) && utils::load_prefix(&mut self.banks, 0, reader)
+    }
+
+    fn save(&self, writer: &mut dyn utils::Write) -> bool
+    {
+        self.cart.save(writer) && utils::save_prefix(&self.banks, 0, writer)
+    }
+}
+
+#[test_case]
+fn test_nsf_banks()
+{
+    // 16 banks of 4k, each 8k test bank is two of them.
+    let mut m = NsfBanks::new(crate::emulation::mappers::test_cart::cart(8, 8));
+    assert_eq!(m.read(0xF000), 7);
+    m.write(0x5FF8, 5);
+    assert_eq!(m.read(0x8000), 2);
+    m.write(0x5FFA, 6);
+    assert_eq!(m.read(0xA000), 3);
+    assert_eq!(m.read(IDLE), 0x4C);
+}
@@ -0,0 +1,398 @@
+// konami's vrc2 and vrc4, mappers 21, 22, 23 and 25, plus the irq counter the vrc4 shares with the vrc6.
+// two switchable 8k prg banks, eight 1k chr banks each set a nibble at a time, and mirroring. the vrc4 adds a mode
+// swapping which end the first prg bank sits at, and a counter that can fire an irq every so many cycles or
+// scanlines.
+// every board wired the chip's two register select pins to different cpu address lines, which is the only
+// difference between the mapper numbers. the vrc2 and vrc4 boards sharing a number never overlap, so each number
+// listens on both boards' lines, unless a nes 2.0 submapper says which board it is.
+// that isn't enough for 23 and 25 though, where a vrc2 board shares its lines with a vrc4 one and the two chips
+// disagree about $9002: mirroring on the vrc2, the prg mode on the vrc4. without a submapper the chip starts out
+// Either, and settles on the vrc4 the first time the game does something only a vrc4 can: touches the irq, asks for
+// single screen mirroring, or sets the prg swap bit. until then $9002 sets the mirroring, which is all a vrc2 game
+// ever means by it.
+extern crate alloc;
+
+use alloc::rc::Rc;
+use core::cell::Cell;
+use runes::cartridge::{Cartridge, MirrorType};
+use runes::mapper::Mapper;
+use runes::utils;
+
+use crate::emulation::irq::{IrqLine, Source};
+use crate::emulation::mappers::Memory;
+
+// folds an address down to $x000-$x003 by the two lines a board uses for register select.
+pub fn register(addr: u16, lines: [u16; 2]) -> u16
+{
+    let bit = |line: u16| if addr & line != 0 { 1 } else { 0 };
+    (addr & 0xF000) | bit(lines[0]) | bit(lines[1]) << 1
+}
+
+// the counter counts up from the latch and fires when it wraps. it either counts cpu cycles, or "scanlines" of 341
+// ppu dots measured off the cpu clock, which keeps going through vblank unlike the mmc3's.
+pub struct VrcIrq
+{
+    latch: Cell<u8>,
+    counter: Cell<u8>,
+    prescaler: Cell<i16>,
+    // bit 0 turns it back on after an acknowledge, bit 1 on now, bit 2 counts cycles instead of scanlines.
+    control: Cell<u8>,
+    line: Rc<IrqLine>,
+}
+
+const PRESCALER_RELOAD: i16 = 341;
+
+impl VrcIrq
+{
+    pub fn new(line: Rc<IrqLine>) -> Rc<VrcIrq>
+    {
+        Rc::new(VrcIrq {
+            latch: Cell::new(0),
+            counter: Cell::new(0),
+            prescaler: Cell::new(PRESCALER_RELOAD),
+            control: Cell::new(0),
+            line,
+        })
+    }
+
+    pub fn write_latch(&self, data: u8)
+    {
+        self.latch.set(data);
+    }
+
+    // the vrc4 only has room for a nibble per register.
+    pub fn write_latch_nibble(&self, high: bool, data: u8)
+    {
+        let latch = self.latch.get();
+        self.latch.set(if high { (latch & 0x0F) | (data << 4) } else { (latch & 0xF0) | (data & 0x0F) });
+    }
+
+    pub fn write_control(&self, data: u8)
+    {
+        self.control.set(data & 0x07);
+        self.line.set(Source::Mapper, false);
+        if data & 0x02 != 0 {
+            self.counter.set(self.latch.get());
+            self.prescaler.set(PRESCALER_RELOAD);
+        }
+    }
+
+    pub fn acknowledge(&self)
+    {
+        self.line.set(Source::Mapper, false);
+        let control = self.control.get();
+        self.control.set((control & !0x02) | ((control & 0x01) << 1));
+    }
+
+    // once every cpu cycle.
+    pub fn clock(&self)
+    {
+        let control = self.control.get();
+        if control & 0x02 == 0 {
+            return;
+        }
+        if control & 0x04 == 0 {
+            // three ppu dots a cycle.
+            let prescaler = self.prescaler.get() - 3;
+            if prescaler > 0 {
+                self.prescaler.set(prescaler);
+                return;
+            }
+            self.prescaler.set(prescaler + PRESCALER_RELOAD);
+        }
+        match self.counter.get() {
+            0xFF => {
+                self.counter.set(self.latch.get());
+                self.line.set(Source::Mapper, true);
+            }
+            n => self.counter.set(n + 1),
+        }
+    }
+
+    pub fn pending(&self) -> bool
+    {
+        self.line.is_asserted_by(Source::Mapper)
+    }
+
+    pub fn save(&self, writer: &mut dyn utils::Write) -> bool
+    {
+        let prescaler = self.prescaler.get().to_le_bytes();
+        let state = [self.latch.get(), self.counter.get(), prescaler[0], prescaler[1], self.control.get(), self.pending() as u8];
+        writer.write(&state) == Some(state.len())
+    }
+
+    pub fn load(&self, reader: &mut dyn utils::Read) -> bool
+    {
+        let mut state = [0u8; 6];
+        if reader.read(&mut state) != Some(state.len()) {
+            return false;
+        }
+        self.latch.set(state[0]);
+        self.counter.set(state[1]);
+        self.prescaler.set(i16::from_le_bytes([state[2], state[3]]));
+        self.control.set(state[4]);
+        self.line.set(Source::Mapper, state[5] != 0);
+        true
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chip
+{
+    Vrc2,
+    Vrc4,
+    // 23 or 25 without a submapper, until the game shows which.
+    Either,
+}
+
+#[repr(C)]
+struct Registers
+{
+    prg: [u8; 2],
+    // bit 1 puts the first prg bank at $C000 and the second to last bank at $8000.
+    prg_mode: u8,
+    mirror: u8,
+    chr: [u16; 8],
+}
+
+pub struct Vrc<C: Cartridge>
+{
+    cart: C,
+    mem: Memory,
+    chip: Chip,
+    lines: [u16; 2],
+    // the vrc2a board leaves the chr bank's low bit unconnected.
+    chr_shift: u8,
+    regs: Registers,
+    irq: Rc<VrcIrq>,
+}
+
+impl<C: Cartridge> Vrc<C>
+{
+    // None for a mapper number that isn't a vrc2 or vrc4.
+    pub fn new(mut cart: C, mapper_id: u8, submapper: u8, irq: Rc<VrcIrq>) -> Option<Vrc<C>>
+    {
+        let (chip, lines, chr_shift) = match (mapper_id, submapper) {
+            (21, 1) => (Chip::Vrc4, [0x02, 0x04], 0),
+            (21, 2) => (Chip::Vrc4, [0x40, 0x80], 0),
+            (21, _) => (Chip::Vrc4, [0x02 | 0x40, 0x04 | 0x80], 0),
+            (22, _) => (Chip::Vrc2, [0x02, 0x01], 1),
+            (23, 1) => (Chip::Vrc4, [0x01, 0x02], 0),
+            (23, 2) => (Chip::Vrc4, [0x04, 0x08], 0),
+            (23, 3) => (Chip::Vrc2, [0x01, 0x02], 0),
+            (23, _) => (Chip::Either, [0x01 | 0x04, 0x02 | 0x08], 0),
+            (25, 1) => (Chip::Vrc4, [0x02, 0x01], 0),
+            (25, 2) => (Chip::Vrc4, [0x08, 0x04], 0),
+            (25, 3) => (Chip::Vrc2, [0x02, 0x01], 0),
+            (25, _) => (Chip::Either, [0x02 | 0x08, 0x01 | 0x04], 0),
+            _ => return None,
+        };
+        let mem = Memory::new(&mut cart);
+        let regs = Registers { prg: [0, 1], prg_mode: 0, mirror: 0, chr: [0; 8] };
+        Some(Vrc { cart, mem, chip, lines, chr_shift, regs, irq })
+    }
+
+    fn set_mirror(&mut self, data: u8)
+    {
+        let data = if self.chip == Chip::Vrc2 { data & 0x01 } else { data & 0x03 };
+        if data > 1 {
+            self.settle_vrc4();
+        }
+        self.regs.mirror = data;
+        self.cart.set_mirror_type(match data {
+            0 => MirrorType::Vertical,
+            1 => MirrorType::Horizontal,
+            2 => MirrorType::Single0,
+            _ => MirrorType::Single1,
+        });
+    }
+
+    // for savestates, so a game that's shown it's a vrc4 doesn't go back to guessing after a load.
+    fn chip_from(byte: u8) -> Chip
+    {
+        match byte {
+            0 => Chip::Vrc2,
+            1 => Chip::Vrc4,
+            _ => Chip::Either,
+        }
+    }
+
+    fn settle_vrc4(&mut self)
+    {
+        if self.chip == Chip::Either {
+            self.chip = Chip::Vrc4;
+        }
+    }
+
+    fn write_chr_bank(&mut self, reg: u16, data: u8)
+    {
+        // $B000-$E003, two banks to each $x000 and a nibble to each register.
+        let bank = (((reg >> 12) - 0xB) * 2 + ((reg >> 1) & 1)) as usize;
+        let chr = self.regs.chr[bank];
+        self.regs.chr[bank] = if reg & 1 == 0 {
+            (chr & 0x1F0) | (data as u16 & 0x0F)
+        } else {
+            (chr & 0x00F) | ((data as u16 & 0x1F) << 4)
+        };
+    }
+
+    fn prg_bank(&self, addr: u16) -> usize
+    {
+        let second_last = self.mem.prg_banks(0x2000).saturating_sub(2);
+        let swapped = self.chip != Chip::Vrc2 && self.regs.prg_mode & 0x02 != 0;
+        match (addr, swapped) {
+            (0x8000..=0x9FFF, false) | (0xC000..=0xDFFF, true) => self.regs.prg[0] as usize,
+            (0x8000..=0x9FFF, true) | (0xC000..=0xDFFF, false) => second_last,
+            (0xA000..=0xBFFF, _) => self.regs.prg[1] as usize,
+            _ => second_last + 1,
+        }
+    }
+}
+
+impl<C: Cartridge> Mapper for Vrc<C>
+{
+    fn read(&self, addr: u16) -> u8
+    {
+        match addr {
+            0x0000..=0x1FFF => {
+                let bank = self.regs.chr[(addr >> 10) as usize] >> self.chr_shift;
+                self.mem.chr(0x400, bank as usize, addr)
+            }
+            0x6000..=0x7FFF => self.mem.sram(addr),
+            0x8000..=0xFFFF => self.mem.prg(0x2000, self.prg_bank(addr), addr),
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8)
+    {
+        if addr < 0x2000 {
+            let bank = self.regs.chr[(addr >> 10) as usize] >> self.chr_shift;
+            self.mem.write_chr(0x400, bank as usize, addr, data);
+            return;
+        }
+        if addr < 0x8000 {
+            if addr >= 0x6000 {
+                self.mem.write_sram(addr, data);
+            }
+            return;
+        }
+        let reg = register(addr, self.lines);
+        match reg {
+            0x8000..=0x8003 => self.regs.prg[0] = data & 0x1F,
+            0x9000..=0x9001 => self.set_mirror(data),
+            0x9002..=0x9003 if self.chip == Chip::Either && data & 0x02 != 0 => {
+                self.settle_vrc4();
+                self.regs.prg_mode = data;
+            }
+            0x9002..=0x9003 if self.chip == Chip::Vrc4 => self.regs.prg_mode = data,
+            0x9002..=0x9003 => self.set_mirror(data),
+            0xA000..=0xA003 => self.regs.prg[1] = data & 0x1F,
+            0xB000..=0xE003 => self.write_chr_bank(reg, data),
+            0xF000..=0xF003 if self.chip != Chip::Vrc2 => {
+                self.settle_vrc4();
+                match reg & 0x03 {
+                    0 => self.irq.write_latch_nibble(false, data),
+                    1 => self.irq.write_latch_nibble(true, data),
+                    2 => self.irq.write_control(data),
+                    _ => self.irq.acknowledge(),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn get_cart(&self) -> &dyn Cartridge
+    {
+        &self.cart
+    }
+
+    fn load(&mut self, reader: &mut dyn utils::Read) -> bool
+    {
+        let mut chip = [0u8; 1];
+        if !(self.cart.load(reader) && utils::load_prefix(&mut self.regs, 0, reader)) ||
+            reader.read(&mut chip) != Some(1)
+        {
+            return false;
+        }
+        self.chip = Self::chip_from(chip[0]);
+        self.irq.load(reader)
+    }
+
+    fn save(&self, writer: &mut dyn utils::Write) -> bool
+    {
+        self.cart.save(writer) &&
+            utils::save_prefix(&self.regs, 0, writer) &&
+            writer.write(&[self.chip as u8]) == Some(1) &&
+            self.irq.save(writer)
+    }
+}
+
+#[test_case]
+fn test_vrc4_banks_and_irq()
+{
+    // mapper 21 as vrc4c, registers on a6 and a7.
+    let irq = VrcIrq::new(IrqLine::new());
+    let mut m = Vrc::new(crate::emulation::mappers::test_cart::cart(16, 256), 21, 0, irq.clone()).unwrap();
+    m.write(0x8000, 3);
+    m.write(0xA000, 4);
+    assert_eq!(m.read(0x8000), 3);
+    assert_eq!(m.read(0xA000), 4);
+    assert_eq!(m.read(0xC000), 14);
+    m.write(0x9080, 0x02);
+    assert_eq!(m.read(0x8000), 14);
+    assert_eq!(m.read(0xC000), 3);
+
+    // chr bank 3 is $C002 low nibble and $C003 high, which 21 puts at $C080 and $C0C0.
+    m.write(0xC080, 0x05);
+    m.write(0xC0C0, 0x01);
+    assert_eq!(m.read(0x0C00), 0x15);
+
+    // cycle mode, fires after 256 - latch cycles.
+    m.write(0xF000, 0x0C);
+    m.write(0xF040, 0x0F);
+    m.write(0xF080, 0x06);
+    for _ in 0..3
+    {
+        irq.clock();
+    }
+    assert!(!irq.pending());
+    irq.clock();
+    assert!(irq.pending());
+    m.write(0xF0C0, 0);
+    assert!(!irq.pending());
+}
+
+#[test_case]
+fn test_vrc2_or_vrc4()
+{
+    // mapper 23 with no submapper, as a vrc2b game would use it: $9002 is mirroring.
+    let cart = || crate::emulation::mappers::test_cart::cart(16, 256);
+    let mut m = Vrc::new(cart(), 23, 0, VrcIrq::new(IrqLine::new())).unwrap();
+    m.write(0x8000, 3);
+    m.write(0x9002, 0x01);
+    assert_eq!(m.regs.mirror, 1);
+    assert_eq!(m.read(0x8000), 3);
+    assert_eq!(m.chip, Chip::Either);
+
+    // and as a vrc4e game would: the swap bit gives it away.
+    let mut m = Vrc::new(cart(), 23, 0, VrcIrq::new(IrqLine::new())).unwrap();
+    m.write(0x8000, 3);
+    m.write(0x9008, 0x02);
+    assert_eq!(m.chip, Chip::Vrc4);
+    assert_eq!(m.read(0xC000), 3);
+    m.write(0x9000, 0x01);
+    m.write(0x9008, 0x00);
+    assert_eq!(m.regs.mirror, 1);
+    assert_eq!(m.read(0x8000), 3);
+
+    // a savestate remembers what the game showed it was.
+    m.write(0x9008, 0x02);
+    use crate::emulation::machine::{SliceReader, VecWriter};
+    let mut state = VecWriter(alloc::vec::Vec::new());
+    assert!(m.save(&mut state));
+    let mut loaded = Vrc::new(cart(), 23, 0, VrcIrq::new(IrqLine::new())).unwrap();
+    assert!(loaded.load(&mut SliceReader::new(&state.0)));
+    assert_eq!(loaded.chip, Chip::Vrc4);
+    assert_eq!(loaded.read(0xC000), 3);
+}
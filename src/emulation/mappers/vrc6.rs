@@ -0,0 +1,136 @@
+// konami's vrc6, mappers 24 (akumajou densetsu) and 26 (madara, esper dream 2), which only differ in swapping the two
+// register select lines.
+// a 16k prg bank at $8000, an 8k one at $C000 and the last 8k fixed at $E000, eight 1k chr banks and the same irq
+// counter as the vrc4. the chip also has two pulse channels and a saw that we don't play, so those games are missing
+// some of their music.
+// only the chr banking mode everything shipped with is done, $B003 = $20 and friends, where the eight banks are 1k
+// each and bits 2-3 are the mirroring.
+extern crate alloc;
+
+use alloc::rc::Rc;
+use runes::cartridge::{Cartridge, MirrorType};
+use runes::mapper::Mapper;
+use runes::utils;
+
+use crate::emulation::mappers::vrc::{register, VrcIrq};
+use crate::emulation::mappers::Memory;
+
+#[repr(C)]
+struct Registers
+{
+    prg_16k: u8,
+    prg_8k: u8,
+    control: u8,
+    chr: [u8; 8],
+}
+
+pub struct Vrc6<C: Cartridge>
+{
+    cart: C,
+    mem: Memory,
+    lines: [u16; 2],
+    regs: Registers,
+    irq: Rc<VrcIrq>,
+}
+
+impl<C: Cartridge> Vrc6<C>
+{
+    // None for a mapper number that isn't a vrc6.
+    pub fn new(mut cart: C, mapper_id: u8, irq: Rc<VrcIrq>) -> Option<Vrc6<C>>
+    {
+        let lines = match mapper_id {
+            24 => [0x01, 0x02],
+            26 => [0x02, 0x01],
+            _ => return None,
+        };
+        let mem = Memory::new(&mut cart);
+        let regs = Registers { prg_16k: 0, prg_8k: 0, control: 0, chr: [0; 8] };
+        Some(Vrc6 { cart, mem, lines, regs, irq })
+    }
+
+    fn set_control(&mut self, data: u8)
+    {
+        self.regs.control = data;
+        self.cart.set_mirror_type(match (data >> 2) & 0x03 {
+            0 => MirrorType::Vertical,
+            1 => MirrorType::Horizontal,
+            2 => MirrorType::Single0,
+            _ => MirrorType::Single1,
+        });
+    }
+}
+
+impl<C: Cartridge> Mapper for Vrc6<C>
+{
+    fn read(&self, addr: u16) -> u8
+    {
+        match addr {
+            0x0000..=0x1FFF => self.mem.chr(0x400, self.regs.chr[(addr >> 10) as usize] as usize, addr),
+            0x6000..=0x7FFF => self.mem.sram(addr),
+            0x8000..=0xBFFF => self.mem.prg(0x4000, self.regs.prg_16k as usize, addr),
+            0xC000..=0xDFFF => self.mem.prg(0x2000, self.regs.prg_8k as usize, addr),
+            0xE000..=0xFFFF => self.mem.prg(0x2000, self.mem.prg_banks(0x2000) - 1, addr),
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8)
+    {
+        if addr < 0x2000 {
+            let bank = self.regs.chr[(addr >> 10) as usize] as usize;
+            self.mem.write_chr(0x400, bank, addr, data);
+            return;
+        }
+        if addr < 0x8000 {
+            if addr >= 0x6000 {
+                self.mem.write_sram(addr, data);
+            }
+            return;
+        }
+        let reg = register(addr, self.lines);
+        match reg {
+            0x8000..=0x8003 => self.regs.prg_16k = data & 0x0F,
+            0xB003 => self.set_control(data),
+            0xC000..=0xC003 => self.regs.prg_8k = data & 0x1F,
+            0xD000..=0xE003 => self.regs.chr[(((reg >> 12) - 0xD) * 4 + (reg & 0x03)) as usize] = data,
+            0xF000 => self.irq.write_latch(data),
+            0xF001 => self.irq.write_control(data),
+            0xF002 => self.irq.acknowledge(),
+            // $9000-$B002 are the sound channels.
+            _ => {}
+        }
+    }
+
+    fn get_cart(&self) -> &dyn Cartridge
+    {
+        &self.cart
+    }
+
+    fn load(&mut self, reader: &mut dyn utils::Read) -> bool
+    {
+        self.cart.load(reader) && utils::load_prefix(&mut self.regs, 0, reader) && self.irq.load(reader)
+    }
+
+    fn save(&self, writer: &mut dyn utils::Write) -> bool
+    {
+        self.cart.save(writer) && utils::save_prefix(&self.regs, 0, writer) && self.irq.save(writer)
+    }
+}
+
+#[test_case]
+fn test_vrc6_banks()
+{
+    // 26 has a0 and a1 swapped, so $x001 is register 2.
+    let irq = VrcIrq::new(crate::emulation::irq::IrqLine::new());
+    let mut m = Vrc6::new(crate::emulation::mappers::test_cart::cart(32, 256), 26, irq).unwrap();
+    m.write(0x8000, 2);
+    m.write(0xC000, 9);
+    assert_eq!(m.read(0x8000), 4);
+    assert_eq!(m.read(0xA000), 5);
+    assert_eq!(m.read(0xC000), 9);
+    assert_eq!(m.read(0xE000), 31);
+    m.write(0xE001, 0x42);
+    assert_eq!(m.read(0x1800), 0x42);
+    m.write(0xB003, 0x24);
+    assert_eq!(m.get_cart().get_mirror_type() as u8, MirrorType::Horizontal as u8);
+}
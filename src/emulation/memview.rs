@@ -0,0 +1,281 @@
+// a live hex view of cpu ram and cart sram in the side panel, with a cheat search for finding things like the
+// life counter, and freezing whatever it finds.
+//   F8              show or hide it
+//   Page Up/Down    move through $0000-$07FF, then $6000-$7FFF
+//   0               start a search: remember every byte
+//   1 2 3 4         keep the bytes that are equal to / changed from / greater than / less than what they were
+//   Tab             pick the next candidate
+//   5               freeze the picked candidate at its value now, or unfreeze it
+//   6               unfreeze everything
+// the usual way to find lives: 0, die, 4, die, 4, and whatever's left is it.
+// freezes keep getting written every frame whether the panel's up or not.
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use pc_keyboard::KeyCode;
+use vga::colors::Color16;
+
+use crate::emulation::machine::Machine;
+use crate::emulation::panel::{self, Panel, TEXT_ROWS};
+
+const RAM_SIZE: u16 = 0x0800;
+const SRAM_START: u16 = 0x6000;
+const SRAM_SIZE: u16 = 0x2000;
+
+const BYTES_PER_ROW: u16 = 16;
+const HEX_ROWS: u16 = 32;
+const PAGE_SIZE: u16 = BYTES_PER_ROW * HEX_ROWS;
+const RAM_PAGES: u16 = RAM_SIZE / PAGE_SIZE;
+const PAGES: u16 = RAM_PAGES + SRAM_SIZE / PAGE_SIZE;
+
+// candidates listed under the hex, and the most that get listed.
+const LIST_ROW: usize = HEX_ROWS as usize + 2;
+const LISTED: usize = 6;
+
+const REFRESH_FRAMES: u32 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter
+{
+    Equal,
+    Changed,
+    Greater,
+    Less,
+}
+
+impl Filter
+{
+    fn keep(self, before: u8, now: u8) -> bool
+    {
+        match self {
+            Filter::Equal => now == before,
+            Filter::Changed => now != before,
+            Filter::Greater => now > before,
+            Filter::Less => now < before,
+        }
+    }
+}
+
+// every searchable address, ram first.
+fn searchable() -> impl Iterator<Item = u16>
+{
+    (0..RAM_SIZE).chain(SRAM_START..SRAM_START + SRAM_SIZE)
+}
+
+pub struct CheatSearch
+{
+    // (address, value when last looked at)
+    candidates: Vec<(u16, u8)>,
+    searching: bool,
+}
+
+impl CheatSearch
+{
+    pub fn new() -> CheatSearch
+    {
+        CheatSearch { candidates: Vec::new(), searching: false }
+    }
+
+    pub fn start(&mut self, machine: &Machine)
+    {
+        self.candidates = searchable().map(|addr| (addr, machine.peek(addr))).collect();
+        self.searching = true;
+    }
+
+    pub fn filter(&mut self, machine: &Machine, filter: Filter)
+    {
+        if !self.searching {
+            self.start(machine);
+            return;
+        }
+        self.candidates.retain(|&(addr, before)| filter.keep(before, machine.peek(addr)));
+        for candidate in self.candidates.iter_mut()
+        {
+            candidate.1 = machine.peek(candidate.0);
+        }
+    }
+
+    pub fn candidates(&self) -> &[(u16, u8)]
+    {
+        &self.candidates
+    }
+}
+
+pub struct MemoryViewer
+{
+    open: bool,
+    page: u16,
+    frames: u32,
+    search: CheatSearch,
+    picked: usize,
+    // (address, value) written back every frame.
+    frozen: Vec<(u16, u8)>,
+    panel: Panel,
+}
+
+fn page_start(page: u16) -> u16
+{
+    if page < RAM_PAGES {
+        page * PAGE_SIZE
+    } else {
+        SRAM_START + (page - RAM_PAGES) * PAGE_SIZE
+    }
+}
+
+impl MemoryViewer
+{
+    pub fn new() -> MemoryViewer
+    {
+        MemoryViewer {
+            open: false,
+            page: 0,
+            frames: 0,
+            search: CheatSearch::new(),
+            picked: 0,
+            frozen: Vec::new(),
+            panel: Panel::new(),
+        }
+    }
+
+    fn showing(&self) -> bool
+    {
+        self.open && self.panel.owned_by(panel::MEMORY_VIEWER)
+    }
+
+    // returns whether the key was one of ours. only F8 is while the panel's down, so the rest stay free.
+    pub fn handle_key(&mut self, key: KeyCode, machine: &Machine) -> bool
+    {
+        if key == KeyCode::F8 {
+            if self.showing() {
+                self.open = false;
+                self.panel.release(panel::MEMORY_VIEWER);
+            } else {
+                self.open = true;
+                self.panel.claim(panel::MEMORY_VIEWER);
+                self.frames = REFRESH_FRAMES;
+            }
+            return true;
+        }
+        if !self.showing() {
+            return false;
+        }
+
+        match key {
+            KeyCode::PageUp => self.page = (self.page + PAGES - 1) % PAGES,
+            KeyCode::PageDown => self.page = (self.page + 1) % PAGES,
+            KeyCode::Key0 => {
+                self.search.start(machine);
+                self.picked = 0;
+            }
+            KeyCode::Key1 => self.search.filter(machine, Filter::Equal),
+            KeyCode::Key2 => self.search.filter(machine, Filter::Changed),
+            KeyCode::Key3 => self.search.filter(machine, Filter::Greater),
+            KeyCode::Key4 => self.search.filter(machine, Filter::Less),
+            KeyCode::Tab => self.picked += 1,
+            KeyCode::Key5 => self.toggle_freeze(machine),
+            KeyCode::Key6 => self.frozen.clear(),
+            _ => return false,
+        }
+        let count = self.search.candidates().len().min(LISTED);
+        if self.picked >= count {
+            self.picked = 0;
+        }
+        // show the result right away.
+        self.frames = REFRESH_FRAMES;
+        true
+    }
+
+    fn toggle_freeze(&mut self, machine: &Machine)
+    {
+        let addr = match self.search.candidates().get(self.picked) {
+            Some(&(addr, _)) => addr,
+            None => return,
+        };
+        match self.frozen.iter().position(|&(a, _)| a == addr) {
+            Some(i) => {
+                self.frozen.remove(i);
+            }
+            None => self.frozen.push((addr, machine.peek(addr))),
+        }
+    }
+
+    // called once a frame.
+    pub fn frame(&mut self, machine: &Machine)
+    {
+        for &(addr, value) in self.frozen.iter()
+        {
+            machine.poke(addr, value);
+        }
+
+        if !self.showing() {
+            return;
+        }
+        self.frames += 1;
+        if self.frames < REFRESH_FRAMES {
+            return;
+        }
+        self.frames = 0;
+        self.draw(machine);
+    }
+
+    fn draw(&mut self, machine: &Machine)
+    {
+        let start = page_start(self.page);
+        let title = format!("MEMORY ${:04X}-${:04X}", start, start + PAGE_SIZE - 1);
+        self.panel.text(0, &title, Color16::White);
+
+        for row in 0..HEX_ROWS
+        {
+            let addr = start + row * BYTES_PER_ROW;
+            let mut line = format!("{:04X} ", addr);
+            for i in 0..BYTES_PER_ROW
+            {
+                if i % 4 == 0 {
+                    line.push(' ');
+                }
+                line.push_str(&format!("{:02X}", machine.peek(addr + i)));
+            }
+            self.panel.text(1 + row as usize, &line, Color16::LightGrey);
+        }
+
+        let candidates = self.search.candidates();
+        let status = if self.search.searching {
+            format!("SEARCH: {} CANDIDATES", candidates.len())
+        } else {
+            String::from("SEARCH: PRESS 0 TO START")
+        };
+        self.panel.text(LIST_ROW, &status, Color16::White);
+        for i in 0..LISTED
+        {
+            let (line, color) = match candidates.get(i) {
+                Some(&(addr, before)) => {
+                    let marker = if i == self.picked { '>' } else { ' ' };
+                    let frozen = if self.frozen.iter().any(|&(a, _)| a == addr) { " FROZEN" } else { "" };
+                    let line = format!("{}${:04X} {:02X} NOW {:02X}{}", marker, addr, before, machine.peek(addr), frozen);
+                    (line, if i == self.picked { Color16::Yellow } else { Color16::LightGrey })
+                }
+                None => (String::new(), Color16::LightGrey),
+            };
+            self.panel.text(LIST_ROW + 1 + i, &line, color);
+        }
+
+        let mut frozen = String::from("FROZEN:");
+        for &(addr, value) in self.frozen.iter().take(6)
+        {
+            frozen.push_str(&format!(" {:04X}={:02X}", addr, value));
+        }
+        self.panel.text(TEXT_ROWS - 1, &frozen, Color16::LightCyan);
+    }
+}
+
+#[test_case]
+fn test_cheat_filters()
+{
+    assert!(Filter::Less.keep(3, 2));
+    assert!(!Filter::Less.keep(3, 3));
+    assert!(Filter::Changed.keep(3, 4));
+    assert!(Filter::Equal.keep(9, 9));
+    assert_eq!(page_start(RAM_PAGES), SRAM_START);
+}
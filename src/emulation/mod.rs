@@ -1,26 +1,55 @@
+pub mod capture;
+pub mod cheats;
 pub mod construct;
+pub mod crc32;
+pub mod debugger;
+pub mod disasm;
+pub mod fds;
+pub mod fourscore;
+pub mod harness;
+pub mod irq;
+pub mod machine;
+pub mod mappers;
+pub mod memview;
+pub mod movie;
+pub mod nsf;
+pub mod panel;
+pub mod ppuview;
+pub mod region;
+pub mod rewind;
+pub mod romdb;
+pub mod savestate;
+pub mod sram;
+pub mod trace;
+pub mod zapper;
 
 extern crate alloc;
 
 use alloc::vec;
+use pc_keyboard::KeyCode;
 use runes::apu::APU;
 use runes::cartridge::{BankType, Cartridge, MirrorType};
-use runes::controller::{stdctl, InputPoller};
+use runes::controller::{stdctl, Controller, InputPoller};
 use runes::mapper;
 use runes::memory::{CPUMemory, PPUMemory};
 use runes::mos6502;
 use runes::ppu;
 use runes::utils;
 
-use alloc::{vec::Vec, boxed::Box};
+use alloc::{vec::Vec, boxed::Box, rc::Rc};
 
 use core::mem::transmute;
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+use spin::Mutex;
 
 use crate::emulation::construct::TerminalKeyboard;
+pub use crate::emulation::region::Region;
+use crate::{cmdline, romdisk, sound};
 use crate::{println, serial_println};
 
 
-const RGB_COLORS: [u32; 64] = [
+pub(crate) const RGB_COLORS: [u32; 64] = [
     0x666666, 0x002a88, 0x1412a7, 0x3b00a4, 0x5c007e, 0x6e0040, 0x6c0600,
     0x561d00, 0x333500, 0x0b4800, 0x005200, 0x004f08, 0x00404d, 0x000000,
     0x000000, 0x000000, 0xadadad, 0x155fd9, 0x4240ff, 0x7527fe, 0xa01acc,
@@ -37,12 +66,16 @@ const PIX_WIDTH: u32 = 256;
 const PIX_HEIGHT: u32 = 240;
 const FB_PITCH: usize = PIX_WIDTH as usize * 3;
 const FB_SIZE: usize = PIX_HEIGHT as usize * FB_PITCH;
-const AUDIO_SAMPLES: u16 = 441;
-const AUDIO_EXTRA_SAMPLES: u16 = 4410;
-const AUDIO_ALL_SAMPLES: u16 = AUDIO_SAMPLES + AUDIO_EXTRA_SAMPLES;
+pub(crate) const AUDIO_SAMPLES: u16 = 441;
+pub(crate) const AUDIO_EXTRA_SAMPLES: u16 = 4410;
+pub(crate) const AUDIO_ALL_SAMPLES: u16 = AUDIO_SAMPLES + AUDIO_EXTRA_SAMPLES;
+// the apu mixes its channels down to one sample at this rate, AUDIO_SAMPLES is 10ms worth.
+pub const APU_SAMPLE_RATE: u32 = AUDIO_SAMPLES as u32 * 100;
 
 pub struct SimpleCart {
     chr_rom: Vec<u8>,
+    // whether chr_rom is really chr ram. only then is it part of the cart's state, rom never changes.
+    chr_ram: bool,
     prg_rom: Vec<u8>,
     sram: Vec<u8>,
     pub mirror_type: MirrorType,
@@ -51,12 +84,14 @@ pub struct SimpleCart {
 impl SimpleCart {
     pub fn new(
         chr_rom: Vec<u8>,
+        chr_ram: bool,
         prg_rom: Vec<u8>,
         sram: Vec<u8>,
         mirror_type: MirrorType,
     ) -> Self {
         SimpleCart {
             chr_rom,
+            chr_ram,
             prg_rom,
             sram,
             mirror_type,
@@ -71,6 +106,12 @@ impl SimpleCart {
         }
     }
 
+    // the sram lives in a heap buffer that doesn't move when the cart does, so this stays valid after the
+    // mapper takes ownership of the cart. same trick as get_bank.
+    pub fn sram_view<'a>(&self) -> &'a [u8] {
+        unsafe { &*(&self.sram[..] as *const [u8]) }
+    }
+
     fn save_vec(vec: &Vec<u8>, writer: &mut dyn utils::Write) -> bool {
         let len = vec.len();
         match writer.write(vec) {
@@ -127,13 +168,13 @@ impl Cartridge for SimpleCart {
 
     fn load(&mut self, reader: &mut dyn utils::Read) -> bool {
         self.load_sram(reader) &&
-            SimpleCart::load_vec(&mut self.chr_rom, reader) &&
+            (!self.chr_ram || SimpleCart::load_vec(&mut self.chr_rom, reader)) &&
             utils::load_prefix(&mut self.mirror_type, 0, reader)
     }
 
     fn save(&self, writer: &mut dyn utils::Write) -> bool {
         self.save_sram(writer) &&
-            SimpleCart::save_vec(&self.chr_rom, writer) &&
+            (!self.chr_ram || SimpleCart::save_vec(&self.chr_rom, writer)) &&
             utils::save_prefix(&self.mirror_type, 0, writer)
     }
 
@@ -146,24 +187,21 @@ impl Cartridge for SimpleCart {
     }
 }
 
-// use the keyname that the pc_keyboard library generates to match against.
-fn keyboard_mapping(key: &str) -> u8 {
-    match key {
-        I => stdctl::UP,
-        K => stdctl::DOWN,
-        J => stdctl::LEFT,
-        L => stdctl::RIGHT,
-        Z => stdctl::A,
-        X => stdctl::B,
-        Return => stdctl::START,
-        S => stdctl::SELECT,
-        Up => stdctl::UP,
-        Down => stdctl::DOWN,
-        Left => stdctl::LEFT,
-        Right => stdctl::RIGHT,
-        _ => 0,
-    }
-}
+// which keyboard keys drive which controller buttons. either ijkl or the arrows steer.
+pub(crate) const KEYBOARD_MAPPING: [(KeyCode, u8); 12] = [
+    (KeyCode::I, stdctl::UP),
+    (KeyCode::K, stdctl::DOWN),
+    (KeyCode::J, stdctl::LEFT),
+    (KeyCode::L, stdctl::RIGHT),
+    (KeyCode::Z, stdctl::A),
+    (KeyCode::X, stdctl::B),
+    (KeyCode::Enter, stdctl::START),
+    (KeyCode::S, stdctl::SELECT),
+    (KeyCode::ArrowUp, stdctl::UP),
+    (KeyCode::ArrowDown, stdctl::DOWN),
+    (KeyCode::ArrowLeft, stdctl::LEFT),
+    (KeyCode::ArrowRight, stdctl::RIGHT),
+];
 
 #[inline(always)]
 fn get_rgb(color: u8) -> (u8, u8, u8) {
@@ -171,22 +209,27 @@ fn get_rgb(color: u8) -> (u8, u8, u8) {
     ((c >> 16) as u8, ((c >> 8) & 0xff) as u8, (c & 0xff) as u8)
 }
 
-struct CircularBuffer {
+pub(crate) struct CircularBuffer {
     buffer: [i16; (AUDIO_ALL_SAMPLES + 1) as usize],
     head: usize,
     tail: usize,
 }
 
 impl CircularBuffer {
-    fn new() -> Self {
+    const fn new() -> Self {
         CircularBuffer {
             buffer: [0; (AUDIO_ALL_SAMPLES + 1) as usize],
-            head: 1,
+            head: 0,
             tail: 0,
         }
     }
 
-    fn enque(&mut self, sample: i16) {
+    // how many samples are waiting to be dequed.
+    pub(crate) fn len(&self) -> usize {
+        (self.tail + self.buffer.len() - self.head) % self.buffer.len()
+    }
+
+    pub(crate) fn enque(&mut self, sample: i16) {
         self.buffer[self.tail] = sample;
         self.tail += 1;
         if self.tail == self.buffer.len() {
@@ -194,7 +237,7 @@ impl CircularBuffer {
         }
     }
 
-    fn deque(&mut self) -> i16 {
+    pub(crate) fn deque(&mut self) -> i16 {
         let res = self.buffer[self.head];
         if self.head != self.tail {
             let mut h = self.head + 1;
@@ -211,53 +254,20 @@ impl CircularBuffer {
     }
 }
 
-// struct AudioSync {
-//     time_barrier: Condvar,
-//     buffer: Mutex<(CircularBuffer, u16)>,
-// }
-
-// struct SDLAudio<'a>(&'a AudioSync);
-// struct SDLAudioPlayback<'a>(&'a AudioSync);
-
-// impl<'a> sdl2::audio::AudioCallback for SDLAudioPlayback<'a> {
-//     type Channel = i16;
-//     fn callback(&mut self, out: &mut [i16]) {
-//         let mut m = self.0.buffer.lock().unwrap();
-//         {
-//             let b = &mut m.0;
-//             /*
-//             let l1 = (b.tail + b.buffer.len() - b.head) % b.buffer.len();
-//             print!("{} ", l1);
-//             */
-//             for x in out.iter_mut() {
-//                 *x = b.deque()
-//             }
-//         }
-//         //println!("{}", m.1);
-//         if m.1 >= AUDIO_SAMPLES {
-//             m.1 -= AUDIO_SAMPLES;
-//             self.0.time_barrier.notify_one();
-//         } else {
-//             //println!("audio frame skipping {}", m.1);
-//             m.1 = 0;
-//         }
-//     }
-// }
-
-//// TODO: make own audio structure, implement this similarly
-// impl<'a> apu::Speaker for SDLAudio<'a> {
-//     fn queue(&mut self, sample: i16) {
-//         let mut m = self.0.buffer.lock().unwrap();
-//         {
-//             let b = &mut m.0;
-//             b.enque(sample);
-//         }
-//         m.1 += 1;
-//         while m.1 >= AUDIO_ALL_SAMPLES {
-//             m = self.0.time_barrier.wait(m).unwrap();
-//         }
-//     }
-// }
+// samples the apu has produced that the sound hardware hasn't played yet.
+// the emulator fills it from Speaker::queue, the sound backend drains it, usually from an interrupt.
+// so, only ever lock it with interrupts off outside of the interrupt handlers.
+pub(crate) static AUDIO_BUFFER: Mutex<CircularBuffer> = Mutex::new(CircularBuffer::new());
+
+// bumped by the screen every time the ppu finishes a frame. the run loop watches it to do its once-a-frame work.
+pub(crate) static FRAME_COUNT: AtomicU64 = AtomicU64::new(0);
+
+// crc32 of everything drawn in the last finished frame.
+pub(crate) static LAST_FRAME_HASH: AtomicU32 = AtomicU32::new(0);
+
+pub fn frame_count() -> u64 {
+    FRAME_COUNT.load(Ordering::Relaxed)
+}
 
 #[repr(C, packed)]
 struct INesHeader {
@@ -272,146 +282,462 @@ struct INesHeader {
     padding: [u8; 5],
 }
 
-// #[allow(dead_code)]
-// fn print_cpu_trace(cpu: &mos6502::CPU) {
-//     let pc = cpu.get_pc();
-//     let mem = cpu.get_mem();
-//     let opcode = mem.read_without_tick(pc) as usize;
-//     let len = mos6502::INST_LENGTH[opcode];
-//     let mut code = vec![0; len as usize];
-//     for i in 0..len as u16 {
-//         code[i as usize] = mem.read_without_tick(pc + i);
-//     }
-//     println!(
-//         "0x{:04x} {} a:{:02x} x:{:02x} y:{:02x} s: {:02x} sp: {:02x}",
-//         pc,
-//         disasm::parse(opcode as u8, &code[1..]),
-//         cpu.get_a(),
-//         cpu.get_x(),
-//         cpu.get_y(),
-//         cpu.get_status(),
-//         cpu.get_sp()
-//     );
-// }
+// a parsed ines file, everything needed to build the cart.
+pub struct Rom {
+    pub prg_rom: Vec<u8>,
+    pub chr_rom: Vec<u8>,
+    // there was no chr in the file, so chr_rom is 8k of ram.
+    pub chr_ram: bool,
+    pub sram: Vec<u8>,
+    pub mirror: MirrorType,
+    pub mapper_id: u8,
+    // nes 2.0 headers can say which of the boards sharing a mapper number this is. 0 when they don't.
+    pub submapper: u8,
+    pub battery: bool,
+    // which console it was made for, see region.rs.
+    pub region: Region,
+    // 8k of ram at $6000. everything but nrom has it whatever the header says. so does nrom, unless it's a nes 2.0
+    // header saying there isn't any: ines 1.0 has no way to say none, byte 8 being 0 means 8k.
+    pub prg_ram: bool,
+    // crc32 of the prg and chr, which is how saves, states and movies know which game they belong to.
+    pub crc: u32,
+    // the disk in the drive, when it's a disk system game rather than a cart. see fds.rs.
+    pub disk: Option<Rc<fds::Disk>>,
+}
 
-pub fn run_rom() {
-    println!("Booting NES...");
+impl Rom {
+    pub fn parse(rom: &[u8]) -> Option<Rom> {
+        if rom.len() < 16 {
+            println!("Not an INES file, cannot run.");
+            return None;
+        }
 
-    //// it's actually really easy, this just inputs it at compile time.
-    //// SLICK!
-    // uses relative pathing
-    // to change the rom, just change the path.
-    // or just change the file, and always have a rom.nes at the root of the project, so that the script just has to replace the rom.nes file
-    // and then recompile the OS.
-    // what's easiest? this is important, this is the entire point of the project.
-    let rom = include_bytes!("../../rom/smb.nes");
-
-    // then slice the array for the header.
-    let rheader = &rom[0..16];
-
-    serial_println!("{:#?}", rheader);
-
-    // transmute these bytes into a packed C struct.
-    // slick! one line!
-    let header = INesHeader {
-        magic: [rheader[0], rheader[1], rheader[2], rheader[3]],
-        prg_rom_nbanks: rheader[4],
-        chr_rom_nbanks: rheader[5],
-        flags6: rheader[6],
-        flags7: rheader[7],
-        prg_ram_nbanks: rheader[8],
-        flags9: rheader[9],
-        flags10: rheader[10],
-        padding: [0; 5],
-    };
+        // then slice the array for the header.
+        let rheader = &rom[0..16];
+
+        serial_println!("{:#?}", rheader);
+
+        // transmute these bytes into a packed C struct.
+        // slick! one line!
+        let header = INesHeader {
+            magic: [rheader[0], rheader[1], rheader[2], rheader[3]],
+            prg_rom_nbanks: rheader[4],
+            chr_rom_nbanks: rheader[5],
+            flags6: rheader[6],
+            flags7: rheader[7],
+            prg_ram_nbanks: rheader[8],
+            flags9: rheader[9],
+            flags10: rheader[10],
+            padding: [0; 5],
+        };
+
+        let mirror = match ((header.flags6 >> 2) & 2) | (header.flags6 & 1) {
+            0 => MirrorType::Horizontal,
+            1 => MirrorType::Vertical,
+            2 => MirrorType::Single0,
+            3 => MirrorType::Single1,
+            _ => MirrorType::Four,
+        };
+        let mapper_id = (header.flags7 & 0xf0) | (header.flags6 >> 4);
+        // nes 2.0 reuses byte 8 for the top of the mapper number and the submapper, and byte 10 for ram sizes.
+        let nes2 = header.flags7 & 0x0c == 0x08;
+        let submapper = if nes2 { header.prg_ram_nbanks >> 4 } else { 0 };
+        // and byte 12 for the region. plain ines has a pal bit in byte 9 that hardly anything sets, so that's ntsc.
+        let region = if nes2 { Region::from_nes2(rheader[12]) } else { Region::Ntsc };
+
+        let magic = b"NES\x1a";
+        if header.magic != magic.as_ref() {
+            println!("Not an INES file, cannot run.");
+            return None;
+        }
 
-    let mirror = match ((header.flags6 >> 2) & 2) | (header.flags6 & 1) {
-        0 => MirrorType::Horizontal,
-        1 => MirrorType::Vertical,
-        2 => MirrorType::Single0,
-        3 => MirrorType::Single1,
-        _ => MirrorType::Four,
-    };
-    let mapper_id = (header.flags7 & 0xf0) | (header.flags6 >> 4);
-
-    let magic = b"NES\x1a";
-    println!("magic: {:#?}", header.magic);
-    println!("desired magic: {:#?}", magic);
-    if header.magic != magic.as_ref() {
-        println!("Not an INES file, cannot run.");
-        return;
-    }
+        println!(
+            "prg size:{}, chr size:{}, mirror type:{}, mapper:{}",
+            header.prg_rom_nbanks, header.chr_rom_nbanks, mirror as u8, mapper_id
+        );
+        // the prg comes right after the header, unless there's a 512 byte trainer in the way.
+        let mut offset = 16;
+        if header.flags6 & 0x04 == 0x04 {
+            println!("skipping trainer");
+            offset += 512;
+        }
 
-    println!(
-        "prg size:{}, chr size:{}, mirror type:{}, mapper:{}",
-        header.prg_rom_nbanks, header.chr_rom_nbanks, mirror as u8, mapper_id
-    );
-    if header.flags6 & 0x04 == 0x04 {
-        // let mut trainer: [u8; 512] = [0; 512];
-        // file.read(&mut trainer[..]).unwrap();
-        // println!("skipping trainer");
-    }
+        let prg_len = header.prg_rom_nbanks as usize * 0x4000;
+        let rom_chr_len = header.chr_rom_nbanks as usize * 0x2000;
+        // no chr rom means the cart has 8KiB of chr ram instead.
+        let chr_len = if rom_chr_len == 0 { 0x2000 } else { rom_chr_len };
+
+        if rom.len() < offset + prg_len + rom_chr_len {
+            println!("rom is truncated, cannot run.");
+            return None;
+        }
+        let prg_data = &rom[offset..offset + prg_len];
+        let chr_data = &rom[offset + prg_len..offset + prg_len + rom_chr_len];
+
+        let mut crc = crc32::Crc32::new();
+        crc.update(prg_data);
+        crc.update(chr_data);
+        let crc = crc.finish();
+        println!("rom crc32: {:08x}", crc);
+
+        let mut chr_rom = vec![0; chr_len];
+        chr_rom[..rom_chr_len].copy_from_slice(chr_data);
 
-    let prg_len = header.prg_rom_nbanks as usize * 0x4000;
-    let mut chr_len = header.chr_rom_nbanks as usize * 0x2000;
-    if chr_len == 0 {
-        chr_len = 0x2000;
+        Some(Rom {
+            prg_rom: prg_data.to_vec(),
+            chr_rom,
+            chr_ram: rom_chr_len == 0,
+            sram: vec![0; 0x2000],
+            mirror,
+            mapper_id,
+            submapper,
+            battery: header.flags6 & 0x02 == 0x02,
+            region,
+            prg_ram: !nes2 || header.flags6 & 0x02 == 0x02 || header.flags10 != 0,
+            crc,
+            disk: None,
+        })
     }
+}
 
-    let mut prg_rom = vec![0; prg_len];
-    let mut chr_rom = vec![0; chr_len];
-    let sram = vec![0; 0x2000];
-    println!("zero initializing the SRAM");
+// runes has 1 and 2, the rest are ours, see mappers/. the ones with irq counters hook them up to the cpu's irq line.
+// mmc5 (mapper 5) isn't here yet, and its games stop at "unsupported mapper 5". it's a project of its own: split
+// screen, extended attributes, its own sound channels, and a scanline irq it finds by watching the ppu's fetches.
+fn make_mapper(
+    cart: SimpleCart,
+    mapper_id: u8,
+    submapper: u8,
+    prg_ram: bool,
+    disk: Option<Rc<fds::Disk>>,
+    irq_line: &Rc<irq::IrqLine>,
+) -> Option<(Box<dyn mapper::Mapper>, mappers::CartIrq)> {
+    use mappers::CartIrq;
+    let vrc_irq = || mappers::vrc::VrcIrq::new(irq_line.clone());
+    let mut cart_irq = CartIrq::None;
+    let m: Box<dyn mapper::Mapper> = match mapper_id {
+        0 => Box::new(mappers::nrom::Nrom::new(cart, prg_ram)),
+        1 => Box::new(mapper::Mapper1::new(cart)),
+        2 => Box::new(mapper::Mapper2::new(cart)),
+        3 => Box::new(mappers::cnrom::Cnrom::new(cart)),
+        4 => {
+            let irq = mappers::mmc3::Mmc3Irq::new(irq_line.clone());
+            cart_irq = CartIrq::Mmc3(irq.clone());
+            Box::new(mappers::mmc3::Mmc3::new(cart, irq))
+        }
+        7 => Box::new(mappers::axrom::Axrom::new(cart)),
+        9 => Box::new(mappers::mmc2::Mmc2::new(cart, mappers::mmc2::Chip::Mmc2)),
+        10 => Box::new(mappers::mmc2::Mmc2::new(cart, mappers::mmc2::Chip::Mmc4)),
+        11 => Box::new(mappers::color_dreams::ColorDreams::new(cart)),
+        fds::MAPPER_ID => {
+            let adapter = mappers::fds::Adapter::new(disk?, irq_line.clone());
+            cart_irq = CartIrq::Fds(adapter.clone());
+            Box::new(mappers::fds::Fds::new(cart, adapter))
+        }
+        21 | 22 | 23 | 25 => {
+            let irq = vrc_irq();
+            cart_irq = CartIrq::Vrc(irq.clone());
+            Box::new(mappers::vrc::Vrc::new(cart, mapper_id, submapper, irq)?)
+        }
+        24 | 26 => {
+            let irq = vrc_irq();
+            cart_irq = CartIrq::Vrc(irq.clone());
+            Box::new(mappers::vrc6::Vrc6::new(cart, mapper_id, irq)?)
+        }
+        31 => Box::new(mappers::nsf_banks::NsfBanks::new(cart)),
+        66 => Box::new(mappers::gxrom::Gxrom::new(cart)),
+        _ => return None,
+    };
+    Some((m, cart_irq))
+}
 
+// what's in the controller ports, other than player 1's pad in port 1.
+pub enum Plugged<'a> {
+    Nothing,
+    // something in port 2, like the zapper.
+    Port2(&'a dyn Controller),
+    // a four score across both ports, with player 1's pad and the pads for players 2 to 4. see fourscore.rs.
+    FourScore([&'a dyn InputPoller; 3]),
+}
+
+// build the machine around a rom and run it until one of the hooks says stop.
+// frame_hook gets called between instructions once a frame, with the machine and the controller to latch input into.
+// step_hook, if there is one, gets called before every instruction with the cpu cycles run so far, while
+// machine.stepping() says to.
+// genie, if there is one, gets put between the cpu and the mapper to patch prg reads, see cheats.rs.
+// plugged is what's in the controller ports besides player 1's pad.
+pub fn emulate(
+    rom: Rom,
+    screen: &mut dyn ppu::Screen,
+    output: &mut dyn sound::AudioOutput,
+    frame_hook: &mut dyn FnMut(&machine::Machine, &TerminalKeyboard) -> bool,
+    mut step_hook: Option<&mut dyn FnMut(&machine::Machine, u64) -> bool>,
+    genie: Option<Rc<cheats::GeniePatches>>,
+    plugged: Plugged,
+) {
     /* construct mapper from cartridge data */
-    let cart = SimpleCart::new(chr_rom, prg_rom, sram, mirror);
+    let cart = SimpleCart::new(rom.chr_rom, rom.chr_ram, rom.prg_rom, rom.sram, rom.mirror);
+    let sram = cart.sram_view();
     println!("constructing the cart");
-    let mut m: Box<dyn mapper::Mapper> = match mapper_id {
-        0 | 2 => Box::new(mapper::Mapper2::new(cart)),
-        1 => Box::new(mapper::Mapper1::new(cart)),
-        4 => Box::new(mapper::Mapper4::new(cart)),
-        _ => panic!("unsupported mapper {}", mapper_id),
+    // the cpu's irq pin. the cart's counter and the apu both pull on it, see irq.rs.
+    let irq_line = irq::IrqLine::new();
+    let (mut m, cart_irq) = match make_mapper(cart, rom.mapper_id, rom.submapper, rom.prg_ram, rom.disk, &irq_line) {
+        Some(m) => m,
+        None => {
+            println!("unsupported mapper {}", rom.mapper_id);
+            return;
+        }
     };
+    if let Some(patches) = genie {
+        m = Box::new(cheats::GenieMapper::new(m, patches));
+    }
 
     println!("constructing the devices");
-    let keyboard = TerminalKeyboard {};
+    let keyboard = match plugged {
+        Plugged::FourScore(_) => TerminalKeyboard::with_mapping(&fourscore::PLAYER_1_KEYS),
+        _ => TerminalKeyboard::new(),
+    };
 
     // p1 controller init.
     // pass a pointer to a pollable object.
     // it'll just call the poll method and update the CPU controller MMIO with the u8 controller state byte.
     let p1ctl = stdctl::Joystick::new(&keyboard);
+    let hub;
+    let (port1, port2): (&dyn Controller, Option<&dyn Controller>) = match plugged {
+        Plugged::Nothing => (&p1ctl, None),
+        Plugged::Port2(device) => (&p1ctl, Some(device)),
+        Plugged::FourScore(others) => {
+            hub = fourscore::FourScore::new([&keyboard, others[0], others[1], others[2]]);
+            (hub.port(0), Some(hub.port(1)))
+        }
+    };
 
     /* setup the emulated machine */
+    let mapper_ptr = &mut (*m) as *mut dyn mapper::Mapper;
     let mapper = mapper::RefMapper::new(&mut (*m) as &mut dyn mapper::Mapper);
     let mut cpu =
-        mos6502::CPU::new(CPUMemory::new(&mapper, Some(&p1ctl), None));
+        mos6502::CPU::new(CPUMemory::new(&mapper, Some(port1), port2));
 
     // need to pass the ppu anything that implements "Screen" in ppu.rs
-    let mut win = construct::TerminalScreen::new();
-    let mut ppu = ppu::PPU::new(PPUMemory::new(&mapper), &mut win);
-
-    // same deal here. just fill the implementation.
-    let mut spkr = construct::TerminalAudio {};
+    let mut ppu = ppu::PPU::new(PPUMemory::new(&mapper), screen);
+
+    // ntsc unless the rom says otherwise, see region.rs.
+    let timing = rom.region.timing();
+    let mut clock = region::Clock::new(&timing, irq_line.clone());
+    let mut spkr = construct::TerminalAudio::new(output, timing.apu_sample_rate());
+    if let Some(audio) = cart_irq.audio() {
+        spkr.set_expansion(audio);
+    }
+    // the apu output as a wav over serial, when the capture option or scroll lock say so. see capture.rs.
+    let mut capture_trigger = capture::Trigger::new();
     let mut apu = APU::new(&mut spkr);
 
     let cpu_ptr = &mut cpu as *mut mos6502::CPU;
 
     println!("attaching the devices");
     cpu.mem.bus.attach(cpu_ptr, &mut ppu, &mut apu);
+    let machine = machine::Machine::new(cpu_ptr, &mut ppu, &mut apu, mapper_ptr, sram);
 
-    //// tries to load the default_sram_name? what is this?
-    //// this is probably just savestate stuff, which we don't need right now.
-        // None => match File::open(&default_sram_name) {
     println!("powering up the initialized CPU.");
     cpu.powerup();
 
+    let mut last_frame = frame_count();
+    let mut cycles: u64 = 0;
     loop {
         /* consume the leftover cycles from the last instruction */
         while cpu.cycle > 0 {
-            cpu.mem.bus.tick()
+            clock.tick(&machine);
+            cart_irq.cpu_cycle();
+            cycles += 1;
+        }
+        // the line stays low until the game acknowledges it, the cpu takes it whenever the i flag lets it.
+        if irq_line.asserted() {
+            cpu.trigger_irq();
+        }
+        irq_line.observe(&machine);
+        clock.observe(&machine);
+        cart_irq.observe(&machine);
+
+        if machine.stepping() {
+            if let Some(hook) = step_hook.as_mut() {
+                if !hook(&machine, cycles) {
+                    break;
+                }
+            }
         }
 
-        println!("{}", cpu.get_sp());
         cpu.step();
+
+        let frame = frame_count();
+        if frame != last_frame {
+            last_frame = frame;
+            clock.frame();
+            capture_trigger.frame();
+            if !frame_hook(&machine, &keyboard) {
+                break;
+            }
+        }
+    }
+}
+
+// what there is to run.
+enum Game {
+    Cart(Rom),
+    Music(nsf::Nsf),
+}
+
+// the game we ship with, or with the rom=<name> boot option, a file off the rom disk: an ines rom, a disk system
+// image, which also needs the bios on the disk, or an nsf tune.
+fn load_game() -> Option<Game> {
+    //// it's actually really easy, this just inputs it at compile time.
+    //// SLICK!
+    // uses relative pathing
+    // to change the rom, just change the path.
+    // or just change the file, and always have a rom.nes at the root of the project, so that the script just has to replace the rom.nes file
+    // and then recompile the OS.
+    // what's easiest? this is important, this is the entire point of the project.
+    let name = match cmdline::get("rom") {
+        Some(name) => name,
+        None => return Rom::parse(include_bytes!("../../rom/smb.nes")).map(Game::Cart),
+    };
+    let data = match romdisk::read(name) {
+        Some(data) => data,
+        None => {
+            println!("no {} on the rom disk, cannot run.", name);
+            return None;
+        }
+    };
+    if nsf::is_nsf(&data) {
+        nsf::parse(&data).map(Game::Music)
+    } else if fds::is_fds(&data) {
+        fds::load(&data).map(Game::Cart)
+    } else {
+        Rom::parse(&data).map(Game::Cart)
     }
 }
+
+pub fn run_rom() {
+    println!("Booting NES...");
+
+    let mut rom = match load_game() {
+        Some(Game::Cart(rom)) => rom,
+        // no picture, no controller, so none of the rest of this. see nsf.rs.
+        Some(Game::Music(tune)) => return nsf::play(tune),
+        None => return,
+    };
+    // before anything looks at the header, since a lot of headers are wrong.
+    romdb::apply(&mut rom);
+    rom.region = rom.region.choose();
+    println!("region: {:?}", rom.region);
+    let rom_crc = rom.crc;
+
+    let battery = rom.battery;
+    if battery {
+        sram::load(rom_crc, &mut rom.sram);
+    } else {
+        println!("zero initializing the SRAM");
+    }
+
+    let mut battery_save: Option<sram::BatterySave> = None;
+    let mut save_slots = savestate::SaveSlots::new(rom_crc);
+    let mut rewind = rewind::Rewind::new();
+    // F10 cheat menu, see cheats.rs.
+    let mut cheats = cheats::Cheats::new(rom_crc);
+    let genie = cheats.genie();
+    // F11 flips disks over, when there's a disk.
+    let disk = rom.disk.clone();
+    // four players, players 2-4 on their own keys. see fourscore.rs.
+    let four_score = cmdline::flag("fourscore");
+    let other_pads = fourscore::keyboard_pads();
+    // movies start from power-on, so this has to be set up before the first frame.
+    let mut movie = movie::Movie::new(rom_crc, four_score);
+    // a light gun in port 2 aimed with the mouse, for duck hunt and friends. see zapper.rs.
+    // the four score's using port 2 if it's in.
+    let zapper = if cmdline::flag("zapper") && !four_score { zapper::Zapper::plug_in() } else { None };
+    let plugged = match &zapper {
+        _ if four_score => Plugged::FourScore([&other_pads[0], &other_pads[1], &other_pads[2]]),
+        Some(zapper) => Plugged::Port2(zapper),
+        None => Plugged::Nothing,
+    };
+
+    let mut win = construct::TerminalScreen::new();
+    // with the zapper in, it needs to see what the ppu draws on the way to the screen.
+    let mut sight;
+    let screen: &mut dyn ppu::Screen = match &zapper {
+        Some(zapper) => {
+            sight = zapper::Sight::new(&mut win, zapper);
+            &mut sight
+        }
+        None => &mut win,
+    };
+    // whatever sound hardware the "audio" boot option asks for, or the best one we can find.
+    let mut output = sound::open_output();
+
+    // every instruction out over serial, see trace.rs. slow, but it's for diffing against other emulators.
+    let tracing = cmdline::flag("trace");
+    // the 6502 monitor on serial, see debugger.rs.
+    let mut debugger = debugger::Debugger::new();
+    let debugging = debugger.enabled();
+    // F1-F4 ppu viewers, see ppuview.rs. the shadow watches scroll writes go by, for the nametable view.
+    let scroll_shadow = ppuview::ScrollShadow::new();
+    let mut viewer = ppuview::PpuViewer::new();
+    // F8 memory viewer and cheat search, see memview.rs.
+    let mut memview = memview::MemoryViewer::new();
+    let mut per_instruction = |machine: &machine::Machine, cycles: u64| {
+        if tracing {
+            serial_println!("{}", trace::trace_line(machine, cycles));
+        }
+        scroll_shadow.observe(machine);
+        !debugging || debugger.step(machine, cycles)
+    };
+
+    emulate(rom, screen, &mut *output, &mut |machine, keyboard| {
+        // nothing needs to see every instruction unless one of these is going.
+        machine.set_stepping(tracing || debugging || scroll_shadow.watching());
+
+        if battery {
+            battery_save
+                .get_or_insert_with(|| sram::BatterySave::new(rom_crc, machine.sram()))
+                .frame();
+        }
+
+        let frame_hash = LAST_FRAME_HASH.load(Ordering::Relaxed);
+        // players 2-4 are on keys player 1 uses without a four score, so they're only read with one.
+        let mut live = [keyboard.read_keys(), 0, 0, 0];
+        if four_score {
+            for (state, pad) in live[1..].iter_mut().zip(other_pads.iter())
+            {
+                *state = pad.read_keys();
+            }
+        }
+        let pads = movie.frame(frame_hash, live);
+        keyboard.latch(pads[0]);
+        if four_score {
+            for (pad, &state) in other_pads.iter().zip(pads[1..].iter())
+            {
+                pad.latch(state);
+            }
+        }
+
+        while let Some(key) = crate::keyboard::pop_key() {
+            let _ = movie.handle_key(key)
+                || save_slots.handle_key(key, machine)
+                || viewer.handle_key(key, &scroll_shadow)
+                || memview.handle_key(key, machine)
+                || cheats.handle_key(key)
+                || capture::handle_key(key)
+                || disk.as_ref().is_some_and(|d| d.handle_key(key));
+        }
+
+        rewind.frame(machine, crate::keyboard::is_pressed(KeyCode::Backspace));
+        viewer.frame(machine, &scroll_shadow);
+        memview.frame(machine);
+        cheats.frame(machine);
+        if let Some(disk) = &disk {
+            disk.frame();
+        }
+        if let Some(zapper) = &zapper {
+            zapper.draw_crosshair();
+        }
+        true
+    }, Some(&mut per_instruction), Some(genie), plugged);
+}
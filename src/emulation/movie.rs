@@ -0,0 +1,319 @@
+// input movies, for reproducing bugs frame for frame.
+//   movie=record:<name>   record from power-on, F12 stops and writes the files out
+//   movie=play:<name>     replay a recording instead of reading the keyboard
+//
+// a movie is the controller byte for every frame, starting at power-on, written out as an fm2 (fceux's text
+// format) so other emulators can play it too. with a four score in, it's all four pads' bytes, and the fm2 says so.
+// next to it goes <name>.fmh, the crc32 of every frame we drew while recording, one hex line per frame. on playback
+// we hash every frame again and compare, so the first frame where this build of the emulator does something
+// different gets reported, instead of the movie silently going off the rails a few hundred frames later.
+//
+// recordings go to the rom disk, made with room for MAX_FRAMES up front and appended to as they go, so a long
+// recording never gets rewritten or moved. playback looks there first, then for opt/nesos/<name>.fm2 in fw_cfg.
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use pc_keyboard::KeyCode;
+use runes::controller::stdctl;
+
+use crate::{cmdline, fw_cfg, println, romdisk, serial_println};
+
+// fm2 spells the buttons in this order, a '.' for not pressed.
+const FM2_BUTTONS: [(char, u8); 8] = [
+    ('R', stdctl::RIGHT),
+    ('L', stdctl::LEFT),
+    ('D', stdctl::DOWN),
+    ('U', stdctl::UP),
+    ('T', stdctl::START),
+    ('S', stdctl::SELECT),
+    ('B', stdctl::B),
+    ('A', stdctl::A),
+];
+
+// write the new frames out every so often, in case we never get to F12.
+const RECORD_FLUSH_FRAMES: usize = 600;
+// ten minutes. a frame is an fm2 line and a hash line, so this is about 850KiB of disk, or 2MiB with four pads.
+const MAX_FRAMES: usize = 60 * 60 * 10;
+const PAD_FIELD: usize = "........|".len();
+// the line's ends, without the pads: "|0|" and the port 2 field one pad leaves empty, or just the "|0|" with four.
+const FM2_LINE_ENDS: usize = "|0|||\n".len();
+const FOURSCORE_LINE_ENDS: usize = "|0|\n".len();
+const HASH_LINE: usize = "00000000\n".len();
+// room for the fm2's header.
+const FM2_HEADER: usize = 512;
+
+enum Mode
+{
+    Off,
+    Recording,
+    Playing { frame: usize, desynced: bool },
+}
+
+pub struct Movie
+{
+    mode: Mode,
+    name: String,
+    rom_crc: u32,
+    // whether there's a four score, so four pads a frame. player 1 only otherwise.
+    four_score: bool,
+    // each frame's pads, players 2-4 being 0 without a four score.
+    inputs: Vec<[u8; 4]>,
+    hashes: Vec<u32>,
+    // how many of each are on the disk already, when recording.
+    written_inputs: usize,
+    written_hashes: usize,
+}
+
+fn encode_buttons(state: u8) -> String
+{
+    FM2_BUTTONS.iter().map(|&(c, bit)| if state & bit != 0 { c } else { '.' }).collect()
+}
+
+fn decode_buttons(field: &str) -> u8
+{
+    field.chars()
+        .zip(FM2_BUTTONS.iter())
+        .filter(|&(c, _)| c != '.' && c != ' ')
+        .fold(0, |state, (_, &(_, bit))| state | bit)
+}
+
+// pull the pads out of every frame line of an fm2. without a four score only the first one means anything.
+fn parse_fm2(text: &str) -> Vec<[u8; 4]>
+{
+    text.lines()
+        .filter(|line| line.starts_with('|'))
+        .map(|line| {
+            // |commands|port0|port1|port2|, or |commands|pad1|pad2|pad3|pad4| with a four score
+            let mut pads = [0; 4];
+            for (pad, field) in pads.iter_mut().zip(line.split('|').skip(2))
+            {
+                *pad = decode_buttons(field);
+            }
+            pads
+        })
+        .collect()
+}
+
+fn fm2_line(pads: [u8; 4], four_score: bool) -> String
+{
+    if four_score {
+        let fields: Vec<String> = pads.iter().map(|&p| encode_buttons(p)).collect();
+        format!("|0|{}|", fields.join("|"))
+    } else {
+        format!("|0|{}|||", encode_buttons(pads[0]))
+    }
+}
+
+fn parse_hashes(text: &str) -> Vec<u32>
+{
+    text.lines()
+        .filter_map(|line| u32::from_str_radix(line.trim(), 16).ok())
+        .collect()
+}
+
+fn read_file(name: &str) -> Option<Vec<u8>>
+{
+    romdisk::read(name).or_else(|| fw_cfg::read_file(&format!("opt/nesos/{}", name)))
+}
+
+impl Movie
+{
+    pub fn new(rom_crc: u32, four_score: bool) -> Movie
+    {
+        let mut movie = Movie {
+            mode: Mode::Off,
+            name: String::new(),
+            rom_crc,
+            four_score,
+            inputs: Vec::new(),
+            hashes: Vec::new(),
+            written_inputs: 0,
+            written_hashes: 0,
+        };
+
+        let option = match cmdline::get("movie") {
+            Some(o) => o,
+            None => return movie,
+        };
+        let mut parts = option.splitn(2, ':');
+        let action = parts.next().unwrap_or("");
+        movie.name = String::from(parts.next().unwrap_or("movie"));
+
+        match action {
+            "record" => {
+                if movie.start_files() {
+                    println!("recording movie {}, up to {} frames", movie.name, MAX_FRAMES);
+                    movie.mode = Mode::Recording;
+                } else {
+                    println!("no room for movie {} on the rom disk, not recording", movie.name);
+                }
+            }
+            "play" => {
+                let fm2 = read_file(&format!("{}.fm2", movie.name));
+                match fm2.as_ref().and_then(|f| core::str::from_utf8(f).ok()) {
+                    Some(text) => {
+                        movie.inputs = parse_fm2(text);
+                        if text.lines().any(|l| l.trim() == "fourscore 1") && !four_score {
+                            println!("movie {} has four pads, this is only playing player 1's", movie.name);
+                        }
+                        movie.hashes = read_file(&format!("{}.fmh", movie.name))
+                            .and_then(|h| String::from_utf8(h).ok())
+                            .map(|h| parse_hashes(&h))
+                            .unwrap_or_default();
+                        println!("playing movie {}, {} frames", movie.name, movie.inputs.len());
+                        movie.mode = Mode::Playing { frame: 0, desynced: false };
+                    }
+                    None => println!("couldn't find movie {}", movie.name),
+                }
+            }
+            _ => println!("unknown movie option {}", option),
+        }
+        movie
+    }
+
+    pub fn playing(&self) -> bool
+    {
+        matches!(self.mode, Mode::Playing { .. })
+    }
+
+    // called once a frame with the hash of the frame just drawn and what the keyboard says for each pad.
+    // returns the controller states to use for the next frame.
+    pub fn frame(&mut self, frame_hash: u32, live_input: [u8; 4]) -> [u8; 4]
+    {
+        match &mut self.mode {
+            Mode::Off => live_input,
+            Mode::Recording => {
+                // the hash belongs to the frame that the previous input produced.
+                if !self.inputs.is_empty() {
+                    self.hashes.push(frame_hash);
+                }
+                self.inputs.push(live_input);
+                if self.inputs.len() == MAX_FRAMES {
+                    self.write_out();
+                    println!("movie {} is {} frames long, that's all there's room for", self.name, MAX_FRAMES);
+                    self.mode = Mode::Off;
+                } else if self.inputs.len() % RECORD_FLUSH_FRAMES == 0 {
+                    self.write_out();
+                }
+                live_input
+            }
+            Mode::Playing { frame, desynced } => {
+                if *frame > 0 && !*desynced {
+                    if let Some(&expected) = self.hashes.get(*frame - 1) {
+                        if expected != frame_hash {
+                            *desynced = true;
+                            println!("movie desynced at frame {}: expected {:08x}, drew {:08x}", *frame, expected, frame_hash);
+                            serial_println!("MOVIE DESYNC {} {:08x} {:08x}", *frame, expected, frame_hash);
+                        }
+                    }
+                }
+
+                match self.inputs.get(*frame) {
+                    Some(&input) => {
+                        *frame += 1;
+                        input
+                    }
+                    None => {
+                        if !*desynced {
+                            serial_println!("MOVIE OK {}", *frame);
+                        }
+                        println!("movie finished after {} frames", *frame);
+                        self.mode = Mode::Off;
+                        live_input
+                    }
+                }
+            }
+        }
+    }
+
+    fn fm2_header(&self) -> String
+    {
+        let mut text = String::new();
+        let _ = writeln!(text, "version 3");
+        let _ = writeln!(text, "emuVersion 0");
+        let _ = writeln!(text, "rerecordCount 0");
+        let _ = writeln!(text, "palFlag 0");
+        let _ = writeln!(text, "romFilename {:08x}", self.rom_crc);
+        let _ = writeln!(text, "comment author nesos");
+        let _ = writeln!(text, "comment rom crc32 {:08x}", self.rom_crc);
+        let _ = writeln!(text, "fourscore {}", self.four_score as u8);
+        let _ = writeln!(text, "port0 1");
+        let _ = writeln!(text, "port1 {}", self.four_score as u8);
+        let _ = writeln!(text, "port2 0");
+        text
+    }
+
+    // make both files, empty but with all the room they'll need, and put the fm2's header in.
+    fn start_files(&self) -> bool
+    {
+        let fm2 = format!("{}.fm2", self.name);
+        let line = if self.four_score { FOURSCORE_LINE_ENDS + 4 * PAD_FIELD } else { FM2_LINE_ENDS + PAD_FIELD };
+        romdisk::create(&fm2, FM2_HEADER + MAX_FRAMES * line)
+            && romdisk::append(&fm2, self.fm2_header().as_bytes())
+            && romdisk::create(&format!("{}.fmh", self.name), MAX_FRAMES * HASH_LINE)
+    }
+
+    // append whatever hasn't been written yet. when the files are full that's the end of the recording.
+    fn write_out(&mut self)
+    {
+        let mut inputs = String::new();
+        for &input in self.inputs[self.written_inputs..].iter()
+        {
+            let _ = writeln!(inputs, "{}", fm2_line(input, self.four_score));
+        }
+        let mut hashes = String::new();
+        for h in self.hashes[self.written_hashes..].iter()
+        {
+            let _ = writeln!(hashes, "{:08x}", h);
+        }
+
+        let ok = romdisk::append(&format!("{}.fm2", self.name), inputs.as_bytes())
+            && romdisk::append(&format!("{}.fmh", self.name), hashes.as_bytes());
+        if !ok {
+            serial_println!("couldn't write movie {} to the rom disk", self.name);
+            println!("movie {} is full or the disk failed, stopped recording", self.name);
+            self.mode = Mode::Off;
+            return;
+        }
+        self.written_inputs = self.inputs.len();
+        self.written_hashes = self.hashes.len();
+    }
+
+    pub fn handle_key(&mut self, key: KeyCode) -> bool
+    {
+        if key != KeyCode::F12 {
+            return false;
+        }
+        if let Mode::Recording = self.mode {
+            self.write_out();
+            println!("stopped recording {} after {} frames", self.name, self.inputs.len());
+            self.mode = Mode::Off;
+        }
+        true
+    }
+}
+
+#[test_case]
+fn test_fm2_buttons_roundtrip()
+{
+    let state = stdctl::A | stdctl::START | stdctl::LEFT;
+    let field = encode_buttons(state);
+    assert_eq!(field.as_str(), ".L..T..A");
+    assert_eq!(decode_buttons(&field), state);
+}
+
+#[test_case]
+fn test_fm2_four_pads()
+{
+    let pads = [stdctl::A, 0, stdctl::START, stdctl::UP | stdctl::B];
+    let line = fm2_line(pads, true);
+    assert_eq!(line.len() + 1, FOURSCORE_LINE_ENDS + 4 * PAD_FIELD);
+    assert_eq!(parse_fm2(&line), [pads]);
+    // and one pad still reads back as one pad.
+    let line = fm2_line(pads, false);
+    assert_eq!(line.len() + 1, FM2_LINE_ENDS + PAD_FIELD);
+    assert_eq!(parse_fm2(&line), [[stdctl::A, 0, 0, 0]]);
+}
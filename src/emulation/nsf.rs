@@ -0,0 +1,417 @@
+// nsf files: a game's music ripped out with just enough of its code to play it, and a header saying how.
+//   0    "NESM\x1a"               14   title, 32 bytes, nul padded
+//   6    how many tracks          46   artist
+//   7    the first one to play    78   copyright
+//   8    load address             110  ntsc play rate, in microseconds
+//   10   init address             112  bank for each 4k of $8000-$FFFF, all 0 if it doesn't bankswitch
+//   12   play address             120  pal play rate, 122 bit 0 pal, bit 1 either
+// the data goes in at the load address, or for bankswitched tunes at the load address's offset into a 4k bank, which
+// is just how mapper 31 works, so that's what it runs on. expansion sound chips (byte 123) aren't played.
+//
+// the player is a driver the way a real nsf player cart has one: it calls init with the track number in a, then play
+// over and over at the rate the header asks for, with the cpu parked on a loop at nsf_banks::IDLE in between. play is
+// timed off the emulated cpu's cycles rather than the pit, since the sound card is what paces the emulator, and a
+// second clock would drift against it.
+// there's no picture, so the vga stays in text mode for the track, what it is, and a meter for each apu channel,
+// worked out from the apu registers the tune writes.
+//   Left/Right  previous/next track
+//   Enter       start the track over
+extern crate alloc;
+
+use alloc::format;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use pc_keyboard::KeyCode;
+use runes::cartridge::MirrorType;
+
+use crate::emulation::construct::{HeadlessScreen, TerminalKeyboard};
+use crate::emulation::crc32::Crc32;
+use crate::emulation::machine::{Machine, Register};
+use crate::emulation::mappers::nsf_banks::IDLE;
+use crate::emulation::trace::{effective_address, stored_value};
+use crate::emulation::{emulate, Plugged, Region, Rom};
+use crate::vga_buffer::{Color, WRITER};
+use crate::{keyboard, println, sound};
+
+const MAGIC: &[u8; 5] = b"NESM\x1a";
+const HEADER_SIZE: usize = 0x80;
+const BANK_SIZE: usize = 0x1000;
+// the mapper the tune runs on, see mappers/nsf_banks.rs.
+const MAPPER_ID: u8 = 31;
+
+const CHANNELS: [&str; 5] = ["Pulse 1", "Pulse 2", "Triangle", "Noise", "DMC"];
+const METER_ROW: usize = 11;
+const METER_WIDTH: usize = 45;
+// how many frames a meter takes to fall a step.
+const METER_FALL: u8 = 2;
+
+pub struct Info
+{
+    title: Vec<u8>,
+    artist: Vec<u8>,
+    copyright: Vec<u8>,
+    tracks: u8,
+    first: u8,
+    init: u16,
+    play: u16,
+    banks: [u8; 8],
+    ntsc_rate: u16,
+    pal_rate: u16,
+    region: Region,
+}
+
+pub struct Nsf
+{
+    pub rom: Rom,
+    pub info: Info,
+}
+
+pub fn is_nsf(data: &[u8]) -> bool
+{
+    data.starts_with(MAGIC)
+}
+
+fn word(data: &[u8], at: usize) -> u16
+{
+    data[at] as u16 | (data[at + 1] as u16) << 8
+}
+
+// a nul padded string out of the header, without the padding or anything that won't print.
+fn text(data: &[u8]) -> Vec<u8>
+{
+    data.iter().take_while(|&&b| b != 0).map(|&b| if (0x20..0x7F).contains(&b) { b } else { b'?' }).collect()
+}
+
+pub fn parse(data: &[u8]) -> Option<Nsf>
+{
+    if data.len() <= HEADER_SIZE || !is_nsf(data) {
+        println!("Not an NSF file, cannot play.");
+        return None;
+    }
+    let header = &data[..HEADER_SIZE];
+    let load = word(header, 8);
+    if load < 0x8000 {
+        println!("tune loads at {:04x}, below the rom, cannot play.", load);
+        return None;
+    }
+    let mut banks = [0u8; 8];
+    banks.copy_from_slice(&header[112..120]);
+    let bankswitched = banks.iter().any(|&b| b != 0);
+    // without bankswitching the banks are just $8000-$FFFF in order.
+    let pad = if bankswitched { load as usize & (BANK_SIZE - 1) } else { load as usize - 0x8000 };
+    if !bankswitched {
+        banks = [0, 1, 2, 3, 4, 5, 6, 7];
+    }
+
+    let tune = &data[HEADER_SIZE..];
+    let mut prg = vec![0; pad];
+    prg.extend_from_slice(tune);
+    prg.resize((prg.len() + BANK_SIZE - 1) / BANK_SIZE * BANK_SIZE, 0);
+
+    let mut crc = Crc32::new();
+    crc.update(tune);
+    let crc = crc.finish();
+
+    let region = if header[122] & 0x03 == 0x01 { Region::Pal } else { Region::Ntsc };
+    let info = Info {
+        title: text(&header[14..46]),
+        artist: text(&header[46..78]),
+        copyright: text(&header[78..110]),
+        tracks: header[6].max(1),
+        first: header[7].max(1).min(header[6].max(1)),
+        init: word(header, 10),
+        play: word(header, 12),
+        banks,
+        ntsc_rate: word(header, 110),
+        pal_rate: word(header, 120),
+        region,
+    };
+    let rom = Rom {
+        prg_rom: prg,
+        chr_rom: vec![0; 0x2000],
+        chr_ram: true,
+        sram: vec![0; 0x2000],
+        mirror: MirrorType::Vertical,
+        mapper_id: MAPPER_ID,
+        submapper: 0,
+        battery: false,
+        region,
+        prg_ram: true,
+        crc,
+        disk: None,
+    };
+    Some(Nsf { rom, info })
+}
+
+// what the tune's told the apu, enough for the meters.
+struct ApuShadow
+{
+    // $4015
+    enabled: u8,
+    // each channel's volume, and a bit per channel for the ones on a constant volume rather than an envelope.
+    volume: [u8; 5],
+    constant: u8,
+    levels: [u8; 5],
+    fall: u8,
+}
+
+impl ApuShadow
+{
+    fn new() -> ApuShadow
+    {
+        ApuShadow { enabled: 0, volume: [0; 5], constant: 0, levels: [0; 5], fall: 0 }
+    }
+
+    fn write(&mut self, addr: u16, value: u8)
+    {
+        let channel = ((addr - 0x4000) / 4) as usize;
+        match addr {
+            // pulses and noise: bit 4 means constant volume, otherwise the envelope starts from 15.
+            0x4000 | 0x4004 | 0x400C => {
+                if value & 0x10 != 0 {
+                    self.volume[channel] = value & 0x0F;
+                    self.constant |= 1 << channel;
+                    // games fade notes out by writing the volume down, so follow it.
+                    self.levels[channel] = self.levels[channel].min(self.volume[channel]);
+                } else {
+                    self.volume[channel] = 15;
+                    self.constant &= !(1 << channel);
+                }
+            }
+            // the triangle has no volume, just on or off.
+            0x4008 => self.volume[2] = if value & 0x7F != 0 { 15 } else { 0 },
+            // a note starting.
+            0x4003 | 0x4007 | 0x400B | 0x400F => {
+                if self.enabled & (1 << channel) != 0 {
+                    self.levels[channel] = self.volume[channel];
+                }
+            }
+            // the dmc's output level, written directly.
+            0x4011 => self.levels[4] = value >> 3,
+            0x4015 => {
+                self.enabled = value;
+                for channel in 0..5
+                {
+                    if value & (1 << channel) == 0 {
+                        self.levels[channel] = 0;
+                    }
+                }
+                if value & 0x10 != 0 {
+                    self.levels[4] = 15;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // constant volume holds as long as the channel's on, everything else dies away.
+    fn frame(&mut self)
+    {
+        self.fall += 1;
+        if self.fall < METER_FALL {
+            return;
+        }
+        self.fall = 0;
+        for channel in 0..5
+        {
+            if self.constant & (1 << channel) == 0 {
+                self.levels[channel] = self.levels[channel].saturating_sub(1);
+            }
+        }
+    }
+}
+
+struct Player
+{
+    info: Info,
+    track: u8,
+    // cpu cycles between play calls, and when the next one's due.
+    period: u64,
+    next_play: u64,
+    // set when init needs calling, the next time the cpu's idle.
+    restart: bool,
+    // whether the cpu's been sent to the idle loop yet.
+    started: bool,
+    apu: ApuShadow,
+    // the whole screen needs drawing again.
+    redraw: bool,
+}
+
+impl Player
+{
+    fn new(info: Info, region: Region) -> Player
+    {
+        let rate = match region {
+            Region::Ntsc => info.ntsc_rate,
+            _ => info.pal_rate,
+        };
+        // a rate of 0 is a broken header, go with the tv's.
+        let rate = if rate == 0 { if region == Region::Ntsc { 16639 } else { 19997 } } else { rate };
+        let period = region.timing().cpu_hz as u64 * rate as u64 / 1_000_000;
+        let track = info.first;
+        Player { info, track, period, next_play: 0, restart: true, started: false, apu: ApuShadow::new(), redraw: true }
+    }
+
+    // jsr to addr from the idle loop, so the rts comes back to it.
+    fn call(&self, machine: &Machine, addr: u16)
+    {
+        let ret = IDLE - 1;
+        let sp = machine.register(Register::SP) as u8;
+        machine.poke(0x100 | sp as u16, (ret >> 8) as u8);
+        machine.poke(0x100 | sp.wrapping_sub(1) as u16, ret as u8);
+        machine.set_register(Register::SP, sp.wrapping_sub(2) as u16);
+        machine.set_register(Register::PC, addr);
+    }
+
+    // what the nsf spec says a tune can expect before init: clear ram, a quiet apu, the banks in place.
+    fn init(&mut self, machine: &Machine)
+    {
+        for addr in (0x0000..0x0800).chain(0x6000..0x8000)
+        {
+            machine.poke(addr, 0);
+        }
+        for addr in 0x4000..0x4014
+        {
+            machine.poke(addr, 0);
+        }
+        machine.poke(0x4015, 0x00);
+        machine.poke(0x4015, 0x0F);
+        machine.poke(0x4017, 0x40);
+        for (i, &bank) in self.info.banks.iter().enumerate()
+        {
+            machine.poke(0x5FF8 + i as u16, bank);
+        }
+        self.apu = ApuShadow::new();
+        self.apu.enabled = 0x0F;
+
+        machine.set_register(Register::SP, 0xFD);
+        machine.set_register(Register::A, (self.track - 1) as u16);
+        machine.set_register(Register::X, (self.info.region == Region::Pal) as u16);
+        machine.set_register(Register::Y, 0);
+        self.call(machine, self.info.init);
+    }
+
+    // before every instruction.
+    fn step(&mut self, machine: &Machine, cycles: u64) -> bool
+    {
+        let pc = machine.register(Register::PC);
+        if let (Some(addr), Some(value)) = (effective_address(machine, pc), stored_value(machine, pc)) {
+            if (0x4000..=0x4017).contains(&addr) {
+                self.apu.write(addr, value);
+            }
+        }
+
+        // the cpu comes out of reset somewhere in the tune, send it to the idle loop before anything runs.
+        if !self.started {
+            self.started = true;
+            machine.set_register(Register::PC, IDLE);
+            return true;
+        }
+        // init or play is still going.
+        if pc != IDLE {
+            return true;
+        }
+        if self.restart {
+            self.restart = false;
+            self.redraw = true;
+            self.init(machine);
+            self.next_play = cycles + self.period;
+        } else if cycles >= self.next_play {
+            // a play that ran long just makes the next one late, the same as on a real player.
+            self.next_play = (self.next_play + self.period).max(cycles);
+            self.call(machine, self.info.play);
+        }
+        true
+    }
+
+    // once a frame, from the ppu.
+    fn frame(&mut self) -> bool
+    {
+        while let Some(key) = keyboard::pop_key()
+        {
+            let track = match key {
+                KeyCode::ArrowLeft if self.track > 1 => self.track - 1,
+                KeyCode::ArrowRight if self.track < self.info.tracks => self.track + 1,
+                KeyCode::Enter => self.track,
+                _ => continue,
+            };
+            self.track = track;
+            self.restart = true;
+        }
+        self.apu.frame();
+        self.draw();
+        true
+    }
+
+    fn draw(&mut self)
+    {
+        x86_64::instructions::interrupts::without_interrupts(|| {
+            let mut screen = WRITER.lock();
+            if self.redraw {
+                self.redraw = false;
+                screen.fill_screen_w_char(b' ');
+                screen.write_at(1, 2, b"NSF player", Color::White);
+                let info = &self.info;
+                let lines = [("Title", &info.title), ("Artist", &info.artist), ("Copyright", &info.copyright)];
+                for (i, (label, value)) in lines.iter().enumerate()
+                {
+                    screen.write_at(3 + i, 2, label.as_bytes(), Color::LightGray);
+                    screen.write_at(3 + i, 14, value, Color::Yellow);
+                }
+                let track = format!("Track {} of {}", self.track, self.info.tracks);
+                screen.write_at(8, 2, track.as_bytes(), Color::LightCyan);
+                screen.write_at(23, 2, b"Left/Right: previous/next track   Enter: start over", Color::DarkGray);
+            }
+            for (i, name) in CHANNELS.iter().enumerate()
+            {
+                let row = METER_ROW + i * 2;
+                let filled = self.apu.levels[i] as usize * METER_WIDTH / 15;
+                screen.write_at(row, 2, name.as_bytes(), Color::LightGray);
+                screen.write_at(row, 14, &[0xDB; METER_WIDTH][..filled], Color::LightGreen);
+                screen.write_at(row, 14 + filled, &[0xFA; METER_WIDTH][filled..], Color::DarkGray);
+            }
+        });
+    }
+}
+
+// play a tune until the machine's turned off.
+pub fn play(nsf: Nsf)
+{
+    let Nsf { mut rom, info } = nsf;
+    rom.region = rom.region.choose();
+    println!("nsf: {} tracks, region {:?}", info.tracks, rom.region);
+
+    let player = RefCell::new(Player::new(info, rom.region));
+    let mut screen = HeadlessScreen::new();
+    let mut output = sound::open_output();
+    let mut step = |machine: &Machine, cycles: u64| player.borrow_mut().step(machine, cycles);
+    let mut frame = |_: &Machine, _: &TerminalKeyboard| player.borrow_mut().frame();
+    emulate(rom, &mut screen, &mut *output, &mut frame, Some(&mut step), None, Plugged::Nothing);
+}
+
+#[test_case]
+fn test_nsf_header()
+{
+    let mut data = vec![0u8; HEADER_SIZE + 0x1800];
+    data[..5].copy_from_slice(MAGIC);
+    data[6] = 3;
+    data[7] = 2;
+    data[8..10].copy_from_slice(&0x8400u16.to_le_bytes());
+    data[14..18].copy_from_slice(b"Tune");
+    data[HEADER_SIZE] = 0xAB;
+    let nsf = parse(&data).unwrap();
+    assert_eq!(nsf.info.title, b"Tune".to_vec());
+    assert_eq!((nsf.info.tracks, nsf.info.first), (3, 2));
+    assert_eq!(nsf.info.banks, [0, 1, 2, 3, 4, 5, 6, 7]);
+    assert_eq!(nsf.rom.prg_rom[0x400], 0xAB);
+    assert_eq!(nsf.rom.prg_rom.len() % BANK_SIZE, 0);
+
+    let mut apu = ApuShadow::new();
+    apu.write(0x4015, 0x01);
+    apu.write(0x4000, 0x1A);
+    apu.write(0x4003, 0x00);
+    assert_eq!(apu.levels[0], 10);
+    apu.write(0x4007, 0x00);
+    assert_eq!(apu.levels[1], 0);
+}
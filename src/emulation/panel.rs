@@ -0,0 +1,103 @@
+// the strip of the 640x480 screen to the right of the game, shared by the debug viewers.
+// only one viewer gets it at a time: whoever opened last owns it, and everyone else stops drawing.
+use core::sync::atomic::{AtomicU8, Ordering};
+use vga::colors::Color16;
+use vga::writers::{Graphics640x480x16, GraphicsWriter};
+
+pub const PANEL_X: usize = 264;
+pub const PANEL_WIDTH: usize = 640 - PANEL_X;
+pub const PANEL_HEIGHT: usize = 480;
+
+// text is the 8x8 font on a 10 pixel pitch, with a margin.
+const MARGIN: usize = 8;
+const LINE_HEIGHT: usize = 10;
+pub const TEXT_COLUMNS: usize = (PANEL_WIDTH - MARGIN * 2) / 8;
+pub const TEXT_ROWS: usize = (PANEL_HEIGHT - MARGIN) / LINE_HEIGHT;
+
+// who has the panel. 0 is nobody.
+static OWNER: AtomicU8 = AtomicU8::new(0);
+
+pub const PPU_VIEWER: u8 = 1;
+pub const MEMORY_VIEWER: u8 = 2;
+pub const CHEATS: u8 = 3;
+
+pub struct Panel
+{
+    vga: Graphics640x480x16,
+    // what's on screen in each text cell, so redrawing the same text again is free.
+    // the planar writes are slow enough that this matters.
+    drawn: [[(u8, Color16); TEXT_COLUMNS]; TEXT_ROWS],
+}
+
+impl Panel
+{
+    pub fn new() -> Panel
+    {
+        Panel { vga: Graphics640x480x16::new(), drawn: [[(b' ', Color16::Black); TEXT_COLUMNS]; TEXT_ROWS] }
+    }
+
+    // take the panel over, blank.
+    pub fn claim(&mut self, owner: u8)
+    {
+        OWNER.store(owner, Ordering::Relaxed);
+        self.clear();
+    }
+
+    pub fn release(&mut self, owner: u8)
+    {
+        if OWNER.compare_exchange(owner, 0, Ordering::Relaxed, Ordering::Relaxed).is_ok() {
+            self.clear();
+        }
+    }
+
+    pub fn owned_by(&self, owner: u8) -> bool
+    {
+        OWNER.load(Ordering::Relaxed) == owner
+    }
+
+    pub fn clear(&mut self)
+    {
+        for y in 0..PANEL_HEIGHT
+        {
+            for x in PANEL_X..PANEL_X + PANEL_WIDTH
+            {
+                self.vga.set_pixel(x, y, Color16::Black);
+            }
+        }
+        self.drawn = [[(b' ', Color16::Black); TEXT_COLUMNS]; TEXT_ROWS];
+    }
+
+    // x and y relative to the panel.
+    pub fn pixel(&self, x: usize, y: usize, color: Color16)
+    {
+        if x < PANEL_WIDTH && y < PANEL_HEIGHT {
+            self.vga.set_pixel(PANEL_X + x, y, color);
+        }
+    }
+
+    // a line of text at a text row, padded out with blanks to the end of the row so nothing old is left behind.
+    pub fn text(&mut self, row: usize, text: &str, color: Color16)
+    {
+        if row >= TEXT_ROWS {
+            return;
+        }
+        let mut chars = text.bytes();
+        for column in 0..TEXT_COLUMNS
+        {
+            let c = chars.next().unwrap_or(b' ');
+            if self.drawn[row][column] == (c, color) {
+                continue;
+            }
+            let (x, y) = (PANEL_X + MARGIN + column * 8, MARGIN / 2 + row * LINE_HEIGHT);
+            for dy in 0..8
+            {
+                for dx in 0..8
+                {
+                    self.vga.set_pixel(x + dx, y + dy, Color16::Black);
+                }
+            }
+            self.vga.draw_character(x, y, c as char, color);
+            self.drawn[row][column] = (c, color);
+        }
+    }
+}
@@ -0,0 +1,324 @@
+// ppu viewers for homebrew work, drawn in the side panel to the right of the game in the 640x480 mode.
+//   F1  both pattern tables, in the background palette
+//   F2  all four nametables at half size, with the part on screen boxed
+//   F3  palette ram, background row then sprite row
+//   F4  the 64 oam sprites
+// pressing the key of the view that's up takes the panel down again.
+//
+// the ppu doesn't let anyone read back its scroll or control registers, so ScrollShadow keeps its own copy by
+// watching what the cpu writes to them, the same way the ppu's internal t and x latches would.
+use core::cell::Cell;
+use pc_keyboard::KeyCode;
+use vga::colors::Color16;
+
+use crate::emulation::construct::color16_from_u8;
+use crate::emulation::disasm::{self, Access};
+use crate::emulation::machine::{Machine, Register};
+use crate::emulation::panel::{self, Panel};
+use crate::emulation::trace::{effective_address, stored_value};
+
+// room for a title above whatever's drawn, and a margin to the left.
+const CONTENT_Y: usize = 16;
+const LEFT: usize = 8;
+
+// redrawing the panel through the planar vga writes is slow, a few times a second is plenty to watch it change.
+const REFRESH_FRAMES: u32 = 10;
+
+const PALETTE_RAM: u16 = 0x3F00;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum View
+{
+    PatternTables,
+    Nametables,
+    Palettes,
+    Oam,
+}
+
+// the ppu's t register, fine x and write toggle, as the cpu left them, plus PPUCTRL.
+pub struct ScrollShadow
+{
+    watching: Cell<bool>,
+    t: Cell<u16>,
+    fine_x: Cell<u8>,
+    w: Cell<bool>,
+    ctrl: Cell<u8>,
+}
+
+impl ScrollShadow
+{
+    pub fn new() -> ScrollShadow
+    {
+        ScrollShadow { watching: Cell::new(false), t: Cell::new(0), fine_x: Cell::new(0), w: Cell::new(false), ctrl: Cell::new(0) }
+    }
+
+    // called before every instruction, looking for ones that touch $2000, $2002, $2005 or $2006.
+    pub fn observe(&self, machine: &Machine)
+    {
+        if !self.watching.get() {
+            return;
+        }
+        let pc = machine.register(Register::PC);
+        let op = disasm::op(machine.peek(pc));
+        let access = op.access();
+        if access == Access::None {
+            return;
+        }
+        let addr = match effective_address(machine, pc) {
+            Some(a) if (0x2000..0x4000).contains(&a) => a & 0x2007,
+            _ => return,
+        };
+
+        if addr == 0x2002 && access != Access::Write {
+            self.w.set(false);
+            return;
+        }
+        let value = match stored_value(machine, pc) {
+            Some(v) => v,
+            None => return,
+        };
+        let (t, w) = (self.t.get(), self.w.get());
+        match addr {
+            0x2000 => {
+                self.ctrl.set(value);
+                self.t.set((t & 0xF3FF) | ((value as u16 & 0x03) << 10));
+            }
+            0x2005 if !w => {
+                self.t.set((t & 0xFFE0) | (value as u16 >> 3));
+                self.fine_x.set(value & 0x07);
+                self.w.set(true);
+            }
+            0x2005 => {
+                self.t.set((t & 0x8C1F) | ((value as u16 & 0x07) << 12) | ((value as u16 & 0xF8) << 2));
+                self.w.set(false);
+            }
+            0x2006 if !w => {
+                self.t.set((t & 0x80FF) | ((value as u16 & 0x3F) << 8));
+                self.w.set(true);
+            }
+            0x2006 => {
+                self.t.set((t & 0xFF00) | value as u16);
+                self.w.set(false);
+            }
+            _ => {}
+        }
+    }
+
+    // whether a viewer is up and needs observe() called.
+    pub fn watching(&self) -> bool
+    {
+        self.watching.get()
+    }
+
+    // the top left of the screen in the 512x480 space of all four nametables.
+    fn scroll(&self) -> (usize, usize)
+    {
+        let t = self.t.get() as usize;
+        let x = ((t & 0x1F) << 3) | self.fine_x.get() as usize;
+        let y = (((t >> 5) & 0x1F) << 3) | ((t >> 12) & 0x07);
+        (x + ((t >> 10) & 1) * 256, y + ((t >> 11) & 1) * 240)
+    }
+
+    fn ctrl(&self) -> u8
+    {
+        self.ctrl.get()
+    }
+}
+
+pub struct PpuViewer
+{
+    view: Option<View>,
+    frames: u32,
+    panel: Panel,
+}
+
+// one pixel of a tile, 0 to 3.
+fn tile_pixel(machine: &Machine, table: u16, tile: u8, x: usize, y: usize) -> u8
+{
+    let row = table + tile as u16 * 16 + (y & 7) as u16;
+    let (lo, hi) = (machine.ppu_peek(row), machine.ppu_peek(row + 8));
+    let bit = 7 - (x & 7);
+    ((lo >> bit) & 1) | (((hi >> bit) & 1) << 1)
+}
+
+fn palette_color(machine: &Machine, palette: u8, pixel: u8) -> Color16
+{
+    let entry = if pixel == 0 { 0 } else { palette as u16 * 4 + pixel as u16 };
+    color16_from_u8(machine.ppu_peek(PALETTE_RAM + entry))
+}
+
+impl PpuViewer
+{
+    pub fn new() -> PpuViewer
+    {
+        PpuViewer { view: None, frames: 0, panel: Panel::new() }
+    }
+
+    pub fn handle_key(&mut self, key: KeyCode, shadow: &ScrollShadow) -> bool
+    {
+        let view = match key {
+            KeyCode::F1 => View::PatternTables,
+            KeyCode::F2 => View::Nametables,
+            KeyCode::F3 => View::Palettes,
+            KeyCode::F4 => View::Oam,
+            _ => return false,
+        };
+        let showing = self.view == Some(view) && self.panel.owned_by(panel::PPU_VIEWER);
+        if showing {
+            self.view = None;
+            self.panel.release(panel::PPU_VIEWER);
+        } else {
+            self.view = Some(view);
+            self.panel.claim(panel::PPU_VIEWER);
+        }
+        shadow.watching.set(self.view.is_some());
+        // draw it on the next frame instead of waiting out the refresh.
+        self.frames = REFRESH_FRAMES;
+        true
+    }
+
+    // called once a frame.
+    pub fn frame(&mut self, machine: &Machine, shadow: &ScrollShadow)
+    {
+        let view = match self.view {
+            Some(v) if self.panel.owned_by(panel::PPU_VIEWER) => v,
+            _ => return,
+        };
+        self.frames += 1;
+        if self.frames < REFRESH_FRAMES {
+            return;
+        }
+        self.frames = 0;
+
+        match view {
+            View::PatternTables => {
+                self.title("PATTERN TABLES");
+                self.draw_pattern_table(machine, 0x0000, LEFT);
+                self.draw_pattern_table(machine, 0x1000, LEFT + 128 + 16);
+            }
+            View::Nametables => {
+                self.title("NAMETABLES");
+                self.draw_nametables(machine, shadow);
+            }
+            View::Palettes => {
+                self.title("PALETTES");
+                self.draw_palettes(machine);
+            }
+            View::Oam => {
+                self.title("OAM");
+                self.draw_oam(machine, shadow);
+            }
+        }
+    }
+
+    fn title(&mut self, text: &str)
+    {
+        self.panel.text(0, text, Color16::White);
+    }
+
+    fn draw_pattern_table(&self, machine: &Machine, table: u16, left: usize)
+    {
+        for tile in 0..=255u8
+        {
+            let (tx, ty) = ((tile % 16) as usize * 8, (tile / 16) as usize * 8);
+            for y in 0..8
+            {
+                for x in 0..8
+                {
+                    let color = palette_color(machine, 0, tile_pixel(machine, table, tile, x, y));
+                    self.panel.pixel(left + tx + x, CONTENT_Y + ty + y, color);
+                }
+            }
+        }
+    }
+
+    // every other pixel of all four nametables, so the whole 512x480 fits in 256x240.
+    fn draw_nametables(&self, machine: &Machine, shadow: &ScrollShadow)
+    {
+        let table = if shadow.ctrl() & 0x10 != 0 { 0x1000 } else { 0x0000 };
+        let left = LEFT;
+        for py in 0..240
+        {
+            for px in 0..256
+            {
+                let (x, y) = (px * 2, py * 2);
+                let nametable = 0x2000 + ((y / 240) * 2 + x / 256) as u16 * 0x400;
+                let (x, y) = (x % 256, y % 240);
+                let (tx, ty) = (x / 8, y / 8);
+                let tile = machine.ppu_peek(nametable + (ty * 32 + tx) as u16);
+                let attribute = machine.ppu_peek(nametable + 0x3C0 + ((ty / 4) * 8 + tx / 4) as u16);
+                let palette = (attribute >> (((ty % 4) / 2) * 4 + ((tx % 4) / 2) * 2)) & 0x03;
+                let color = palette_color(machine, palette, tile_pixel(machine, table, tile, x, y));
+                self.panel.pixel(left + px, CONTENT_Y + py, color);
+            }
+        }
+
+        // box the 256x240 on screen, wrapping round the edges of the nametables the way scrolling does.
+        let (sx, sy) = shadow.scroll();
+        let mark = |x: usize, y: usize| {
+            self.panel.pixel(left + (x % 512) / 2, CONTENT_Y + (y % 480) / 2, Color16::White)
+        };
+        for i in 0..256
+        {
+            mark(sx + i, sy);
+            mark(sx + i, sy + 239);
+        }
+        for i in 0..240
+        {
+            mark(sx, sy + i);
+            mark(sx + 255, sy + i);
+        }
+    }
+
+    fn draw_palettes(&self, machine: &Machine)
+    {
+        const SWATCH: usize = 20;
+        for entry in 0..32u16
+        {
+            let color = color16_from_u8(machine.ppu_peek(PALETTE_RAM + entry));
+            let (left, top) = (LEFT + (entry as usize % 16) * SWATCH, CONTENT_Y + (entry as usize / 16) * (SWATCH + 4));
+            for y in 0..SWATCH - 2
+            {
+                for x in 0..SWATCH - 2
+                {
+                    self.panel.pixel(left + x, top + y, color);
+                }
+            }
+        }
+    }
+
+    // an 8x8 grid of sprites at double size, each in its own palette.
+    fn draw_oam(&self, machine: &Machine, shadow: &ScrollShadow)
+    {
+        let ctrl = shadow.ctrl();
+        let tall = ctrl & 0x20 != 0;
+        let oam = machine.oam();
+        for sprite in 0..64u8
+        {
+            let tile = oam[sprite as usize * 4 + 1];
+            let attributes = oam[sprite as usize * 4 + 2];
+            let palette = 4 + (attributes & 0x03);
+            let (left, top) = (LEFT + (sprite % 8) as usize * 40, CONTENT_Y + (sprite / 8) as usize * 40);
+
+            let (table, first) = if tall {
+                // 8x16 sprites pick their pattern table with bit 0 of the tile number.
+                ((tile as u16 & 1) * 0x1000, tile & 0xFE)
+            } else {
+                (if ctrl & 0x08 != 0 { 0x1000 } else { 0x0000 }, tile)
+            };
+            let height = if tall { 16 } else { 8 };
+            for y in 0..height
+            {
+                for x in 0..8
+                {
+                    let pixel = tile_pixel(machine, table, first + (y / 8) as u8, x, y);
+                    let color = palette_color(machine, palette, pixel);
+                    for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)].iter()
+                    {
+                        self.panel.pixel(left + x * 2 + dx, top + y * 2 + dy, color);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,291 @@
+// ntsc, pal and dendy consoles, and how their clocks differ.
+// everything hangs off one master crystal. the ntsc cpu divides it by 12 and the ppu by 4, so 3 dots a cpu cycle,
+// 262 scanlines, ~60 frames a second. pal divides by 16 and 5, so 3.2 dots a cycle, 312 scanlines and 50 frames, and
+// its apu steps the frame counter on a longer table. dendy (the russian famiclone) has pal's crystal and scanlines
+// but ntsc's 3 dots a cycle and ntsc's apu, which is why ntsc games mostly run right on it, only slower.
+//
+// runes' ppu only knows ntsc's 262 lines, so Clock makes up the difference: it slips an extra dot in every 5th cycle
+// for pal's 3.2, and once the ppu starts vblank it holds it there for the missing 50 lines while the cpu and apu
+// carry on. games only see vblank get longer, which is all pal's extra lines are anyway.
+// the apu's frame counter is inside runes too, on the ntsc table, and it can't be given another. so on pal Clock runs
+// its own off the region's table and the frame irq comes from that, at the cycles a pal console raises it. runes'
+// counter still clocks the envelopes, sweeps and note lengths, so those run about 10% fast on pal, and what runes'
+// apu tick says about irqs is dropped there, since its frame irq is the ntsc one. that takes the dmc's with it.
+extern crate alloc;
+
+use alloc::rc::Rc;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::emulation::disasm::{self, Access};
+use crate::emulation::irq::{IrqLine, Source};
+use crate::emulation::machine::{Machine, Register};
+use crate::emulation::trace::{effective_address, stored_value};
+use crate::emulation::APU_SAMPLE_RATE;
+use crate::{cmdline, println};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Region
+{
+    Ntsc,
+    Pal,
+    Dendy,
+}
+
+// the ppu's dots in a scanline, the same everywhere.
+const DOTS_PER_LINE: u32 = 341;
+// what runes' ppu draws a frame in.
+const NTSC_LINES: u32 = 262;
+
+pub struct Timing
+{
+    pub cpu_hz: u32,
+    // ppu dots per cpu cycle, as a fraction.
+    pub dots: (u32, u32),
+    pub scanlines: u32,
+    // the cpu cycle each of the apu frame counter's steps lands on, counting from a $4017 write. the 4 step sequence
+    // ends on the 4th, the 5 step one on the 5th.
+    pub frame_counter: [u32; 5],
+}
+
+impl Region
+{
+    pub fn timing(self) -> Timing
+    {
+        match self {
+            Region::Ntsc => Timing {
+                cpu_hz: 1_789_773,
+                dots: (3, 1),
+                scanlines: 262,
+                frame_counter: [7457, 14913, 22371, 29829, 37281],
+            },
+            Region::Pal => Timing {
+                cpu_hz: 1_662_607,
+                dots: (16, 5),
+                scanlines: 312,
+                frame_counter: [8313, 16627, 24939, 33253, 41565],
+            },
+            Region::Dendy => Timing {
+                cpu_hz: 1_773_448,
+                dots: (3, 1),
+                scanlines: 312,
+                frame_counter: [7457, 14913, 22371, 29829, 37281],
+            },
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Region>
+    {
+        match value {
+            "ntsc" => Some(Region::Ntsc),
+            "pal" => Some(Region::Pal),
+            "dendy" => Some(Region::Dendy),
+            _ => None,
+        }
+    }
+
+    // nes 2.0's byte 12. 2 is "runs on either", which gets ntsc.
+    pub fn from_nes2(timing: u8) -> Region
+    {
+        match timing & 0x03 {
+            1 => Region::Pal,
+            3 => Region::Dendy,
+            _ => Region::Ntsc,
+        }
+    }
+
+    // the region boot option beats whatever the header and the rom database said.
+    pub fn choose(self) -> Region
+    {
+        let option = match cmdline::get("region") {
+            Some(value) => value,
+            None => return self,
+        };
+        match Region::parse(option) {
+            Some(region) => region,
+            None => {
+                println!("unknown region {}, keeping {:?}", option, self);
+                self
+            }
+        }
+    }
+}
+
+impl Timing
+{
+    // runes makes a sample every fixed number of cpu cycles, worked out for the ntsc clock. on a slower clock there
+    // are fewer of them a second, and telling the resampler so is what keeps the pitch and the speed right, since
+    // the audio is what paces the emulator.
+    pub fn apu_sample_rate(&self) -> u32
+    {
+        (APU_SAMPLE_RATE as u64 * self.cpu_hz as u64 / Region::Ntsc.timing().cpu_hz as u64) as u32
+    }
+
+    pub fn frames_per_second(&self) -> u32
+    {
+        let dots_per_frame = (DOTS_PER_LINE * self.scanlines) as u64;
+        (self.cpu_hz as u64 * self.dots.0 as u64 / (self.dots.1 as u64 * dots_per_frame)) as u32
+    }
+}
+
+// run ntsc's 3 dots through the bus tick instead of Machine::tick, so the harness can check they agree. the apu's
+// irq skips the irq line on the bus tick, which the harness's roms never turn on.
+static BY_BUS: AtomicBool = AtomicBool::new(false);
+
+pub fn tick_by_bus(on: bool)
+{
+    BY_BUS.store(on, Ordering::Relaxed);
+}
+
+// the apu's frame counter, as far as its irq goes. see the top of the file.
+pub struct FrameCounter
+{
+    steps: [u32; 5],
+    // cycles since the sequence started.
+    cycle: u32,
+    five_step: bool,
+    // $4017 bit 6, no irq.
+    inhibit: bool,
+}
+
+impl FrameCounter
+{
+    pub fn new(timing: &Timing) -> FrameCounter
+    {
+        FrameCounter { steps: timing.frame_counter, cycle: 0, five_step: false, inhibit: false }
+    }
+
+    // a cpu cycle. gives back which step landed on it, if one did.
+    pub fn tick(&mut self) -> Option<usize>
+    {
+        self.cycle += 1;
+        let step = self.steps.iter().position(|&at| at == self.cycle);
+        let last = if self.five_step { 4 } else { 3 };
+        if step == Some(last) {
+            self.cycle = 0;
+        }
+        step
+    }
+
+    // whether the step is the one that raises the irq.
+    pub fn irq(&self, step: usize) -> bool
+    {
+        step == 3 && !self.five_step && !self.inhibit
+    }
+
+    // $4017 starts the sequence over.
+    pub fn write(&mut self, value: u8)
+    {
+        self.five_step = value & 0x80 != 0;
+        self.inhibit = value & 0x40 != 0;
+        self.cycle = 0;
+    }
+}
+
+// drives the machine a cpu cycle at a time, in place of the bus tick.
+pub struct Clock
+{
+    dots: (u32, u32),
+    // the lines runes' ppu doesn't have, in dots times dots.1 so pal's fractions add up.
+    extra: u32,
+    // leftover dots from the fraction, in the same units.
+    phase: u32,
+    // how much longer the ppu is being held in vblank, in the same units again.
+    held: u32,
+    // where the apu's irq goes, see irq.rs.
+    line: Rc<IrqLine>,
+    // our own frame counter, when the region's table isn't the ntsc one runes has.
+    frame_counter: Option<FrameCounter>,
+}
+
+impl Clock
+{
+    pub fn new(timing: &Timing, line: Rc<IrqLine>) -> Clock
+    {
+        let extra = (timing.scanlines - NTSC_LINES) * DOTS_PER_LINE * timing.dots.1;
+        let frame_counter = if timing.frame_counter != Region::Ntsc.timing().frame_counter {
+            Some(FrameCounter::new(timing))
+        } else {
+            None
+        };
+        Clock { dots: timing.dots, extra, phase: 0, held: 0, line, frame_counter }
+    }
+
+    pub fn tick(&mut self, machine: &Machine)
+    {
+        let (num, den) = self.dots;
+        let dots = if self.held >= num {
+            self.held -= num;
+            0
+        } else {
+            self.phase += num;
+            let dots = self.phase / den;
+            self.phase %= den;
+            dots
+        };
+        if dots == 3 && BY_BUS.load(Ordering::Relaxed) {
+            machine.cpu().mem.bus.tick();
+            return;
+        }
+        let apu_irq = machine.tick(dots);
+        let irq = match self.frame_counter.as_mut() {
+            Some(counter) => counter.tick().is_some_and(|step| counter.irq(step)),
+            None => apu_irq,
+        };
+        if irq {
+            self.line.set(Source::Apu, true);
+        }
+    }
+
+    // called before every instruction, for the $4017 writes our frame counter needs to see.
+    pub fn observe(&mut self, machine: &Machine)
+    {
+        let counter = match self.frame_counter.as_mut() {
+            Some(counter) => counter,
+            None => return,
+        };
+        let pc = machine.register(Register::PC);
+        if disasm::op(machine.peek(pc)).access() != Access::Write || effective_address(machine, pc) != Some(0x4017) {
+            return;
+        }
+        if let Some(value) = stored_value(machine, pc) {
+            counter.write(value);
+        }
+    }
+
+    // the ppu just finished drawing. anything short of a whole cycle carries over to next frame.
+    pub fn frame(&mut self)
+    {
+        self.held += self.extra;
+    }
+}
+
+#[test_case]
+fn test_region_timing()
+{
+    assert_eq!(Region::from_nes2(0x01), Region::Pal);
+    assert_eq!(Region::from_nes2(0x02), Region::Ntsc);
+    assert_eq!(Region::from_nes2(0x03), Region::Dendy);
+    assert_eq!(Region::Ntsc.timing().frames_per_second(), 60);
+    assert_eq!(Region::Pal.timing().frames_per_second(), 50);
+    assert_eq!(Region::Dendy.timing().frames_per_second(), 50);
+    assert_eq!(Region::Ntsc.timing().apu_sample_rate(), APU_SAMPLE_RATE);
+    assert!(Region::Pal.timing().apu_sample_rate() < Region::Dendy.timing().apu_sample_rate());
+    // pal holds the ppu 50 lines at 3.2 dots a cycle, a bit over 5328 cycles.
+    let clock = Clock::new(&Region::Pal.timing(), IrqLine::new());
+    assert_eq!(clock.extra / clock.dots.0, 5328);
+    assert_eq!(Clock::new(&Region::Ntsc.timing(), IrqLine::new()).extra, 0);
+    // pal's frame counter steps, and the irq on the last of them.
+    let mut counter = FrameCounter::new(&Region::Pal.timing());
+    let mut steps = [0; 4];
+    for cycle in 1..=33253
+    {
+        if let Some(step) = counter.tick() {
+            steps[step] = cycle;
+            assert_eq!(counter.irq(step), step == 3);
+        }
+    }
+    assert_eq!(steps, [8313, 16627, 24939, 33253]);
+    assert_eq!(counter.tick(), None);
+    assert!(Clock::new(&Region::Pal.timing(), IrqLine::new()).frame_counter.is_some());
+    assert!(Clock::new(&Region::Dendy.timing(), IrqLine::new()).frame_counter.is_none());
+}
@@ -0,0 +1,256 @@
+// rewind. every few frames the whole machine gets snapshotted, and holding backspace walks back through the
+// snapshots, holding each one for as many frames as it was apart from the next, so it goes back at the speed it
+// went forward.
+//
+// a full snapshot is ~20KiB, but two snapshots a few frames apart are almost all the same bytes. so we only keep
+// the newest one whole, and every older one as the difference from the one after it: xor the two, and the result
+// is nearly all zeros, which run length encodes down to almost nothing. stepping back is then just xoring the
+// newest snapshot with the most recent delta.
+// the oldest deltas are only needed to go further back, so when we run out of room they just fall off the end.
+// the cart's chr rom isn't in the snapshots (SimpleCart only saves chr ram), so they're about the same size for
+// every game.
+extern crate alloc;
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+use crate::allocator::HEAP_SIZE;
+use crate::cmdline;
+use crate::emulation::machine::{Machine, SliceReader, VecWriter};
+use crate::serial_println;
+
+// don't let rewind take more than this much of the heap: the deltas, the newest snapshot, and the new one being taken
+// while the newest is still around. the rom, save slots and everything else need the rest.
+const REWIND_BUDGET: usize = HEAP_SIZE / 4;
+
+// frames between snapshots unless the "rewind" boot option says otherwise. 0 turns rewind off.
+const DEFAULT_INTERVAL: u32 = 2;
+
+pub struct Rewind
+{
+    interval: u32,
+    frames: u32,
+    // frames left holding the snapshot we last stepped back to, while rewinding.
+    held: u32,
+    // the newest snapshot, whole.
+    latest: Option<Vec<u8>>,
+    // xor+rle deltas, newest at the back. applying the back one to `latest` gives the snapshot before it.
+    deltas: VecDeque<Vec<u8>>,
+    used: usize,
+}
+
+// zero runs and literal runs, alternating, each prefixed with its length as a varint.
+// starts with a zero run, which may be empty.
+fn write_varint(out: &mut Vec<u8>, mut n: usize)
+{
+    loop
+    {
+        let byte = (n & 0x7F) as u8;
+        n >>= 7;
+        if n == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> Option<usize>
+{
+    let mut n = 0;
+    let mut shift = 0;
+    loop
+    {
+        let byte = *data.get(*pos)?;
+        *pos += 1;
+        n |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Some(n);
+        }
+        shift += 7;
+    }
+}
+
+// rle(a xor b). a and b have to be the same length.
+fn encode_delta(a: &[u8], b: &[u8]) -> Vec<u8>
+{
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < a.len()
+    {
+        let zeros_start = i;
+        while i < a.len() && a[i] == b[i] {
+            i += 1;
+        }
+        write_varint(&mut out, i - zeros_start);
+
+        let literal_start = i;
+        while i < a.len() && a[i] != b[i] {
+            i += 1;
+        }
+        write_varint(&mut out, i - literal_start);
+        for j in literal_start..i
+        {
+            out.push(a[j] ^ b[j]);
+        }
+    }
+    out
+}
+
+// xor a delta back into a snapshot in place.
+fn apply_delta(state: &mut [u8], delta: &[u8]) -> bool
+{
+    let mut pos = 0;
+    let mut i = 0;
+    while pos < delta.len()
+    {
+        let zeros = match read_varint(delta, &mut pos) {
+            Some(n) => n,
+            None => return false,
+        };
+        i += zeros;
+        let literal = match read_varint(delta, &mut pos) {
+            Some(n) => n,
+            None => return false,
+        };
+        if i + literal > state.len() || pos + literal > delta.len() {
+            return false;
+        }
+        for j in 0..literal
+        {
+            state[i + j] ^= delta[pos + j];
+        }
+        i += literal;
+        pos += literal;
+    }
+    true
+}
+
+impl Rewind
+{
+    pub fn new() -> Rewind
+    {
+        let interval = cmdline::get("rewind")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_INTERVAL);
+        Rewind { interval, frames: 0, held: 0, latest: None, deltas: VecDeque::new(), used: 0 }
+    }
+
+    pub fn enabled(&self) -> bool
+    {
+        self.interval != 0
+    }
+
+    // how many steps back we can go right now.
+    pub fn depth(&self) -> usize
+    {
+        self.deltas.len()
+    }
+
+    fn snapshot(&mut self, machine: &Machine)
+    {
+        let size = self.latest.as_ref().map_or(0, |l| l.len());
+        let mut writer = VecWriter(Vec::with_capacity(size));
+        if !machine.save(&mut writer) {
+            return;
+        }
+        let mut state = writer.0;
+        state.shrink_to_fit();
+        // two whole snapshots at once, this one and the last, and the deltas get what's left.
+        if state.len() * 2 > REWIND_BUDGET {
+            serial_println!("rewind: a {} byte snapshot doesn't fit, turning rewind off", state.len());
+            self.interval = 0;
+            self.latest = None;
+            self.deltas.clear();
+            self.used = 0;
+            return;
+        }
+
+        if let Some(latest) = self.latest.take() {
+            if latest.len() == state.len() {
+                let mut delta = encode_delta(&latest, &state);
+                delta.shrink_to_fit();
+                self.used += delta.len();
+                self.deltas.push_back(delta);
+            } else {
+                // the state changed shape, there's no going back past this.
+                self.deltas.clear();
+                self.used = 0;
+            }
+        }
+        let room = REWIND_BUDGET - state.len() * 2;
+        self.latest = Some(state);
+
+        while self.used > room
+        {
+            match self.deltas.pop_front() {
+                Some(oldest) => self.used -= oldest.len(),
+                None => break,
+            }
+        }
+    }
+
+    fn step_back(&mut self, machine: &Machine) -> bool
+    {
+        let (latest, delta) = match (self.latest.as_mut(), self.deltas.pop_back()) {
+            (Some(l), Some(d)) => (l, d),
+            _ => return false,
+        };
+        self.used -= delta.len();
+
+        if !apply_delta(latest, &delta) {
+            serial_println!("rewind: corrupt delta, dropping history");
+            self.deltas.clear();
+            self.used = 0;
+            return false;
+        }
+        machine.load(&mut SliceReader::new(latest))
+    }
+
+    // back to the snapshot we're already at, to hold it there another frame.
+    fn hold(&self, machine: &Machine) -> bool
+    {
+        match &self.latest {
+            Some(latest) => machine.load(&mut SliceReader::new(latest)),
+            None => false,
+        }
+    }
+
+    // called once a frame. returns true if it rewound the machine this frame.
+    pub fn frame(&mut self, machine: &Machine, rewinding: bool) -> bool
+    {
+        if !self.enabled() {
+            return false;
+        }
+
+        if rewinding {
+            self.frames = 0;
+            if self.held > 0 {
+                self.held -= 1;
+                return self.hold(machine);
+            }
+            self.held = self.interval - 1;
+            return self.step_back(machine);
+        }
+        self.held = 0;
+
+        self.frames += 1;
+        if self.frames >= self.interval
+        {
+            self.frames = 0;
+            self.snapshot(machine);
+        }
+        false
+    }
+}
+
+#[test_case]
+fn test_delta_roundtrip()
+{
+    let a = [1u8, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+    let b = [1u8, 2, 0, 4, 5, 6, 7, 0, 0, 10];
+    let delta = encode_delta(&a, &b);
+    let mut restored = b;
+    assert!(apply_delta(&mut restored, &delta));
+    assert_eq!(restored, a);
+}
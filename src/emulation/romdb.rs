@@ -0,0 +1,158 @@
+// fixes for roms whose ines headers are wrong, which is a lot of them: old dumps guessed at mapper numbers and
+// mirroring, and plain ines has nowhere to put submappers or the region.
+// entries are keyed by the crc32 of the prg and chr, the same one Rom::crc holds, so a fixed header doesn't stop an
+// entry matching. there's a list built in (romdb.txt next to this file), and a romdb.txt on the rom disk can add more
+// or replace built in ones without a rebuild. a line per game:
+//   <crc32> [mapper=n] [submapper=n] [mirror=horizontal|vertical|four] [battery=yes|no] [region=ntsc|pal|dendy]
+// and a # starts a comment.
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use runes::cartridge::MirrorType;
+
+use crate::emulation::{Region, Rom};
+use crate::{println, romdisk};
+
+const BUILT_IN: &str = include_str!("romdb.txt");
+
+#[derive(Clone, Copy, Default)]
+pub struct Overrides
+{
+    pub mapper: Option<u8>,
+    pub submapper: Option<u8>,
+    pub mirror: Option<MirrorType>,
+    pub battery: Option<bool>,
+    pub region: Option<Region>,
+}
+
+fn parse_mirror(value: &str) -> Option<MirrorType>
+{
+    match value {
+        "horizontal" => Some(MirrorType::Horizontal),
+        "vertical" => Some(MirrorType::Vertical),
+        "four" => Some(MirrorType::Four),
+        _ => None,
+    }
+}
+
+// the entry for crc in one database, if it has one.
+fn find(db: &str, crc: u32) -> Option<Overrides>
+{
+    for line in db.lines()
+    {
+        let line = line.split('#').next().unwrap_or("");
+        let mut words = line.split_whitespace();
+        match words.next().and_then(|w| u32::from_str_radix(w, 16).ok()) {
+            Some(c) if c == crc => {}
+            _ => continue,
+        }
+
+        let mut overrides = Overrides::default();
+        for word in words
+        {
+            let (key, value) = match word.split_once('=') {
+                Some(kv) => kv,
+                None => continue,
+            };
+            match key {
+                "mapper" => overrides.mapper = value.parse().ok(),
+                "submapper" => overrides.submapper = value.parse().ok(),
+                "mirror" => overrides.mirror = parse_mirror(value),
+                "battery" => overrides.battery = Some(value == "yes"),
+                "region" => overrides.region = Region::parse(value),
+                _ => println!("romdb: unknown key {}", key),
+            }
+        }
+        return Some(overrides);
+    }
+    None
+}
+
+pub fn lookup(crc: u32) -> Option<Overrides>
+{
+    let on_disk = romdisk::read("romdb.txt").and_then(|data| String::from_utf8(data).ok());
+    on_disk.as_deref().and_then(|db| find(db, crc)).or_else(|| find(BUILT_IN, crc))
+}
+
+// fix up the rom from the database before anything gets built from it, saying what changed.
+pub fn apply(rom: &mut Rom)
+{
+    if let Some(overrides) = lookup(rom.crc) {
+        for change in fix(rom, &overrides)
+        {
+            println!("romdb: {}", change);
+        }
+    }
+}
+
+// make the rom match the entry, returning a line for each thing that was different.
+fn fix(rom: &mut Rom, overrides: &Overrides) -> Vec<String>
+{
+    let mut changes = Vec::new();
+    if let Some(mapper) = overrides.mapper.filter(|&m| m != rom.mapper_id) {
+        changes.push(format!("mapper {} -> {}", rom.mapper_id, mapper));
+        rom.mapper_id = mapper;
+    }
+    if let Some(submapper) = overrides.submapper.filter(|&s| s != rom.submapper) {
+        changes.push(format!("submapper {} -> {}", rom.submapper, submapper));
+        rom.submapper = submapper;
+    }
+    if let Some(mirror) = overrides.mirror.filter(|&m| m as u8 != rom.mirror as u8) {
+        changes.push(format!("mirroring {} -> {}", rom.mirror as u8, mirror as u8));
+        rom.mirror = mirror;
+    }
+    if let Some(battery) = overrides.battery.filter(|&b| b != rom.battery) {
+        changes.push(format!("battery {} -> {}", rom.battery, battery));
+        rom.battery = battery;
+        rom.prg_ram |= battery;
+    }
+    if let Some(region) = overrides.region.filter(|&r| r != rom.region) {
+        changes.push(format!("region {:?} -> {:?}", rom.region, region));
+        rom.region = region;
+    }
+    changes
+}
+
+#[test_case]
+fn test_romdb_lines()
+{
+    let db = "# comment\n0badf00d mapper=4\n12345678 mapper=66 mirror=vertical battery=yes region=pal # a game\n";
+    let o = find(db, 0x12345678).unwrap();
+    assert_eq!(o.mapper, Some(66));
+    assert_eq!(o.mirror.map(|m| m as u8), Some(MirrorType::Vertical as u8));
+    assert_eq!(o.battery, Some(true));
+    assert_eq!(o.region, Some(Region::Pal));
+    assert!(o.submapper.is_none());
+    assert!(find(db, 0x87654321).is_none());
+}
+
+#[test_case]
+fn test_romdb_fixes_rom()
+{
+    // a discrete board whose header got the mirroring backwards.
+    let db = "0badf00d mapper=2 mirror=horizontal\n";
+    let overrides = find(db, 0x0badf00d).unwrap();
+    let mut rom = Rom {
+        prg_rom: Vec::new(),
+        chr_rom: Vec::new(),
+        chr_ram: false,
+        sram: Vec::new(),
+        mirror: MirrorType::Vertical,
+        mapper_id: 2,
+        submapper: 0,
+        battery: false,
+        region: Region::Ntsc,
+        prg_ram: true,
+        crc: 0x0badf00d,
+        disk: None,
+    };
+    let changes = fix(&mut rom, &overrides);
+    assert_eq!(rom.mirror as u8, MirrorType::Horizontal as u8);
+    assert_eq!(rom.mapper_id, 2);
+    assert_eq!(changes.len(), 1);
+    assert!(changes[0].starts_with("mirroring"));
+    // once it's right there's nothing left to say.
+    assert!(fix(&mut rom, &overrides).is_empty());
+}
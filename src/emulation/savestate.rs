@@ -0,0 +1,214 @@
+// numbered save state slots.
+//   F5 saves the machine into the current slot, F9 loads it back.
+//   F6 and F7 step the current slot down and up.
+// states live in memory, and with the "statedisk" boot option they're also written through to the rom disk as
+// <crc32 of the rom>.st<slot>, where F9 will find them again after a reboot.
+//
+// every state starts with a header saying which game and slot it came from, so loading a state from another game
+// (or an older layout) gets refused instead of scrambling the machine.
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use pc_keyboard::KeyCode;
+
+use crate::emulation::machine::{Machine, SliceReader, VecWriter};
+use crate::{cmdline, println, romdisk, rtc, serial_println};
+
+const MAGIC: &[u8; 8] = b"NESOSSAV";
+// bump this whenever what Machine::save writes changes shape. 2 left chr rom out, 3 gave vrc2/vrc4 carts their chip.
+const VERSION: u16 = 3;
+const HEADER_SIZE: usize = 28;
+
+pub const SLOT_COUNT: usize = 10;
+
+#[derive(Debug, Clone, Copy)]
+pub struct StateHeader
+{
+    pub version: u16,
+    pub slot: u8,
+    pub rom_crc: u32,
+    // unix seconds, off the cmos clock.
+    pub timestamp: u64,
+    pub length: u32,
+}
+
+impl StateHeader
+{
+    fn encode(&self, out: &mut Vec<u8>)
+    {
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&self.version.to_le_bytes());
+        out.push(self.slot);
+        out.push(0);
+        out.extend_from_slice(&self.rom_crc.to_le_bytes());
+        out.extend_from_slice(&self.timestamp.to_le_bytes());
+        out.extend_from_slice(&self.length.to_le_bytes());
+    }
+
+    fn decode(data: &[u8]) -> Option<StateHeader>
+    {
+        if data.len() < HEADER_SIZE || &data[..8] != MAGIC {
+            return None;
+        }
+        let u16_at = |at: usize| u16::from_le_bytes([data[at], data[at + 1]]);
+        let u32_at = |at: usize| u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]);
+        let u64_at = |at: usize| u32_at(at) as u64 | (u32_at(at + 4) as u64) << 32;
+        Some(StateHeader {
+            version: u16_at(8),
+            slot: data[10],
+            rom_crc: u32_at(12),
+            timestamp: u64_at(16),
+            length: u32_at(24),
+        })
+    }
+}
+
+// the machine with a header in front, ready to keep or write out.
+pub fn capture(machine: &Machine, rom_crc: u32, slot: u8) -> Option<Vec<u8>>
+{
+    let mut body = VecWriter(Vec::new());
+    if !machine.save(&mut body) {
+        return None;
+    }
+
+    let header = StateHeader {
+        version: VERSION,
+        slot,
+        rom_crc,
+        timestamp: rtc::unix_time(),
+        length: body.0.len() as u32,
+    };
+    let mut state = Vec::with_capacity(HEADER_SIZE + body.0.len());
+    header.encode(&mut state);
+    state.extend_from_slice(&body.0);
+    Some(state)
+}
+
+#[derive(Debug)]
+pub enum RestoreError
+{
+    BadHeader,
+    WrongVersion(u16),
+    WrongRom(u32),
+    WrongSlot(u8),
+    Truncated,
+    LoadFailed,
+}
+
+// check a state belongs to this game and slot, then load it.
+// if the load falls over halfway, put the machine back how it was.
+pub fn restore(machine: &Machine, rom_crc: u32, slot: u8, state: &[u8]) -> Result<StateHeader, RestoreError>
+{
+    let header = StateHeader::decode(state).ok_or(RestoreError::BadHeader)?;
+    if header.version != VERSION {
+        return Err(RestoreError::WrongVersion(header.version));
+    }
+    if header.rom_crc != rom_crc {
+        return Err(RestoreError::WrongRom(header.rom_crc));
+    }
+    // a file copied over from another slot, or a header that's been scribbled on.
+    if header.slot != slot || header.slot as usize >= SLOT_COUNT {
+        return Err(RestoreError::WrongSlot(header.slot));
+    }
+    let body = &state[HEADER_SIZE..];
+    if body.len() != header.length as usize {
+        return Err(RestoreError::Truncated);
+    }
+
+    let mut backup = VecWriter(Vec::new());
+    let have_backup = machine.save(&mut backup);
+
+    if machine.load(&mut SliceReader::new(body)) {
+        return Ok(header);
+    }
+    if have_backup {
+        machine.load(&mut SliceReader::new(&backup.0));
+    }
+    Err(RestoreError::LoadFailed)
+}
+
+pub struct SaveSlots
+{
+    rom_crc: u32,
+    current: u8,
+    slots: [Option<Vec<u8>>; SLOT_COUNT],
+    to_disk: bool,
+}
+
+impl SaveSlots
+{
+    pub fn new(rom_crc: u32) -> SaveSlots
+    {
+        SaveSlots {
+            rom_crc,
+            current: 0,
+            slots: Default::default(),
+            to_disk: cmdline::flag("statedisk"),
+        }
+    }
+
+    fn file_name(&self, slot: u8) -> String
+    {
+        format!("{:08x}.st{}", self.rom_crc, slot)
+    }
+
+    pub fn save(&mut self, machine: &Machine)
+    {
+        let slot = self.current;
+        let state = match capture(machine, self.rom_crc, slot) {
+            Some(s) => s,
+            None => {
+                println!("couldn't save the machine state");
+                return;
+            }
+        };
+
+        if self.to_disk && !romdisk::write(&self.file_name(slot), &state) {
+            serial_println!("couldn't write state {} to the rom disk", slot);
+        }
+        serial_println!("saved state {} ({} bytes)", slot, state.len());
+        self.slots[slot as usize] = Some(state);
+    }
+
+    pub fn load(&mut self, machine: &Machine)
+    {
+        let slot = self.current;
+        if self.slots[slot as usize].is_none() {
+            self.slots[slot as usize] = romdisk::read(&self.file_name(slot));
+        }
+
+        let state = match &self.slots[slot as usize] {
+            Some(s) => s,
+            None => {
+                serial_println!("state {} is empty", slot);
+                return;
+            }
+        };
+
+        match restore(machine, self.rom_crc, slot, state) {
+            Ok(header) => serial_println!("loaded state {} from {}", slot, header.timestamp),
+            Err(e) => serial_println!("refusing state {}: {:?}", slot, e),
+        }
+    }
+
+    // returns whether the key was one of ours.
+    pub fn handle_key(&mut self, key: KeyCode, machine: &Machine) -> bool
+    {
+        match key {
+            KeyCode::F5 => self.save(machine),
+            KeyCode::F9 => self.load(machine),
+            KeyCode::F6 => {
+                self.current = (self.current + SLOT_COUNT as u8 - 1) % SLOT_COUNT as u8;
+                serial_println!("state slot {}", self.current);
+            }
+            KeyCode::F7 => {
+                self.current = (self.current + 1) % SLOT_COUNT as u8;
+                serial_println!("state slot {}", self.current);
+            }
+            _ => return false,
+        }
+        true
+    }
+}
@@ -0,0 +1,75 @@
+// battery backed save ram. carts with bit 1 of flags 6 set had a coin cell keeping their $6000-$7fff ram alive,
+// which is where zelda and final fantasy keep their save files.
+// we keep it on the rom disk instead, as <crc32 of the rom>.sav, so a save follows the game and not the file name.
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::{println, romdisk, serial_println};
+
+// how often to look for changes to write back, about every two seconds.
+// games write sram a byte at a time, so this also batches a whole save into one disk write.
+const FLUSH_INTERVAL_FRAMES: u32 = 120;
+
+pub struct BatterySave
+{
+    file: String,
+    // the cart's live sram.
+    sram: &'static [u8],
+    // what's on disk right now.
+    saved: Vec<u8>,
+    frames: u32,
+}
+
+pub fn save_name(rom_crc: u32) -> String
+{
+    format!("{:08x}.sav", rom_crc)
+}
+
+// fill the cart's sram from its save file, if there is one.
+pub fn load(rom_crc: u32, sram: &mut [u8])
+{
+    let file = save_name(rom_crc);
+    match romdisk::read(&file) {
+        Some(data) if data.len() == sram.len() => {
+            sram.copy_from_slice(&data);
+            println!("loaded battery save {}", file);
+        }
+        Some(data) => serial_println!("ignoring {}, it's {} bytes and the sram is {}", file, data.len(), sram.len()),
+        None => println!("no battery save for this game yet"),
+    }
+}
+
+impl BatterySave
+{
+    pub fn new(rom_crc: u32, sram: &'static [u8]) -> BatterySave
+    {
+        BatterySave { file: save_name(rom_crc), sram, saved: sram.to_vec(), frames: 0 }
+    }
+
+    // called once a frame.
+    pub fn frame(&mut self)
+    {
+        self.frames += 1;
+        if self.frames >= FLUSH_INTERVAL_FRAMES
+        {
+            self.frames = 0;
+            self.flush();
+        }
+    }
+
+    // write the sram out if the game changed it since last time.
+    pub fn flush(&mut self)
+    {
+        if self.saved.as_slice() == self.sram {
+            return;
+        }
+
+        if romdisk::write(&self.file, self.sram) {
+            self.saved.copy_from_slice(self.sram);
+            serial_println!("wrote battery save {}", self.file);
+        }
+    }
+}
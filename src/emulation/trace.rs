@@ -0,0 +1,184 @@
+// cpu traces in nintendulator's format, which is what nestest.log is and what most other emulators can spit out,
+// so a trace from here can be diffed against any of them:
+//   C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD CYC:7
+// with the "trace" boot option every instruction goes out over serial like this.
+// there's no PPU:scanline,dot column. runes keeps where the ppu is to itself, and working it out from the cycle count
+// goes wrong as soon as rendering skips a dot or the region isn't 3 dots a cycle, so it's left out rather than guessed.
+// comparisons against logs that have it skip over it.
+// operands on $2000-$5FFF don't get an "= xx", reading those to find out would change them. see Machine::peek.
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+
+use crate::emulation::disasm::{self, Mode};
+use crate::emulation::machine::{is_register, Machine};
+
+fn peek_word_zp(machine: &Machine, addr: u8) -> u16
+{
+    machine.peek(addr as u16) as u16 | (machine.peek(addr.wrapping_add(1) as u16) as u16) << 8
+}
+
+// the address the instruction at pc is about to touch, for the modes that touch one.
+pub fn effective_address(machine: &Machine, pc: u16) -> Option<u16>
+{
+    let cpu = machine.cpu();
+    let (x, y) = (cpu.get_x() as u16, cpu.get_y() as u16);
+    let opcode = machine.peek(pc);
+    let lo = machine.peek(pc.wrapping_add(1));
+    let word = lo as u16 | (machine.peek(pc.wrapping_add(2)) as u16) << 8;
+    match disasm::op(opcode).mode {
+        Mode::ZeroPage => Some(lo as u16),
+        Mode::ZeroPageX => Some(lo.wrapping_add(x as u8) as u16),
+        Mode::ZeroPageY => Some(lo.wrapping_add(y as u8) as u16),
+        Mode::Absolute => Some(word),
+        Mode::AbsoluteX => Some(word.wrapping_add(x)),
+        Mode::AbsoluteY => Some(word.wrapping_add(y)),
+        Mode::IndirectX => Some(peek_word_zp(machine, lo.wrapping_add(x as u8))),
+        Mode::IndirectY => Some(peek_word_zp(machine, lo).wrapping_add(y)),
+        _ => None,
+    }
+}
+
+// the byte the instruction at pc is about to store, if it's a store.
+pub fn stored_value(machine: &Machine, pc: u16) -> Option<u8>
+{
+    let cpu = machine.cpu();
+    match disasm::op(machine.peek(pc)).name {
+        "STA" => Some(cpu.get_a()),
+        "STX" => Some(cpu.get_x()),
+        "STY" => Some(cpu.get_y()),
+        "SAX" => Some(cpu.get_a() & cpu.get_x()),
+        _ => None,
+    }
+}
+
+// the operand with what it points at, the way nintendulator shows it: "$0300,X @ 0300 = 89".
+// values are read before the instruction runs, so stores show what's about to be overwritten.
+fn annotated_operand(machine: &Machine, pc: u16, opcode: u8, operands: &[u8]) -> String
+{
+    let cpu = machine.cpu();
+    let (x, y) = (cpu.get_x(), cpu.get_y());
+    let lo = operands.get(0).copied().unwrap_or(0);
+    let hi = operands.get(1).copied().unwrap_or(0);
+    let word = lo as u16 | (hi as u16) << 8;
+    let op = disasm::op(opcode);
+    let plain = disasm::operand(pc, opcode, operands);
+
+    // what's at addr, or nothing if looking would disturb it.
+    let value = |addr: u16| if is_register(addr) { String::new() } else { format!(" = {:02X}", machine.peek(addr)) };
+
+    match op.mode {
+        Mode::ZeroPage => format!("{} = {:02X}", plain, machine.peek(lo as u16)),
+        Mode::ZeroPageX | Mode::ZeroPageY => {
+            let index = if op.mode == Mode::ZeroPageX { x } else { y };
+            let addr = lo.wrapping_add(index) as u16;
+            format!("{} @ {:02X} = {:02X}", plain, addr, machine.peek(addr))
+        }
+        Mode::Absolute if op.name == "JMP" || op.name == "JSR" => plain,
+        Mode::Absolute => format!("{}{}", plain, value(word)),
+        Mode::AbsoluteX | Mode::AbsoluteY => {
+            let index = if op.mode == Mode::AbsoluteX { x } else { y };
+            let addr = word.wrapping_add(index as u16);
+            format!("{} @ {:04X}{}", plain, addr, value(addr))
+        }
+        Mode::Indirect if is_register(word) => plain,
+        Mode::Indirect => {
+            // the 6502 never carries into the high byte of the pointer, jmp ($02FF) reads $02FF and $0200.
+            let hi_addr = (word & 0xFF00) | (word.wrapping_add(1) & 0x00FF);
+            let target = machine.peek(word) as u16 | (machine.peek(hi_addr) as u16) << 8;
+            format!("{} = {:04X}", plain, target)
+        }
+        Mode::IndirectX => {
+            let pointer = lo.wrapping_add(x);
+            let addr = peek_word_zp(machine, pointer);
+            format!("{} @ {:02X} = {:04X}{}", plain, pointer, addr, value(addr))
+        }
+        Mode::IndirectY => {
+            let base = peek_word_zp(machine, lo);
+            let addr = base.wrapping_add(y as u16);
+            format!("{} = {:04X} @ {:04X}{}", plain, base, addr, value(addr))
+        }
+        _ => plain,
+    }
+}
+
+// the line for the instruction the cpu is about to run. cycles is how many cpu cycles have gone by since power-on.
+pub fn trace_line(machine: &Machine, cycles: u64) -> String
+{
+    let cpu = machine.cpu();
+    let pc = cpu.get_pc();
+    let opcode = machine.peek(pc);
+    let len = disasm::length(opcode);
+
+    let mut bytes = String::new();
+    let mut operands = [0u8; 2];
+    for i in 0..len
+    {
+        let b = machine.peek(pc.wrapping_add(i as u16));
+        if i > 0 {
+            operands[i - 1] = b;
+            bytes.push(' ');
+        }
+        bytes.push_str(&format!("{:02X}", b));
+    }
+
+    let op = disasm::op(opcode);
+    let operand = annotated_operand(machine, pc, opcode, &operands[..len - 1]);
+    let instruction = if operand.is_empty() { String::from(op.name) } else { format!("{} {}", op.name, operand) };
+
+    format!(
+        "{:04X}  {:<8} {}{:<32}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
+        pc,
+        bytes,
+        if op.official { ' ' } else { '*' },
+        instruction,
+        cpu.get_a(),
+        cpu.get_x(),
+        cpu.get_y(),
+        cpu.get_status(),
+        cpu.get_sp(),
+        cycles
+    )
+}
+
+// where each field sits in a trace line. the disassembly in between is left out of comparisons, emulators never
+// quite agree on how to annotate it, and the registers after the instruction catch anything it got wrong anyway.
+// CYC is found by name, since it moves depending on whether the line has a PPU column.
+const FIELDS: [(&str, usize, usize); 8] = [
+    ("PC", 0, 4),
+    ("opcode bytes", 6, 14),
+    ("instruction", 15, 19),
+    ("A", 48, 52),
+    ("X", 53, 57),
+    ("Y", 58, 62),
+    ("P", 63, 67),
+    ("SP", 68, 73),
+];
+
+// the first field two trace lines disagree on, if any.
+pub fn first_difference(ours: &str, theirs: &str) -> Option<&'static str>
+{
+    let field = |line: &str, start: usize, end: usize| {
+        let line = line.trim_end();
+        String::from(line.get(start..end.min(line.len())).unwrap_or("").trim())
+    };
+    let cycles = |line: &str| line.find("CYC:").map(|at| String::from(line[at + 4..].trim()));
+    FIELDS.iter()
+        .find(|&&(_, start, end)| field(ours, start, end) != field(theirs, start, end))
+        .map(|&(name, _, _)| name)
+        .or_else(|| if cycles(ours) != cycles(theirs) { Some("CYC") } else { None })
+}
+
+#[test_case]
+fn test_trace_field_compare()
+{
+    let golden = "C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7";
+    let ours = "C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD CYC:7";
+    let cycles_off = "C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD CYC:8";
+    let annotated_differently = "C000  4C F5 C5  JMP $C5F5 = 0000                A:00 X:00 Y:00 P:24 SP:FD CYC:7";
+    assert_eq!(first_difference(golden, golden), None);
+    assert_eq!(first_difference(ours, golden), None);
+    assert_eq!(first_difference(cycles_off, golden), Some("CYC"));
+    assert_eq!(first_difference(annotated_differently, golden), None);
+}
@@ -0,0 +1,200 @@
+// the zapper, the light gun, plugged into controller port 2 and aimed with the ps/2 mouse (see mouse.rs). it isn't a
+// shift register like a joypad, it just puts two bits on every $4017 read:
+//   bit 4  the trigger, 1 while it's pulled (the left button)
+//   bit 3  0 while the sensor sees light
+// the sensor looks at a small patch of the tv, and only sees it lit for the couple of dozen scanlines after the beam
+// has gone over it. so games black the screen out, draw a white box over each target for a frame, and poll $4017
+// while it's being drawn. Sight sits between the ppu and the real screen and keeps what that would have seen.
+extern crate alloc;
+
+use alloc::{rc::Rc, vec, vec::Vec};
+use core::cell::Cell;
+use runes::controller::Controller;
+use runes::ppu::Screen;
+use vga::colors::Color16;
+use vga::writers::{Graphics640x480x16, GraphicsWriter};
+
+use crate::emulation::{PIX_HEIGHT, PIX_WIDTH, RGB_COLORS};
+use crate::mouse;
+use crate::println;
+
+const TRIGGER: u8 = 0x10;
+const NO_LIGHT: u8 = 0x08;
+
+// how far round the cursor the sensor looks, each way.
+const SENSE_RADIUS: i32 = 2;
+// how many scanlines after the beam a lit pixel still counts. the photodiode is slow to let go.
+const SENSE_LINES: i32 = 24;
+// brightness (out of 255) a color needs to set the sensor off. the whites and the palest colors do, the rest don't.
+const LIGHT_THRESHOLD: u32 = 0xC0;
+const CROSSHAIR_SIZE: i32 = 5;
+
+// which of the nes colors the sensor sees, worked out at compile time.
+const fn lit_colors() -> [bool; 64]
+{
+    let mut table = [false; 64];
+    let mut i = 0;
+    while i < 64
+    {
+        let rgb = RGB_COLORS[i];
+        let (r, g, b) = ((rgb >> 16) & 0xff, (rgb >> 8) & 0xff, rgb & 0xff);
+        table[i] = (r * 299 + g * 587 + b * 114) / 1000 >= LIGHT_THRESHOLD;
+        i += 1;
+    }
+    table
+}
+
+const LIT: [bool; 64] = lit_colors();
+
+// what the sensor could see: which pixels are lit, and where the beam is.
+pub struct Light
+{
+    lit: Vec<Cell<bool>>,
+    // the line the ppu drew last. None between frames, when the beam's off.
+    beam: Cell<Option<u8>>,
+}
+
+impl Light
+{
+    fn new() -> Light
+    {
+        Light { lit: vec![Cell::new(false); (PIX_WIDTH * PIX_HEIGHT) as usize], beam: Cell::new(None) }
+    }
+
+    // whether the sensor sees light pointed at (x, y): some lit pixel near it that the beam went over a moment ago.
+    // everything below the beam is still the last frame, which has long faded.
+    fn sees(&self, x: i32, y: i32) -> bool
+    {
+        let beam = match self.beam.get() {
+            Some(beam) => beam as i32,
+            None => return false,
+        };
+        for py in (y - SENSE_RADIUS).max(0)..=(y + SENSE_RADIUS).min(PIX_HEIGHT as i32 - 1)
+        {
+            if py > beam || beam - py >= SENSE_LINES {
+                continue;
+            }
+            for px in (x - SENSE_RADIUS).max(0)..=(x + SENSE_RADIUS).min(PIX_WIDTH as i32 - 1)
+            {
+                if self.lit[(py * PIX_WIDTH as i32 + px) as usize].get() {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+}
+
+// the screen the ppu draws to when there's a zapper plugged in. passes everything on, keeping track for Light.
+pub struct Sight<'a>
+{
+    screen: &'a mut dyn Screen,
+    light: Rc<Light>,
+}
+
+impl<'a> Sight<'a>
+{
+    pub fn new(screen: &'a mut dyn Screen, zapper: &Zapper) -> Sight<'a>
+    {
+        Sight { screen, light: zapper.light.clone() }
+    }
+}
+
+impl<'a> Screen for Sight<'a>
+{
+    fn put(&mut self, x: u8, y: u8, color: u8)
+    {
+        self.light.lit[y as usize * PIX_WIDTH as usize + x as usize].set(LIT[(color & 0x3f) as usize]);
+        self.light.beam.set(Some(y));
+        self.screen.put(x, y, color);
+    }
+    fn render(&mut self)
+    {
+        self.screen.render();
+    }
+    fn frame(&mut self)
+    {
+        self.light.beam.set(None);
+        self.screen.frame();
+    }
+}
+
+pub struct Zapper
+{
+    light: Rc<Light>,
+}
+
+impl Zapper
+{
+    // None without a mouse to aim it with.
+    pub fn plug_in() -> Option<Zapper>
+    {
+        if !mouse::init() {
+            println!("no mouse, so no zapper");
+            return None;
+        }
+        mouse::confine(PIX_WIDTH as i32, PIX_HEIGHT as i32);
+        Some(Zapper { light: Rc::new(Light::new()) })
+    }
+
+    // once a frame, over the finished picture. the ppu draws over it again next frame, so it never needs erasing.
+    pub fn draw_crosshair(&self)
+    {
+        let vga = Graphics640x480x16::new();
+        let (x, y) = mouse::position();
+        for d in -CROSSHAIR_SIZE..=CROSSHAIR_SIZE
+        {
+            // a gap in the middle, so you can see what you're aiming at.
+            if d.abs() < 2 {
+                continue;
+            }
+            for &(px, py) in [(x + d, y), (x, y + d)].iter()
+            {
+                if px >= 0 && py >= 0 && px < PIX_WIDTH as i32 && py < PIX_HEIGHT as i32 {
+                    vga.set_pixel(px as usize, py as usize, Color16::White);
+                }
+            }
+        }
+    }
+}
+
+impl Controller for Zapper
+{
+    fn read(&self) -> u8
+    {
+        let (x, y) = mouse::position();
+        let trigger = if mouse::buttons() & mouse::LEFT != 0 { TRIGGER } else { 0 };
+        let light = if self.light.sees(x, y) { 0 } else { NO_LIGHT };
+        trigger | light
+    }
+
+    // no strobe, there's nothing to latch.
+    fn write(&self, _data: u8)
+    {
+    }
+}
+
+#[test_case]
+fn test_zapper_light()
+{
+    let light = Rc::new(Light::new());
+    let mut screen = crate::emulation::construct::HeadlessScreen::new();
+    let mut sight = Sight { screen: &mut screen, light: light.clone() };
+    // a white box at 100-109, 50-59 on black.
+    for y in 0..PIX_HEIGHT as u8
+    {
+        for x in 0..=255u8
+        {
+            let white = (100..110).contains(&x) && (50..60).contains(&y);
+            sight.put(x, y, if white { 0x30 } else { 0x0F });
+        }
+        if y == 60 {
+            assert!(light.sees(105, 55));
+            assert!(!light.sees(20, 55));
+        }
+    }
+    // long after the beam's gone by, it's faded.
+    assert!(!light.sees(105, 55));
+    sight.frame();
+    assert!(!light.sees(105, 55));
+}
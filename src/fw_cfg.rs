@@ -0,0 +1,95 @@
+// qemu's firmware config device. it's how qemu hands blobs to the guest without a disk:
+//   -fw_cfg name=opt/nesos/cmdline,string=audio=sb16
+//   -fw_cfg name=opt/nesos/rom,file=path/to/game.nes
+// there's a directory of named files, each with a selector key. write the key to the selector port, then read the
+// file a byte at a time out of the data port.
+// on real hardware there's nothing on these ports and the signature check fails, so everything here just says no.
+extern crate alloc;
+
+use alloc::vec::Vec;
+use x86_64::instructions::port::Port;
+
+const SELECTOR_PORT: u16 = 0x510;
+const DATA_PORT: u16 = 0x511;
+
+const KEY_SIGNATURE: u16 = 0x0000;
+const KEY_FILE_DIR: u16 = 0x0019;
+
+// each directory entry: size (be32), select (be16), reserved (be16), name (56 bytes, nul padded).
+const NAME_LEN: usize = 56;
+
+fn select(key: u16)
+{
+    unsafe { Port::<u16>::new(SELECTOR_PORT).write(key) }
+}
+
+fn read_bytes(buf: &mut [u8])
+{
+    let mut data = Port::<u8>::new(DATA_PORT);
+    for b in buf.iter_mut()
+    {
+        *b = unsafe { data.read() };
+    }
+}
+
+fn read_be32() -> u32
+{
+    let mut b = [0u8; 4];
+    read_bytes(&mut b);
+    u32::from_be_bytes(b)
+}
+
+fn read_be16() -> u16
+{
+    let mut b = [0u8; 2];
+    read_bytes(&mut b);
+    u16::from_be_bytes(b)
+}
+
+pub fn present() -> bool
+{
+    let mut signature = [0u8; 4];
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        select(KEY_SIGNATURE);
+        read_bytes(&mut signature);
+    });
+    &signature == b"QEMU"
+}
+
+// the selector key and size of a named file, if qemu was given one.
+fn find(name: &str) -> Option<(u16, u32)>
+{
+    if !present() {
+        return None;
+    }
+
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        select(KEY_FILE_DIR);
+        let count = read_be32();
+        for _ in 0..count
+        {
+            let size = read_be32();
+            let key = read_be16();
+            let _reserved = read_be16();
+            let mut entry_name = [0u8; NAME_LEN];
+            read_bytes(&mut entry_name);
+
+            let len = entry_name.iter().position(|&c| c == 0).unwrap_or(NAME_LEN);
+            if &entry_name[..len] == name.as_bytes() {
+                return Some((key, size));
+            }
+        }
+        None
+    })
+}
+
+pub fn read_file(name: &str) -> Option<Vec<u8>>
+{
+    let (key, size) = find(name)?;
+    let mut contents = alloc::vec![0u8; size as usize];
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        select(key);
+        read_bytes(&mut contents);
+    });
+    Some(contents)
+}
@@ -0,0 +1,474 @@
+// a gdb remote serial protocol stub on COM2, for debugging the kernel itself with nothing but a serial cable.
+// boot with the "gdb" option and the kernel stops early on with an int3 and waits for gdb:
+//   (gdb) set architecture i386:x86-64
+//   (gdb) target remote /dev/ttyS0        (or localhost:1234 for qemu's -serial tcp::1234,server on COM2)
+// from there breakpoints, stepping, registers and memory all work. gdb sets breakpoints by writing int3s into
+// memory itself, so all we have to do is catch them.
+//
+// the x86-interrupt handlers only get the interrupt frame, and gdb wants every register, so int3 and the debug
+// exception come in through the little assembly stubs below instead. they push everything, hand us a pointer to it,
+// and pop it all back (including whatever gdb changed) on the way out.
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::arch::global_asm;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, Ordering};
+use x86_64::registers::control::{Cr0, Cr0Flags};
+use x86_64::VirtAddr;
+
+use crate::serial::SERIAL2;
+use crate::{cmdline, memory, println};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+const VECTOR_DEBUG: u64 = 1;
+const VECTOR_BREAKPOINT: u64 = 3;
+
+// rflags' trap flag, which makes the cpu raise a debug exception after every instruction.
+const TRAP_FLAG: u64 = 1 << 8;
+
+// what the entry stubs push, lowest address first, with the interrupt frame the cpu pushed on top.
+#[repr(C)]
+#[derive(Debug)]
+pub struct SavedRegisters
+{
+    r15: u64,
+    r14: u64,
+    r13: u64,
+    r12: u64,
+    r11: u64,
+    r10: u64,
+    r9: u64,
+    r8: u64,
+    rbp: u64,
+    rdi: u64,
+    rsi: u64,
+    rdx: u64,
+    rcx: u64,
+    rbx: u64,
+    rax: u64,
+    rip: u64,
+    cs: u64,
+    rflags: u64,
+    rsp: u64,
+    ss: u64,
+}
+
+global_asm!(
+    ".global gdb_debug_entry",
+    "gdb_debug_entry:",
+    "push rax",
+    "mov eax, 1",
+    "jmp gdb_trap_common",
+    ".global gdb_breakpoint_entry",
+    "gdb_breakpoint_entry:",
+    "push rax",
+    "mov eax, 3",
+    "jmp gdb_trap_common",
+    "gdb_trap_common:",
+    "push rbx",
+    "push rcx",
+    "push rdx",
+    "push rsi",
+    "push rdi",
+    "push rbp",
+    "push r8",
+    "push r9",
+    "push r10",
+    "push r11",
+    "push r12",
+    "push r13",
+    "push r14",
+    "push r15",
+    // 15 pushes on top of the 5 word interrupt frame leaves the stack 16 byte aligned for the call.
+    "mov rdi, rsp",
+    "mov esi, eax",
+    "cld",
+    "call gdb_trap",
+    "pop r15",
+    "pop r14",
+    "pop r13",
+    "pop r12",
+    "pop r11",
+    "pop r10",
+    "pop r9",
+    "pop r8",
+    "pop rbp",
+    "pop rdi",
+    "pop rsi",
+    "pop rdx",
+    "pop rcx",
+    "pop rbx",
+    "pop rax",
+    "iretq",
+);
+
+extern "C" {
+    // for the idt, see interrupts.rs.
+    pub fn gdb_debug_entry();
+    pub fn gdb_breakpoint_entry();
+}
+
+pub fn enabled() -> bool
+{
+    ENABLED.load(Ordering::Relaxed)
+}
+
+// turn the stub on if the "gdb" boot option says so, and stop right away so gdb can attach.
+// needs the heap, for the boot options and the packets.
+pub fn init()
+{
+    if !cmdline::flag("gdb") {
+        return;
+    }
+    ENABLED.store(true, Ordering::Relaxed);
+    println!("waiting for gdb on COM2");
+    x86_64::instructions::interrupts::int3();
+}
+
+#[no_mangle]
+extern "C" fn gdb_trap(regs: &mut SavedRegisters, vector: u64)
+{
+    if !enabled() {
+        match vector {
+            VECTOR_BREAKPOINT => println!("EXCEPTION: BREAKPOINT\n{:#?}", regs),
+            _ => println!("EXCEPTION: DEBUG\n{:#?}", regs),
+        }
+        return;
+    }
+
+    // a step is done once it traps, gdb will ask for another if it wants one.
+    if vector == VECTOR_DEBUG {
+        regs.rflags &= !TRAP_FLAG;
+    }
+    send_packet("S05");
+    serve(regs);
+}
+
+fn read_byte() -> u8
+{
+    SERIAL2.lock().receive()
+}
+
+fn write_bytes(bytes: &[u8])
+{
+    let mut port = SERIAL2.lock();
+    for &b in bytes
+    {
+        port.send(b);
+    }
+}
+
+fn hex_value(c: u8) -> Option<u8>
+{
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+fn parse_hex(text: &str) -> Option<u64>
+{
+    u64::from_str_radix(text, 16).ok()
+}
+
+fn decode_hex(text: &[u8]) -> Option<Vec<u8>>
+{
+    text.chunks(2)
+        .map(|pair| match pair {
+            &[hi, lo] => Some(hex_value(hi)? << 4 | hex_value(lo)?),
+            _ => None,
+        })
+        .collect()
+}
+
+fn push_hex(out: &mut String, bytes: &[u8])
+{
+    for b in bytes
+    {
+        let _ = write!(out, "{:02x}", b);
+    }
+}
+
+// $<data>#<checksum>, acked with a + (or a - to ask for it again).
+fn receive_packet() -> Vec<u8>
+{
+    loop
+    {
+        while read_byte() != b'$' {}
+
+        let mut data = Vec::new();
+        let mut sum: u8 = 0;
+        loop
+        {
+            match read_byte() {
+                b'#' => break,
+                b => {
+                    sum = sum.wrapping_add(b);
+                    data.push(b);
+                }
+            }
+        }
+        let checksum = decode_hex(&[read_byte(), read_byte()]).and_then(|c| c.first().copied());
+        if checksum == Some(sum) {
+            write_bytes(b"+");
+            return data;
+        }
+        write_bytes(b"-");
+    }
+}
+
+fn send_packet(data: &str)
+{
+    let sum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+    let mut packet = String::with_capacity(data.len() + 4);
+    let _ = write!(packet, "${}#{:02x}", data, sum);
+    loop
+    {
+        write_bytes(packet.as_bytes());
+        // anything but a nak counts as delivered.
+        if read_byte() != b'-' {
+            return;
+        }
+    }
+}
+
+// gdb's amd64 numbering: rax rbx rcx rdx rsi rdi rbp rsp r8-r15 rip, then eflags and the segment registers, which
+// are only 32 bits. everything after that (the fpu and sse state) we just don't send, and gdb copes.
+const REGISTER_COUNT: usize = 24;
+
+fn register_size(n: usize) -> usize
+{
+    if n < 17 { 8 } else { 4 }
+}
+
+fn register(regs: &SavedRegisters, n: usize) -> u64
+{
+    match n {
+        0 => regs.rax,
+        1 => regs.rbx,
+        2 => regs.rcx,
+        3 => regs.rdx,
+        4 => regs.rsi,
+        5 => regs.rdi,
+        6 => regs.rbp,
+        7 => regs.rsp,
+        8 => regs.r8,
+        9 => regs.r9,
+        10 => regs.r10,
+        11 => regs.r11,
+        12 => regs.r12,
+        13 => regs.r13,
+        14 => regs.r14,
+        15 => regs.r15,
+        16 => regs.rip,
+        17 => regs.rflags,
+        18 => regs.cs,
+        19 => regs.ss,
+        // ds, es, fs and gs are all the null selector in long mode.
+        _ => 0,
+    }
+}
+
+fn set_register(regs: &mut SavedRegisters, n: usize, value: u64)
+{
+    let slot = match n {
+        0 => &mut regs.rax,
+        1 => &mut regs.rbx,
+        2 => &mut regs.rcx,
+        3 => &mut regs.rdx,
+        4 => &mut regs.rsi,
+        5 => &mut regs.rdi,
+        6 => &mut regs.rbp,
+        7 => &mut regs.rsp,
+        8 => &mut regs.r8,
+        9 => &mut regs.r9,
+        10 => &mut regs.r10,
+        11 => &mut regs.r11,
+        12 => &mut regs.r12,
+        13 => &mut regs.r13,
+        14 => &mut regs.r14,
+        15 => &mut regs.r15,
+        16 => &mut regs.rip,
+        17 => &mut regs.rflags,
+        // changing segments out from under the kernel isn't going to end well, ignore it.
+        _ => return,
+    };
+    *slot = value;
+}
+
+// a register, in target byte order, as hex.
+fn push_register(out: &mut String, regs: &SavedRegisters, n: usize)
+{
+    let bytes = register(regs, n).to_le_bytes();
+    push_hex(out, &bytes[..register_size(n)]);
+}
+
+fn little_endian(bytes: &[u8]) -> u64
+{
+    bytes.iter().rev().fold(0, |value, &b| value << 8 | b as u64)
+}
+
+fn range_mapped(addr: u64, len: u64) -> bool
+{
+    if len == 0 {
+        return true;
+    }
+    let end = match addr.checked_add(len - 1) {
+        Some(end) => end,
+        None => return false,
+    };
+    // canonical addresses only, VirtAddr::new panics on the rest.
+    if VirtAddr::try_new(addr).is_err() || VirtAddr::try_new(end).is_err() {
+        return false;
+    }
+    let mut page = addr & !0xFFF;
+    while page <= end
+    {
+        if !memory::is_mapped(VirtAddr::new(page)) {
+            return false;
+        }
+        page = match page.checked_add(0x1000) {
+            Some(p) => p,
+            None => break,
+        };
+    }
+    true
+}
+
+// "addr,len" out of an m or M packet.
+fn parse_range(text: &str) -> Option<(u64, u64)>
+{
+    let mut parts = text.splitn(2, ',');
+    Some((parse_hex(parts.next()?)?, parse_hex(parts.next()?)?))
+}
+
+// answer packets until gdb continues or steps.
+fn serve(regs: &mut SavedRegisters)
+{
+    loop
+    {
+        let packet = receive_packet();
+        let text = match core::str::from_utf8(&packet) {
+            Ok(t) => t,
+            Err(_) => {
+                send_packet("");
+                continue;
+            }
+        };
+        let (command, args) = text.split_at(text.len().min(1));
+
+        match command {
+            "?" => send_packet("S05"),
+            "g" => {
+                let mut out = String::new();
+                for n in 0..REGISTER_COUNT
+                {
+                    push_register(&mut out, regs, n);
+                }
+                send_packet(&out);
+            }
+            "G" => match decode_hex(args.as_bytes()) {
+                Some(bytes) => {
+                    let mut at = 0;
+                    for n in 0..REGISTER_COUNT
+                    {
+                        let size = register_size(n);
+                        if at + size > bytes.len() {
+                            break;
+                        }
+                        set_register(regs, n, little_endian(&bytes[at..at + size]));
+                        at += size;
+                    }
+                    send_packet("OK");
+                }
+                None => send_packet("E01"),
+            },
+            "p" => match parse_hex(args) {
+                Some(n) if (n as usize) < REGISTER_COUNT => {
+                    let mut out = String::new();
+                    push_register(&mut out, regs, n as usize);
+                    send_packet(&out);
+                }
+                _ => send_packet("E01"),
+            },
+            "P" => {
+                let mut parts = args.splitn(2, '=');
+                let n = parts.next().and_then(parse_hex);
+                let value = parts.next().and_then(|v| decode_hex(v.as_bytes()));
+                match (n, value) {
+                    (Some(n), Some(bytes)) => {
+                        set_register(regs, n as usize, little_endian(&bytes));
+                        send_packet("OK");
+                    }
+                    _ => send_packet("E01"),
+                }
+            }
+            "m" => match parse_range(args) {
+                Some((addr, len)) if range_mapped(addr, len) => {
+                    let memory = unsafe { core::slice::from_raw_parts(addr as *const u8, len as usize) };
+                    let mut out = String::with_capacity(len as usize * 2);
+                    push_hex(&mut out, memory);
+                    send_packet(&out);
+                }
+                _ => send_packet("E14"),
+            },
+            "M" => {
+                let mut parts = args.splitn(2, ':');
+                let range = parts.next().and_then(parse_range);
+                let data = parts.next().and_then(|d| decode_hex(d.as_bytes()));
+                match (range, data) {
+                    (Some((addr, len)), Some(data)) if data.len() as u64 == len && range_mapped(addr, len) => {
+                        // breakpoints go into the kernel's code, which is mapped read only. the write protect bit is
+                        // the only thing stopping ring 0 from writing there anyway.
+                        let cr0 = Cr0::read();
+                        unsafe {
+                            Cr0::write(cr0 - Cr0Flags::WRITE_PROTECT);
+                            core::ptr::copy_nonoverlapping(data.as_ptr(), addr as *mut u8, data.len());
+                            Cr0::write(cr0);
+                        }
+                        send_packet("OK");
+                    }
+                    _ => send_packet("E14"),
+                }
+            }
+            "c" | "s" => {
+                if let Some(addr) = parse_hex(args) {
+                    regs.rip = addr;
+                }
+                if command == "s" {
+                    regs.rflags |= TRAP_FLAG;
+                } else {
+                    regs.rflags &= !TRAP_FLAG;
+                }
+                return;
+            }
+            "D" => {
+                send_packet("OK");
+                regs.rflags &= !TRAP_FLAG;
+                return;
+            }
+            "k" => {
+                regs.rflags &= !TRAP_FLAG;
+                return;
+            }
+            "q" if args.starts_with("Supported") => send_packet("PacketSize=1000"),
+            "q" if args == "Attached" => send_packet("1"),
+            "q" if args == "C" => send_packet("QC1"),
+            "H" => send_packet("OK"),
+            _ => send_packet(""),
+        }
+    }
+}
+
+#[test_case]
+fn test_gdb_hex()
+{
+    assert_eq!(decode_hex(b"0aff"), Some(alloc::vec![0x0a, 0xff]));
+    assert_eq!(decode_hex(b"0g"), None);
+    assert_eq!(little_endian(&[0x34, 0x12]), 0x1234);
+}
@@ -5,7 +5,8 @@ use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, Pag
 use crate::{print, println, vga_help, registers, time::tick};
 use lazy_static::lazy_static;
 
-use crate::gdt;
+use crate::{gdbstub, gdt};
+use x86_64::VirtAddr;
 
 use pic8259::ChainedPics;
 use spin;
@@ -36,6 +37,8 @@ pub enum InterruptIndex
     // the timer is at index zero.
     Timer = PIC_1_OFFSET,
     Keyboard, // allow the PS2 keyboard to interact, give it a port in our interrupt table.
+    SoundBlaster = PIC_1_OFFSET + crate::sound::sb16::SB16_IRQ, // the sb16 is jumpered (well, mixer-registered) to irq 5.
+    Mouse = PIC_1_OFFSET + crate::mouse::MOUSE_IRQ, // the ps/2 aux port, which is on the slave pic.
 }
 
 impl InterruptIndex {
@@ -55,7 +58,12 @@ lazy_static! {
         //// notice that all of these have different args they take in.
         // the "breakpoint" exception is when the code throws an "int3" interrupt, which is what most
         // debuggers use to set breakpoints. it comes with a stack frame. 
-        idt.breakpoint.set_handler_fn(breakpoint_handler);
+        // these two go through the gdb stub, which wants every register and not just the interrupt frame.
+        // without the "gdb" boot option it just prints them and carries on.
+        unsafe {
+            idt.breakpoint.set_handler_addr(VirtAddr::new(gdbstub::gdb_breakpoint_entry as u64));
+            idt.debug.set_handler_addr(VirtAddr::new(gdbstub::gdb_debug_entry as u64));
+        }
         unsafe {
             // a "double fault" is when the CPU fails to find the pointer for the normal fault.
             // if the double fault fails, it "triple faults" which is just a hard reset on most hardware.
@@ -81,6 +89,10 @@ lazy_static! {
             .set_handler_fn(timer_interrupt_handler);
         idt[InterruptIndex::Keyboard.as_usize()]
             .set_handler_fn(keyboard_interrupt_handler);
+        idt[InterruptIndex::SoundBlaster.as_usize()]
+            .set_handler_fn(sound_blaster_interrupt_handler);
+        idt[InterruptIndex::Mouse.as_usize()]
+            .set_handler_fn(mouse_interrupt_handler);
 
         idt[16]
             .set_handler_fn(vga_mode_interrupt_handler); 
@@ -94,11 +106,23 @@ pub fn init_idt()
     IDT.load();
 }
 
-extern "x86-interrupt" fn breakpoint_handler(
-    stack_frame: InterruptStackFrame
-)
+// the firmware leaves every line it didn't use masked off in the PICs. devices we bring up later have to ask.
+pub fn unmask_irq(irq: u8)
 {
-    println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
+    use x86_64::instructions::port::Port;
+
+    // the mask registers are the PICs' data ports. lines 8-15 are on the slave, which is cascaded through line 2.
+    let (port, bit) = if irq < 8 { (0x21, irq) } else { (0xA1, irq - 8) };
+    x86_64::instructions::interrupts::without_interrupts(|| unsafe {
+        let mut mask = Port::<u8>::new(port);
+        let current = mask.read();
+        mask.write(current & !(1 << bit));
+        if irq >= 8 {
+            let mut master = Port::<u8>::new(0x21);
+            let current = master.read();
+            master.write(current & !(1 << 2));
+        }
+    });
 }
 
 extern "x86-interrupt" fn page_fault_handler(
@@ -128,6 +152,8 @@ extern "x86-interrupt" fn timer_interrupt_handler(
     // increment the tick count and implement stuff like sleeping.
     // actually USE the timer as a TIMER.
     tick();
+    // when the emulator is playing through the pc speaker, the timer is running at the sample rate.
+    crate::sound::pc_speaker::pwm_tick();
 
     // the communication between the PICs and the CPU isn't one way.
     // for certain devices, the CPU needs to bark back at it to get it to run again.
@@ -172,6 +198,38 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(
     }
 }
 
+extern "x86-interrupt" fn sound_blaster_interrupt_handler(
+    stack_frame: InterruptStackFrame,
+)
+{
+    // one half of the dma buffer finished playing, go refill it.
+    crate::sound::sb16::handle_interrupt();
+
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(InterruptIndex::SoundBlaster.as_u8());
+    }
+}
+
+// same ports as the keyboard, the controller just tells us which device the byte's from by which irq it raises.
+extern "x86-interrupt" fn mouse_interrupt_handler(
+    stack_frame: InterruptStackFrame,
+)
+{
+    use x86_64::instructions::port::Port;
+
+    let mut port = Port::new(0x60);
+    let byte: u8 = unsafe {port.read()};
+
+    crate::mouse::handle_byte(byte);
+
+    unsafe {
+        // this one's on the slave, so both pics have to hear about it. the chained pics work that out from the number.
+        PICS.lock()
+            .notify_end_of_interrupt(InterruptIndex::Mouse.as_u8());
+    }
+}
+
 extern "x86-interrupt" fn vga_mode_interrupt_handler(
     stack_frame: InterruptStackFrame,
 )
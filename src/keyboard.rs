@@ -1,3 +1,5 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
 use pc_keyboard::*;
 
 use crate::{print, println};
@@ -16,17 +18,69 @@ lazy_static! {
         );
 }
 
+// whether each key is held down right now, indexed by KeyCode. the emulator's controllers read this.
+static PRESSED: [AtomicBool; 256] = [const { AtomicBool::new(false) }; 256];
+
+// keys that went down since the last time someone looked. this is how hotkeys get to the emulator loop.
+const KEY_QUEUE_SIZE: usize = 32;
+
+struct KeyQueue
+{
+    keys: [KeyCode; KEY_QUEUE_SIZE],
+    head: usize,
+    len: usize,
+}
+
+static KEY_QUEUE: Mutex<KeyQueue> = Mutex::new(KeyQueue {
+    keys: [KeyCode::Escape; KEY_QUEUE_SIZE],
+    head: 0,
+    len: 0,
+});
+
+pub fn is_pressed(key: KeyCode) -> bool
+{
+    PRESSED[key as usize].load(Ordering::Relaxed)
+}
+
+// the next key that was pressed, oldest first.
+pub fn pop_key() -> Option<KeyCode>
+{
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut queue = KEY_QUEUE.lock();
+        if queue.len == 0 {
+            return None;
+        }
+        let key = queue.keys[queue.head];
+        queue.head = (queue.head + 1) % KEY_QUEUE_SIZE;
+        queue.len -= 1;
+        Some(key)
+    })
+}
+
+fn push_key(key: KeyCode)
+{
+    let mut queue = KEY_QUEUE.lock();
+    // nobody's reading, drop it rather than overwrite something older.
+    if queue.len == KEY_QUEUE_SIZE {
+        return;
+    }
+    let tail = (queue.head + queue.len) % KEY_QUEUE_SIZE;
+    queue.keys[tail] = key;
+    queue.len += 1;
+}
+
 // called directly from the interrupt.
 pub fn handle_keycode(scancode: u8)
 {
     let mut keyboard = KEYBOARD.lock();
 
     if let Ok(Some(ev)) = keyboard.add_byte(scancode) {
-        if let Some(key) = keyboard.process_keyevent(ev) {
-            match key {
-                DecodedKey::Unicode(character) => println!("{}", character),
-                DecodedKey::RawKey(key) => println!("{:?}", key),
-            }
+        let down = ev.state == KeyState::Down;
+        PRESSED[ev.code as usize].store(down, Ordering::Relaxed);
+        if down {
+            push_key(ev.code);
         }
+        // still feed the decoder, it keeps track of shift and friends.
+        keyboard.process_keyevent(ev);
     }
 }
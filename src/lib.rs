@@ -14,10 +14,17 @@
 extern crate alloc;
 
 pub mod allocator;
+pub mod ata;
+pub mod cmdline;
+pub mod fw_cfg;
+pub mod gdbstub;
 pub mod memory;
+pub mod pci;
 pub mod emulation;
 pub mod time;
 pub mod registers;
+pub mod romdisk;
+pub mod rtc;
 pub mod sound;
 pub mod serial;
 pub mod vga_help;
@@ -25,6 +32,7 @@ pub mod vga_buffer;
 pub mod vga_draw;
 pub mod interrupts;
 pub mod keyboard;
+pub mod mouse;
 pub mod gdt;
 
 use core::panic::PanicInfo;
@@ -146,6 +146,9 @@ fn kernel_start(boot_info: &'static BootInfo) -> ! {
     allocator::init_heap(&mut mapper, &mut frame_allocator)
         .expect("heap init failed");
 
+    // stops here for gdb on COM2 if the "gdb" boot option is set.
+    nesos::gdbstub::init();
+
     nesos::emulation::run_rom();
 
     halt_loop();
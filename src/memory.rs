@@ -1,6 +1,8 @@
 // page tables themselves are 4kb,
 // but the pages are small, only pointing to the 4kb slot they imply.
 
+use core::sync::atomic::{AtomicU64, Ordering};
+
 use crate::println;
 
 use x86_64::{
@@ -58,13 +60,32 @@ unsafe impl FrameAllocator<Size4KiB> for EmptyFrameAllocator
     }
 }
 
+// remembered so that drivers can find the physical address of their dma buffers without the boot info.
+static PHYSICAL_MEMORY_OFFSET: AtomicU64 = AtomicU64::new(0);
+
 // we'll bake the offset into the actual data structure, so we don't have to pass around the boot info.
 pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static>
 {
+    PHYSICAL_MEMORY_OFFSET.store(physical_memory_offset.as_u64(), Ordering::Relaxed);
     let level_4_table = active_level_4_table(physical_memory_offset);
     OffsetPageTable::new(level_4_table, physical_memory_offset)
 }
 
+// whether there's anything at addr at all, huge pages included. for reading memory at addresses someone else picked,
+// like a debugger, without page faulting.
+pub fn is_mapped(addr: VirtAddr) -> bool
+{
+    use x86_64::structures::paging::Translate;
+
+    let offset = PHYSICAL_MEMORY_OFFSET.load(Ordering::Relaxed);
+    if offset == 0 {
+        return false;
+    }
+    let offset = VirtAddr::new(offset);
+    let mapper = unsafe { OffsetPageTable::new(active_level_4_table(offset), offset) };
+    mapper.translate_addr(addr).is_some()
+}
+
 unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut PageTable
 {
     use x86_64::registers::control::Cr3;
@@ -116,6 +137,14 @@ pub unsafe fn translate_addr(addr: VirtAddr, physical_memory_offset: VirtAddr) -
     translate_addr_inner(addr, physical_memory_offset)
 }
 
+// hardware doing dma doesn't go through our page tables, it needs the real address.
+// only valid after init() has run.
+pub fn virt_to_phys(addr: VirtAddr) -> Option<PhysAddr>
+{
+    let offset = VirtAddr::new(PHYSICAL_MEMORY_OFFSET.load(Ordering::Relaxed));
+    unsafe { translate_addr(addr, offset) }
+}
+
 fn translate_addr_inner(addr: VirtAddr, physical_memory_offset: VirtAddr) -> Option<PhysAddr>
 {
     use x86_64::structures::paging::page_table::FrameError;
@@ -0,0 +1,193 @@
+// the ps/2 mouse, on the keyboard controller's second (aux) port. it talks through the same 0x60/0x64 ports as the
+// keyboard, but gets irq 12, on the slave pic. each interrupt is one byte of a 3 byte packet:
+//   byte 0  buttons in bits 0-2, bit 3 always set, then the sign and overflow bits for x and y
+//   byte 1  x movement
+//   byte 2  y movement, up being positive
+// we keep a position inside whatever box the user of it confines us to, since nothing here has a cursor of its own.
+use core::sync::atomic::{AtomicBool, AtomicI32, AtomicU8, Ordering};
+
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+use crate::{interrupts, println};
+
+pub const MOUSE_IRQ: u8 = 12;
+
+pub const LEFT: u8 = 0x01;
+pub const RIGHT: u8 = 0x02;
+pub const MIDDLE: u8 = 0x04;
+
+const DATA_PORT: u16 = 0x60;
+// reads the controller's status, writes commands to it.
+const COMMAND_PORT: u16 = 0x64;
+const OUTPUT_FULL: u8 = 0x01;
+const INPUT_FULL: u8 = 0x02;
+
+// controller commands.
+const ENABLE_AUX: u8 = 0xA8;
+const READ_CONFIG: u8 = 0x20;
+const WRITE_CONFIG: u8 = 0x60;
+// the next byte on the data port goes to the mouse instead of the keyboard.
+const TO_AUX: u8 = 0xD4;
+// config byte bits.
+const AUX_IRQ: u8 = 0x02;
+const AUX_CLOCK_OFF: u8 = 0x20;
+
+// mouse commands, each answered with ACK.
+const SET_DEFAULTS: u8 = 0xF6;
+const ENABLE_REPORTING: u8 = 0xF4;
+const ACK: u8 = 0xFA;
+
+// packet byte 0.
+const BUTTONS: u8 = 0x07;
+const ALWAYS_SET: u8 = 0x08;
+const X_SIGN: u8 = 0x10;
+const Y_SIGN: u8 = 0x20;
+const OVERFLOW: u8 = 0xC0;
+
+// how long to spin on the controller before deciding nothing's there.
+const TIMEOUT: u32 = 100_000;
+
+static PRESENT: AtomicBool = AtomicBool::new(false);
+static X: AtomicI32 = AtomicI32::new(0);
+static Y: AtomicI32 = AtomicI32::new(0);
+static WIDTH: AtomicI32 = AtomicI32::new(640);
+static HEIGHT: AtomicI32 = AtomicI32::new(480);
+static HELD: AtomicU8 = AtomicU8::new(0);
+
+// the packet so far, and how much of it there is.
+static PACKET: Mutex<([u8; 3], usize)> = Mutex::new(([0; 3], 0));
+
+fn wait_write() -> bool
+{
+    let mut status = Port::<u8>::new(COMMAND_PORT);
+    (0..TIMEOUT).any(|_| unsafe { status.read() } & INPUT_FULL == 0)
+}
+
+fn wait_read() -> bool
+{
+    let mut status = Port::<u8>::new(COMMAND_PORT);
+    (0..TIMEOUT).any(|_| unsafe { status.read() } & OUTPUT_FULL != 0)
+}
+
+fn command(byte: u8) -> bool
+{
+    if !wait_write() {
+        return false;
+    }
+    unsafe { Port::<u8>::new(COMMAND_PORT).write(byte) };
+    true
+}
+
+fn write_data(byte: u8) -> bool
+{
+    if !wait_write() {
+        return false;
+    }
+    unsafe { Port::<u8>::new(DATA_PORT).write(byte) };
+    true
+}
+
+fn read_data() -> Option<u8>
+{
+    if wait_read() { Some(unsafe { Port::<u8>::new(DATA_PORT).read() }) } else { None }
+}
+
+// send the mouse a command and wait for it to say it heard.
+fn write_aux(byte: u8) -> bool
+{
+    command(TO_AUX) && write_data(byte) && read_data() == Some(ACK)
+}
+
+// turn the aux port on and get the mouse sending packets. false if there's no mouse. fine to call more than once.
+pub fn init() -> bool
+{
+    if PRESENT.load(Ordering::Relaxed) {
+        return true;
+    }
+    // the keyboard interrupt reads the same data port, keep it from eating the mouse's answers.
+    let present = x86_64::instructions::interrupts::without_interrupts(|| {
+        if !command(ENABLE_AUX) || !command(READ_CONFIG) {
+            return false;
+        }
+        let config = match read_data() {
+            Some(config) => (config | AUX_IRQ) & !AUX_CLOCK_OFF,
+            None => return false,
+        };
+        command(WRITE_CONFIG) && write_data(config) && write_aux(SET_DEFAULTS) && write_aux(ENABLE_REPORTING)
+    });
+    if !present {
+        println!("no ps/2 mouse");
+        return false;
+    }
+    PRESENT.store(true, Ordering::Relaxed);
+    interrupts::unmask_irq(MOUSE_IRQ);
+    println!("ps/2 mouse on irq {}", MOUSE_IRQ);
+    true
+}
+
+// keep the position inside width x height, starting from the middle.
+pub fn confine(width: i32, height: i32)
+{
+    WIDTH.store(width, Ordering::Relaxed);
+    HEIGHT.store(height, Ordering::Relaxed);
+    X.store(width / 2, Ordering::Relaxed);
+    Y.store(height / 2, Ordering::Relaxed);
+}
+
+pub fn position() -> (i32, i32)
+{
+    (X.load(Ordering::Relaxed), Y.load(Ordering::Relaxed))
+}
+
+// LEFT, RIGHT and MIDDLE, for the ones held down.
+pub fn buttons() -> u8
+{
+    HELD.load(Ordering::Relaxed)
+}
+
+// a whole packet as (x movement, y movement with down positive, buttons). overflowed movement is junk, so that's 0.
+fn decode(packet: [u8; 3]) -> (i32, i32, u8)
+{
+    let flags = packet[0];
+    if flags & OVERFLOW != 0 {
+        return (0, 0, flags & BUTTONS);
+    }
+    let dx = packet[1] as i32 - if flags & X_SIGN != 0 { 256 } else { 0 };
+    let dy = packet[2] as i32 - if flags & Y_SIGN != 0 { 256 } else { 0 };
+    (dx, -dy, flags & BUTTONS)
+}
+
+// called directly from the interrupt.
+pub fn handle_byte(byte: u8)
+{
+    let mut packet = PACKET.lock();
+    let (bytes, len) = &mut *packet;
+    // the first byte always has bit 3 set. if this one doesn't we've lost our place, so wait for one that does.
+    if *len == 0 && byte & ALWAYS_SET == 0 {
+        return;
+    }
+    bytes[*len] = byte;
+    *len += 1;
+    if *len < 3 {
+        return;
+    }
+    *len = 0;
+
+    let (dx, dy, held) = decode(*bytes);
+    let width = WIDTH.load(Ordering::Relaxed);
+    let height = HEIGHT.load(Ordering::Relaxed);
+    X.store((X.load(Ordering::Relaxed) + dx).max(0).min(width - 1), Ordering::Relaxed);
+    Y.store((Y.load(Ordering::Relaxed) + dy).max(0).min(height - 1), Ordering::Relaxed);
+    HELD.store(held, Ordering::Relaxed);
+}
+
+#[test_case]
+fn test_mouse_packets()
+{
+    // left held, moved 5 right and 3 up.
+    assert_eq!(decode([ALWAYS_SET | LEFT, 5, 3]), (5, -3, LEFT));
+    // 2 left and 4 down, nothing held.
+    assert_eq!(decode([ALWAYS_SET | X_SIGN | Y_SIGN, 0xFE, 0xFC]), (-2, 4, 0));
+    assert_eq!(decode([ALWAYS_SET | OVERFLOW | RIGHT, 0x80, 0x80]), (0, 0, RIGHT));
+}
@@ -0,0 +1,161 @@
+// pci configuration space access through the legacy io ports.
+// every pci function has 256 bytes of config space that describe what it is and where its registers live.
+// we get at them by writing the address of the dword we want to CONFIG_ADDRESS, then reading or writing CONFIG_DATA.
+use x86_64::instructions::port::Port;
+
+use crate::serial_println;
+
+const CONFIG_ADDRESS: u16 = 0xCF8;
+const CONFIG_DATA: u16 = 0xCFC;
+
+// offsets into the standard config header.
+const VENDOR_ID: u8 = 0x00;
+const COMMAND: u8 = 0x04;
+const CLASS: u8 = 0x08;
+const HEADER_TYPE: u8 = 0x0C;
+const BAR0: u8 = 0x10;
+const INTERRUPT_LINE: u8 = 0x3C;
+
+// command register bits.
+const COMMAND_IO_SPACE: u16 = 1 << 0;
+const COMMAND_MEMORY_SPACE: u16 = 1 << 1;
+const COMMAND_BUS_MASTER: u16 = 1 << 2;
+
+#[derive(Debug, Clone, Copy)]
+pub struct PciDevice
+{
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+}
+
+fn config_address(bus: u8, device: u8, function: u8, offset: u8) -> u32
+{
+    // bit 31 enables the access, then bus/device/function, then the dword aligned register offset.
+    (1 << 31)
+        | ((bus as u32) << 16)
+        | ((device as u32 & 0x1F) << 11)
+        | ((function as u32 & 0x07) << 8)
+        | (offset as u32 & 0xFC)
+}
+
+pub fn read_config(bus: u8, device: u8, function: u8, offset: u8) -> u32
+{
+    x86_64::instructions::interrupts::without_interrupts(|| unsafe {
+        Port::<u32>::new(CONFIG_ADDRESS).write(config_address(bus, device, function, offset));
+        Port::<u32>::new(CONFIG_DATA).read()
+    })
+}
+
+pub fn write_config(bus: u8, device: u8, function: u8, offset: u8, value: u32)
+{
+    x86_64::instructions::interrupts::without_interrupts(|| unsafe {
+        Port::<u32>::new(CONFIG_ADDRESS).write(config_address(bus, device, function, offset));
+        Port::<u32>::new(CONFIG_DATA).write(value);
+    })
+}
+
+impl PciDevice
+{
+    fn probe(bus: u8, device: u8, function: u8) -> Option<PciDevice>
+    {
+        let id = read_config(bus, device, function, VENDOR_ID);
+        // nothing answers on this address.
+        if id & 0xFFFF == 0xFFFF
+        {
+            return None;
+        }
+
+        let class = read_config(bus, device, function, CLASS);
+        Some(PciDevice {
+            bus,
+            device,
+            function,
+            vendor_id: (id & 0xFFFF) as u16,
+            device_id: (id >> 16) as u16,
+            class: (class >> 24) as u8,
+            subclass: (class >> 16) as u8,
+        })
+    }
+
+    pub fn read(&self, offset: u8) -> u32
+    {
+        read_config(self.bus, self.device, self.function, offset)
+    }
+
+    pub fn write(&self, offset: u8, value: u32)
+    {
+        write_config(self.bus, self.device, self.function, offset, value)
+    }
+
+    // the raw base address register. bit 0 set means it's an io port base, otherwise it's mmio.
+    pub fn bar(&self, index: u8) -> u32
+    {
+        self.read(BAR0 + index * 4)
+    }
+
+    // base of an io port bar, with the flag bits masked off.
+    pub fn io_bar(&self, index: u8) -> Option<u16>
+    {
+        let bar = self.bar(index);
+        if bar & 1 == 1 {
+            Some((bar & 0xFFFC) as u16)
+        } else {
+            None
+        }
+    }
+
+    // the legacy PIC line the firmware routed this device's interrupt pin to.
+    pub fn interrupt_line(&self) -> u8
+    {
+        self.read(INTERRUPT_LINE) as u8
+    }
+
+    // let the device decode its io and memory bars and master the bus for dma.
+    pub fn enable(&self)
+    {
+        let command = self.read(COMMAND);
+        let bits = (COMMAND_IO_SPACE | COMMAND_MEMORY_SPACE | COMMAND_BUS_MASTER) as u32;
+        self.write(COMMAND, command | bits);
+    }
+}
+
+// brute force every bus/device/function and hand back the first one that matches.
+pub fn find_device(matches: impl Fn(&PciDevice) -> bool) -> Option<PciDevice>
+{
+    for bus in 0..=255u8
+    {
+        for device in 0..32u8
+        {
+            let first = match PciDevice::probe(bus, device, 0) {
+                Some(d) => d,
+                None => continue,
+            };
+
+            // only multifunction devices have anything past function 0.
+            let functions = if read_config(bus, device, 0, HEADER_TYPE) & 0x0080_0000 != 0 { 8 } else { 1 };
+
+            for function in 0..functions
+            {
+                let found = if function == 0 { Some(first) } else { PciDevice::probe(bus, device, function) };
+                if let Some(d) = found {
+                    if matches(&d) {
+                        serial_println!("pci: found {:04x}:{:04x} at {:02x}:{:02x}.{}", d.vendor_id, d.device_id, bus, device, function);
+                        return Some(d);
+                    }
+                }
+            }
+        }
+    }
+
+    None
+}
+
+pub fn find_class(class: u8, subclass: u8) -> Option<PciDevice>
+{
+    find_device(|d| d.class == class && d.subclass == subclass)
+}
@@ -0,0 +1,356 @@
+// the rom disk: a second ide drive we keep our files on. saves, mostly.
+// it's not a real filesystem, just a flat directory of named extents:
+//
+//   sector 0          superblock: "NESOSDSK", version, next free sector
+//   sectors 1..=8     directory, 64 byte entries: name (48 bytes, nul padded), start sector, capacity in sectors, size
+//   after that        file data
+//
+// files never move or shrink. rewriting a file with more data than its extent holds gives it a new extent at the
+// end of the disk and leaks the old one, which is fine for a disk full of 8KiB save files. anything that grows as it
+// goes (movies) should create() itself with room up front and append() into it instead.
+//
+// make one with:
+//   truncate -s 4M romdisk.img
+// and attach it as the primary slave, next to the boot image:
+//   -drive file=romdisk.img,format=raw,index=1,media=disk
+// a disk that's all zeros in sector 0 gets formatted on first use. anything else without the magic is left alone.
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use lazy_static::lazy_static;
+use spin::Mutex;
+
+use crate::ata::{self, Disk, SECTOR_SIZE};
+use crate::{println, serial_println};
+
+const MAGIC: &[u8; 8] = b"NESOSDSK";
+const VERSION: u32 = 1;
+
+const DIR_START: u32 = 1;
+const DIR_SECTORS: u32 = 8;
+const DATA_START: u32 = DIR_START + DIR_SECTORS;
+
+const ENTRY_SIZE: usize = 64;
+const ENTRIES_PER_SECTOR: usize = SECTOR_SIZE / ENTRY_SIZE;
+pub const MAX_FILES: usize = DIR_SECTORS as usize * ENTRIES_PER_SECTOR;
+pub const MAX_NAME: usize = 48;
+
+#[derive(Debug, Clone, Copy)]
+struct Entry
+{
+    name: [u8; MAX_NAME],
+    start: u32,
+    capacity: u32,
+    size: u32,
+}
+
+impl Entry
+{
+    fn empty() -> Entry
+    {
+        Entry { name: [0; MAX_NAME], start: 0, capacity: 0, size: 0 }
+    }
+
+    fn is_used(&self) -> bool
+    {
+        self.name[0] != 0
+    }
+
+    fn name(&self) -> &str
+    {
+        let len = self.name.iter().position(|&c| c == 0).unwrap_or(MAX_NAME);
+        core::str::from_utf8(&self.name[..len]).unwrap_or("")
+    }
+
+    fn decode(bytes: &[u8]) -> Entry
+    {
+        let word = |at: usize| u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]]);
+        let mut name = [0; MAX_NAME];
+        name.copy_from_slice(&bytes[..MAX_NAME]);
+        Entry { name, start: word(48), capacity: word(52), size: word(56) }
+    }
+
+    fn encode(&self, bytes: &mut [u8])
+    {
+        bytes[..MAX_NAME].copy_from_slice(&self.name);
+        bytes[48..52].copy_from_slice(&self.start.to_le_bytes());
+        bytes[52..56].copy_from_slice(&self.capacity.to_le_bytes());
+        bytes[56..60].copy_from_slice(&self.size.to_le_bytes());
+        bytes[60..64].copy_from_slice(&[0; 4]);
+    }
+}
+
+pub struct RomDisk
+{
+    disk: Disk,
+    next_free: u32,
+    entries: [Entry; MAX_FILES],
+}
+
+fn sectors_for(size: usize) -> u32
+{
+    ((size + SECTOR_SIZE - 1) / SECTOR_SIZE) as u32
+}
+
+impl RomDisk
+{
+    // look for a formatted (or blank) rom disk on any of the ide drives except the one we booted from.
+    fn find() -> Option<RomDisk>
+    {
+        let candidates = [
+            (ata::Bus::Primary, ata::Drive::Slave),
+            (ata::Bus::Secondary, ata::Drive::Master),
+            (ata::Bus::Secondary, ata::Drive::Slave),
+        ];
+
+        for &(bus, drive) in candidates.iter()
+        {
+            if let Some(disk) = Disk::identify(bus, drive) {
+                if let Some(romdisk) = RomDisk::mount(disk) {
+                    return Some(romdisk);
+                }
+            }
+        }
+        None
+    }
+
+    fn mount(disk: Disk) -> Option<RomDisk>
+    {
+        let mut sector = [0u8; SECTOR_SIZE];
+        disk.read_sector(0, &mut sector).ok()?;
+
+        let mut romdisk = RomDisk { disk, next_free: DATA_START, entries: [Entry::empty(); MAX_FILES] };
+
+        if &sector[..8] != MAGIC {
+            // not ours. only claim it if it's never been written to.
+            if sector.iter().any(|&b| b != 0) {
+                return None;
+            }
+            println!("formatting a blank rom disk");
+            romdisk.flush().ok()?;
+            return Some(romdisk);
+        }
+
+        romdisk.next_free = u32::from_le_bytes([sector[12], sector[13], sector[14], sector[15]]);
+        for s in 0..DIR_SECTORS
+        {
+            disk.read_sector(DIR_START + s, &mut sector).ok()?;
+            for e in 0..ENTRIES_PER_SECTOR
+            {
+                let bytes = &sector[e * ENTRY_SIZE..(e + 1) * ENTRY_SIZE];
+                romdisk.entries[s as usize * ENTRIES_PER_SECTOR + e] = Entry::decode(bytes);
+            }
+        }
+
+        serial_println!("rom disk mounted, {} files", romdisk.entries.iter().filter(|e| e.is_used()).count());
+        Some(romdisk)
+    }
+
+    // write the superblock and directory back out.
+    fn flush(&self) -> Result<(), ata::AtaError>
+    {
+        let mut sector = [0u8; SECTOR_SIZE];
+        sector[..8].copy_from_slice(MAGIC);
+        sector[8..12].copy_from_slice(&VERSION.to_le_bytes());
+        sector[12..16].copy_from_slice(&self.next_free.to_le_bytes());
+        self.disk.write_sector(0, &sector)?;
+
+        for s in 0..DIR_SECTORS
+        {
+            let mut sector = [0u8; SECTOR_SIZE];
+            for e in 0..ENTRIES_PER_SECTOR
+            {
+                self.entries[s as usize * ENTRIES_PER_SECTOR + e]
+                    .encode(&mut sector[e * ENTRY_SIZE..(e + 1) * ENTRY_SIZE]);
+            }
+            self.disk.write_sector(DIR_START + s, &sector)?;
+        }
+        Ok(())
+    }
+
+    fn lookup(&self, name: &str) -> Option<usize>
+    {
+        self.entries.iter().position(|e| e.is_used() && e.name() == name)
+    }
+
+    pub fn exists(&self, name: &str) -> bool
+    {
+        self.lookup(name).is_some()
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = (&str, usize)>
+    {
+        self.entries.iter().filter(|e| e.is_used()).map(|e| (e.name(), e.size as usize))
+    }
+
+    pub fn read(&self, name: &str) -> Option<Vec<u8>>
+    {
+        let entry = self.entries[self.lookup(name)?];
+        let mut contents = Vec::with_capacity(entry.size as usize);
+        let mut sector = [0u8; SECTOR_SIZE];
+
+        for s in 0..sectors_for(entry.size as usize)
+        {
+            self.disk.read_sector(entry.start + s, &mut sector).ok()?;
+            let remaining = entry.size as usize - contents.len();
+            contents.extend_from_slice(&sector[..remaining.min(SECTOR_SIZE)]);
+        }
+        Some(contents)
+    }
+
+    // the directory entry for a file, made if it isn't there yet.
+    fn entry_for(&mut self, name: &str) -> Option<usize>
+    {
+        if name.is_empty() || name.len() >= MAX_NAME {
+            return None;
+        }
+        if let Some(i) = self.lookup(name) {
+            return Some(i);
+        }
+        let i = self.entries.iter().position(|e| !e.is_used())?;
+        let mut entry = Entry::empty();
+        entry.name[..name.len()].copy_from_slice(name.as_bytes());
+        self.entries[i] = entry;
+        Some(i)
+    }
+
+    // make sure a file's extent is at least this many sectors. if it isn't, move it to the end.
+    fn make_room(&mut self, index: usize, needed: u32) -> bool
+    {
+        if self.entries[index].capacity >= needed {
+            return true;
+        }
+        if self.next_free + needed > self.disk.sectors {
+            serial_println!("rom disk full, can't write {}", self.entries[index].name());
+            return false;
+        }
+        self.entries[index].start = self.next_free;
+        self.entries[index].capacity = needed;
+        self.next_free += needed;
+        true
+    }
+
+    // create or replace a file.
+    pub fn write(&mut self, name: &str, data: &[u8]) -> bool
+    {
+        let index = match self.entry_for(name) {
+            Some(i) => i,
+            None => return false,
+        };
+        if !self.make_room(index, sectors_for(data.len()).max(1)) {
+            return false;
+        }
+
+        let start = self.entries[index].start;
+        for (s, chunk) in data.chunks(SECTOR_SIZE).enumerate()
+        {
+            let mut sector = [0u8; SECTOR_SIZE];
+            sector[..chunk.len()].copy_from_slice(chunk);
+            if self.disk.write_sector(start + s as u32, &sector).is_err() {
+                return false;
+            }
+        }
+        self.entries[index].size = data.len() as u32;
+
+        self.flush().is_ok()
+    }
+
+    // create or empty out a file, with room for capacity bytes of appends.
+    pub fn create(&mut self, name: &str, capacity: usize) -> bool
+    {
+        let index = match self.entry_for(name) {
+            Some(i) => i,
+            None => return false,
+        };
+        if !self.make_room(index, sectors_for(capacity).max(1)) {
+            return false;
+        }
+        self.entries[index].size = 0;
+        self.flush().is_ok()
+    }
+
+    // add to the end of a file, inside the extent it has. it never moves for this, so false when it doesn't fit.
+    // only the sectors being added to get written, the one the file ends part way through gets read back first.
+    pub fn append(&mut self, name: &str, data: &[u8]) -> bool
+    {
+        let index = match self.lookup(name) {
+            Some(i) => i,
+            None => return false,
+        };
+        let entry = self.entries[index];
+        let mut offset = entry.size as usize;
+        if offset + data.len() > entry.capacity as usize * SECTOR_SIZE {
+            return false;
+        }
+
+        let mut data = data;
+        while !data.is_empty()
+        {
+            let lba = entry.start + (offset / SECTOR_SIZE) as u32;
+            let within = offset % SECTOR_SIZE;
+            let mut sector = [0u8; SECTOR_SIZE];
+            if within != 0 && self.disk.read_sector(lba, &mut sector).is_err() {
+                return false;
+            }
+            let len = data.len().min(SECTOR_SIZE - within);
+            sector[within..within + len].copy_from_slice(&data[..len]);
+            if self.disk.write_sector(lba, &sector).is_err() {
+                return false;
+            }
+            data = &data[len..];
+            offset += len;
+        }
+        self.entries[index].size = offset as u32;
+
+        self.flush().is_ok()
+    }
+}
+
+lazy_static! {
+    // probed the first time anyone wants a file.
+    static ref ROMDISK: Mutex<Option<RomDisk>> = Mutex::new(RomDisk::find());
+}
+
+pub fn present() -> bool
+{
+    ROMDISK.lock().is_some()
+}
+
+pub fn read(name: &str) -> Option<Vec<u8>>
+{
+    ROMDISK.lock().as_ref()?.read(name)
+}
+
+pub fn write(name: &str, data: &[u8]) -> bool
+{
+    match ROMDISK.lock().as_mut() {
+        Some(disk) => disk.write(name, data),
+        None => false,
+    }
+}
+
+pub fn create(name: &str, capacity: usize) -> bool
+{
+    match ROMDISK.lock().as_mut() {
+        Some(disk) => disk.create(name, capacity),
+        None => false,
+    }
+}
+
+pub fn append(name: &str, data: &[u8]) -> bool
+{
+    match ROMDISK.lock().as_mut() {
+        Some(disk) => disk.append(name, data),
+        None => false,
+    }
+}
+
+// every file on the disk, with its size.
+pub fn list() -> Vec<(String, usize)>
+{
+    match ROMDISK.lock().as_ref() {
+        Some(disk) => disk.files().map(|(name, size)| (String::from(name), size)).collect(),
+        None => Vec::new(),
+    }
+}
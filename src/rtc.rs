@@ -0,0 +1,92 @@
+// the cmos real time clock. the only wall clock a pc has without a network.
+// index port 0x70 picks a register, 0x71 reads it.
+use x86_64::instructions::port::Port;
+
+const CMOS_INDEX: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+const REG_STATUS_A: u8 = 0x0A;
+const REG_STATUS_B: u8 = 0x0B;
+
+fn read_register(register: u8) -> u8
+{
+    unsafe {
+        // bit 7 of the index port is the nmi disable, leave it clear.
+        Port::<u8>::new(CMOS_INDEX).write(register & 0x7F);
+        Port::<u8>::new(CMOS_DATA).read()
+    }
+}
+
+fn bcd_to_binary(value: u8) -> u8
+{
+    (value & 0x0F) + (value >> 4) * 10
+}
+
+// days since 1970-01-01 for a date in the proleptic gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64
+{
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = if y >= 0 { y } else { y - 399 } / 400;
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+// a 12 hour clock's hour, with the top bit as pm, on the 24 hour clock. 12 am is midnight and 12 pm is noon.
+fn hour_from_12(hour: u8) -> u8
+{
+    let pm = hour & 0x80 != 0;
+    let h = hour & 0x7F;
+    if pm { if h == 12 { 12 } else { h + 12 } } else if h == 12 { 0 } else { h }
+}
+
+// seconds since the unix epoch, going by the cmos clock (which is usually utc under qemu).
+pub fn unix_time() -> u64
+{
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        // don't read while the clock is in the middle of ticking over, we'd get half of each second.
+        while read_register(REG_STATUS_A) & 0x80 != 0 {}
+
+        let mut second = read_register(REG_SECONDS);
+        let mut minute = read_register(REG_MINUTES);
+        let mut hour = read_register(REG_HOURS);
+        let mut day = read_register(REG_DAY);
+        let mut month = read_register(REG_MONTH);
+        let mut year = read_register(REG_YEAR);
+        let status_b = read_register(REG_STATUS_B);
+
+        // bit 2 set means binary, otherwise everything's bcd.
+        if status_b & 0x04 == 0 {
+            second = bcd_to_binary(second);
+            minute = bcd_to_binary(minute);
+            hour = bcd_to_binary(hour & 0x7F) | (hour & 0x80);
+            day = bcd_to_binary(day);
+            month = bcd_to_binary(month);
+            year = bcd_to_binary(year);
+        }
+        // bit 1 clear means 12 hour time.
+        if status_b & 0x02 == 0 {
+            hour = hour_from_12(hour);
+        }
+
+        let days = days_from_civil(2000 + year as i64, month as i64, day as i64);
+        (days * 86400 + hour as i64 * 3600 + minute as i64 * 60 + second as i64) as u64
+    })
+}
+
+#[test_case]
+fn test_rtc_12_hour()
+{
+    assert_eq!(hour_from_12(12), 0);
+    assert_eq!(hour_from_12(1), 1);
+    assert_eq!(hour_from_12(0x80 | 12), 12);
+    assert_eq!(hour_from_12(0x80 | 11), 23);
+}
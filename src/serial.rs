@@ -13,6 +13,15 @@ lazy_static! {
     };
 }
 
+lazy_static! {
+    // COM2, kept free for the gdb stub.
+    pub static ref SERIAL2: Mutex<SerialPort> = {
+        let mut serial_port = unsafe { SerialPort::new(0x2F8) };
+        serial_port.init();
+        Mutex::new(serial_port)
+    };
+}
+
 #[doc(hidden)]
 pub fn _print(args: ::core::fmt::Arguments)
 {
@@ -54,3 +63,17 @@ macro_rules! serial_println {
     ));
 }
 
+
+// the uart's line status register, bit 0 is set when a byte has come in.
+const LINE_STATUS: u16 = 0x3F8 + 5;
+
+// a byte from SERIAL1 if one's waiting, without blocking.
+pub fn try_read() -> Option<u8>
+{
+    use x86_64::instructions::port::Port;
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut port = SERIAL1.lock();
+        let ready = unsafe { Port::<u8>::new(LINE_STATUS).read() } & 1 != 0;
+        if ready { Some(port.receive()) } else { None }
+    })
+}
@@ -1 +1,242 @@
 // support for the ac97 sound card architecture, for the actual NES music played during operation.
+// the intel ich flavor of it, which is what qemu's "-device AC97" gives us.
+
+// the card is two sets of io ports:
+// the native audio mixer (NAM) is the codec side, volumes and sample rates.
+// the native audio bus master (NABM) is the dma engine, it walks a list of buffer descriptors in physical memory
+// and streams each buffer out to the codec, then moves on to the next one, until it hits the last valid index.
+// so playing sound is just keeping buffers filled ahead of the one the card is currently on.
+
+use core::sync::atomic::{compiler_fence, Ordering};
+
+use x86_64::instructions::port::Port;
+use x86_64::VirtAddr;
+
+use crate::emulation::AUDIO_BUFFER;
+use crate::memory::virt_to_phys;
+use crate::sound::AudioOutput;
+use crate::{pci, println, serial_println};
+
+// the codec's fixed rate. anything else needs the variable rate extension, which we don't bother with.
+pub const AC97_SAMPLE_RATE: u32 = 48000;
+
+// mixer registers, offsets from the NAM base.
+const NAM_RESET: u16 = 0x00;
+const NAM_MASTER_VOLUME: u16 = 0x02;
+const NAM_PCM_OUT_VOLUME: u16 = 0x18;
+
+// bus master registers, offsets from the NABM base. 0x10 is the pcm out box.
+const NABM_PCM_OUT: u16 = 0x10;
+const NABM_GLOBAL_CONTROL: u16 = 0x2C;
+
+// per-box registers, offsets from the box.
+const BOX_BDL_BASE: u16 = 0x00;
+const BOX_CURRENT_INDEX: u16 = 0x04;
+const BOX_LAST_VALID_INDEX: u16 = 0x05;
+const BOX_STATUS: u16 = 0x06;
+const BOX_CONTROL: u16 = 0x0B;
+
+const CONTROL_RUN: u8 = 1 << 0;
+const CONTROL_RESET: u8 = 1 << 1;
+
+// write-one-to-clear status bits: last valid buffer completed, buffer completed, fifo error.
+const STATUS_CLEAR: u16 = (1 << 2) | (1 << 3) | (1 << 4);
+
+// the hardware's descriptor list is always 32 long.
+const BDL_ENTRIES: usize = 32;
+
+// how many actual buffers we cycle the descriptors over. each is exactly one page so it's physically contiguous.
+const BUFFER_COUNT: usize = 8;
+const BUFFER_SAMPLES: usize = 4096 / 2;
+// interleaved stereo, so half as many frames as samples.
+const BUFFER_FRAMES: usize = BUFFER_SAMPLES / 2;
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct BufferDescriptor
+{
+    // physical address of the samples.
+    address: u32,
+    // how many 16 bit samples, not frames, are in it.
+    samples: u16,
+    flags: u16,
+}
+
+#[repr(C, align(4096))]
+struct DmaBuffer([i16; BUFFER_SAMPLES]);
+
+#[repr(C, align(4096))]
+struct BufferDescriptorList([BufferDescriptor; BDL_ENTRIES]);
+
+// the card reads these behind our back, so they live in static memory that never moves.
+static mut BDL: BufferDescriptorList = BufferDescriptorList(
+    [BufferDescriptor { address: 0, samples: 0, flags: 0 }; BDL_ENTRIES]
+);
+const EMPTY_BUFFER: DmaBuffer = DmaBuffer([0; BUFFER_SAMPLES]);
+static mut BUFFERS: [DmaBuffer; BUFFER_COUNT] = [EMPTY_BUFFER; BUFFER_COUNT];
+
+pub struct Ac97
+{
+    nam: u16,
+    nabm: u16,
+    // the descriptor index we'll fill next. everything from the card's current index up to this is queued.
+    next: u8,
+    running: bool,
+}
+
+fn physical_u32<T>(ptr: *const T) -> Option<u32>
+{
+    let phys = virt_to_phys(VirtAddr::from_ptr(ptr))?.as_u64();
+    // the descriptor list only has room for 32 bit addresses.
+    if phys > u32::MAX as u64 {
+        None
+    } else {
+        Some(phys as u32)
+    }
+}
+
+impl Ac97
+{
+    // find the card on the pci bus and get the pcm out dma engine ready to go.
+    pub fn init() -> Option<Ac97>
+    {
+        // multimedia controller, audio device.
+        let device = pci::find_class(0x04, 0x01)?;
+        device.enable();
+
+        let nam = device.io_bar(0)?;
+        let nabm = device.io_bar(1)?;
+        println!("ac97 found, mixer at {:#x}, bus master at {:#x}", nam, nabm);
+
+        unsafe {
+            // take the codec out of cold reset, then reset the mixer to its defaults.
+            Port::<u32>::new(nabm + NABM_GLOBAL_CONTROL).write(1 << 1);
+            crate::time::hard_sleep(1);
+            Port::<u16>::new(nam + NAM_RESET).write(1);
+
+            // 0 is no attenuation, for both channels.
+            Port::<u16>::new(nam + NAM_MASTER_VOLUME).write(0x0000);
+            Port::<u16>::new(nam + NAM_PCM_OUT_VOLUME).write(0x0808);
+        }
+
+        let mut ac97 = Ac97 { nam, nabm, next: 0, running: false };
+        if !ac97.setup_pcm_out() {
+            serial_println!("ac97: dma buffers aren't below 4GiB, giving up");
+            return None;
+        }
+
+        Some(ac97)
+    }
+
+    fn pcm_out(&self, register: u16) -> u16
+    {
+        self.nabm + NABM_PCM_OUT + register
+    }
+
+    fn setup_pcm_out(&mut self) -> bool
+    {
+        unsafe {
+            // reset the box, the bit clears itself when it's done.
+            let mut control = Port::<u8>::new(self.pcm_out(BOX_CONTROL));
+            control.write(CONTROL_RESET);
+            while control.read() & CONTROL_RESET != 0 {}
+
+            for i in 0..BDL_ENTRIES
+            {
+                let address = match physical_u32(BUFFERS[i % BUFFER_COUNT].0.as_ptr()) {
+                    Some(a) => a,
+                    None => return false,
+                };
+                BDL.0[i] = BufferDescriptor {
+                    address,
+                    samples: BUFFER_SAMPLES as u16,
+                    // no interrupt on completion, nothing's listening for one. the card's position gets polled.
+                    flags: 0,
+                };
+            }
+
+            let bdl = match physical_u32(&BDL as *const BufferDescriptorList) {
+                Some(a) => a,
+                None => return false,
+            };
+            Port::<u32>::new(self.pcm_out(BOX_BDL_BASE)).write(bdl);
+        }
+
+        true
+    }
+
+    fn current_index(&self) -> u8
+    {
+        unsafe { Port::<u8>::new(self.pcm_out(BOX_CURRENT_INDEX)).read() }
+    }
+
+    // how many descriptors are filled and waiting, counting the one being played.
+    fn queued(&self) -> usize
+    {
+        if !self.running {
+            return 0;
+        }
+        (self.next as usize + BDL_ENTRIES - self.current_index() as usize) % BDL_ENTRIES
+    }
+
+    // copy one buffer's worth of samples out of the audio buffer, and tell the card it's valid.
+    fn fill_next(&mut self)
+    {
+        let index = self.next as usize;
+        let buffer = unsafe { &mut BUFFERS[index % BUFFER_COUNT].0 };
+        x86_64::instructions::interrupts::without_interrupts(|| {
+            let mut samples = AUDIO_BUFFER.lock();
+            for frame in 0..BUFFER_FRAMES
+            {
+                // the apu is mono, so both channels get the same thing.
+                let sample = samples.deque();
+                buffer[frame * 2] = sample;
+                buffer[frame * 2 + 1] = sample;
+            }
+        });
+
+        // the samples have to be in memory before the card is allowed to look at them.
+        compiler_fence(Ordering::SeqCst);
+
+        unsafe {
+            Port::<u8>::new(self.pcm_out(BOX_LAST_VALID_INDEX)).write(index as u8);
+            Port::<u16>::new(self.pcm_out(BOX_STATUS)).write(STATUS_CLEAR);
+        }
+        self.next = ((index + 1) % BDL_ENTRIES) as u8;
+    }
+
+    fn start(&mut self)
+    {
+        unsafe {
+            // if the card ran dry it halts on the last valid index, setting run again picks it back up.
+            Port::<u8>::new(self.pcm_out(BOX_CONTROL)).write(CONTROL_RUN);
+        }
+        self.running = true;
+    }
+}
+
+impl AudioOutput for Ac97
+{
+    fn sample_rate(&self) -> u32
+    {
+        AC97_SAMPLE_RATE
+    }
+
+    // keep every free dma buffer topped up, as long as there's a whole buffer of samples waiting.
+    fn service(&mut self)
+    {
+        // never refill the page the card is playing from, it'd get torn.
+        while self.queued() < BUFFER_COUNT - 1
+            && x86_64::instructions::interrupts::without_interrupts(|| AUDIO_BUFFER.lock().len()) >= BUFFER_FRAMES
+        {
+            self.fill_next();
+            self.start();
+        }
+    }
+
+    // the card just walks its descriptors, nobody hears about it until service looks.
+    fn wakes_cpu(&self) -> bool
+    {
+        false
+    }
+}
@@ -1,2 +1,71 @@
+extern crate alloc;
+
 pub mod ac;
 pub mod pc_speaker;
+pub mod sb16;
+
+// something the emulator can play sound out of.
+// the apu's samples get resampled to sample_rate() and queued up in emulation::AUDIO_BUFFER, then it's up to the
+// backend to get them out of there and into the hardware.
+pub trait AudioOutput
+{
+    fn sample_rate(&self) -> u32;
+
+    // move whatever's queued into the hardware.
+    // backends that drain the buffer from their own interrupt don't need to do anything here.
+    fn service(&mut self) {}
+
+    // whether taking samples interrupts the cpu, so something waiting on the buffer can hlt until it does. backends
+    // that only get looked at through service say no, or the wait would be until the next 18.2hz timer tick.
+    fn wakes_cpu(&self) -> bool
+    {
+        true
+    }
+}
+
+// no sound at all. throws the samples away as fast as they come, so nothing ever waits on it either.
+// the test harness runs on this, to go as fast as the cpu allows.
+pub struct Silence;
+
+impl AudioOutput for Silence
+{
+    fn sample_rate(&self) -> u32
+    {
+        crate::emulation::APU_SAMPLE_RATE
+    }
+
+    fn service(&mut self)
+    {
+        x86_64::instructions::interrupts::without_interrupts(|| {
+            let mut buffer = crate::emulation::AUDIO_BUFFER.lock();
+            while buffer.len() > 0 {
+                buffer.deque();
+            }
+        });
+    }
+}
+
+// pick a backend by the "audio" boot option: ac97, sb16, pcspeaker, none, or auto (the default), which takes the first
+// card that answers in that order. the pc speaker is always there, so it's the last resort.
+pub fn open_output() -> alloc::boxed::Box<dyn AudioOutput>
+{
+    use alloc::boxed::Box;
+
+    let choice = crate::cmdline::get_or("audio", "auto");
+    crate::println!("audio backend: {}", choice);
+
+    if choice == "none" {
+        return Box::new(Silence);
+    }
+    if choice == "ac97" || choice == "auto" {
+        if let Some(ac97) = ac::Ac97::init() {
+            return Box::new(ac97);
+        }
+    }
+    if choice == "sb16" || choice == "auto" {
+        if let Some(sb16) = sb16::Sb16::init() {
+            return Box::new(sb16);
+        }
+    }
+    Box::new(pc_speaker::PcSpeaker::new())
+}
@@ -1,4 +1,8 @@
-use crate::println;
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use x86_64::instructions::port::Port;
+
+use crate::emulation::AUDIO_BUFFER;
+use crate::{println, time};
 
 // PIT - programmable interval timer.
 // The PIT's channel 2 data port
@@ -78,3 +82,99 @@ pub fn drum_roll(tick_gap: u64, times_around: u64, base_frequency: u32)
         play_sound(base_frequency + 50, tick_gap);
     }
 }
+
+//// PWM PLAYBACK
+// the speaker can only be pushed all the way out or let go, there's no volume. but if we flip it fast enough, the
+// cone can't keep up, and it ends up sitting at the average position. so, the amount of time it's held out during
+// each sample period is the sample.
+// we fire the timer interrupt once per sample, and every time it fires we load channel 2 in one-shot mode with a
+// count proportional to the sample. the channel holds the speaker low until the count runs out, then lets it go
+// until we reload it next interrupt.
+
+// how many PIT clocks make up one sample. keep this under 256 so the count only takes a single lobyte write.
+pub const PWM_PERIOD: u32 = 64;
+
+// ~18.6khz, close enough to the pulse channels' useful range and still bearable for the timer interrupt.
+pub const PWM_SAMPLE_RATE: u32 = time::PIT_FREQUENCY / PWM_PERIOD;
+
+static PWM_ENABLED: AtomicBool = AtomicBool::new(false);
+// the count loaded for the last sample, to load again when there's no new one to hand. starts at the midpoint.
+static LAST_COUNT: AtomicU8 = AtomicU8::new((PWM_PERIOD / 2) as u8);
+
+// speed the timer interrupt up to the sample rate and hand the speaker over to channel 2's one-shot output.
+pub fn pwm_start()
+{
+    unsafe {
+        // channel 2, lobyte only access, mode 0 (interrupt on terminal count, our one-shot), binary.
+        Port::<u8>::new(PIT_COMMAND_PORT).write(0x90);
+        Port::<u8>::new(PIT_CHANNEL_2_DATA_PORT).write((PWM_PERIOD / 2) as u8);
+
+        // gate channel 2 and connect its output to the speaker.
+        let mut control_port = Port::<u8>::new(PC_SPEAKER_CONTROL_PORT);
+        let current_value = control_port.read();
+        control_port.write(current_value | 0x03);
+    }
+
+    time::set_frequency(PWM_SAMPLE_RATE);
+    PWM_ENABLED.store(true, Ordering::Release);
+    println!("pc speaker playing at {}hz", time::get_frequency());
+}
+
+pub fn pwm_stop()
+{
+    PWM_ENABLED.store(false, Ordering::Release);
+    time::set_frequency(time::DEFAULT_FREQUENCY);
+    stop_sound();
+}
+
+pub fn pwm_enabled() -> bool
+{
+    PWM_ENABLED.load(Ordering::Acquire)
+}
+
+// called from the timer interrupt, once per sample.
+pub fn pwm_tick()
+{
+    if !pwm_enabled()
+    {
+        return;
+    }
+
+    // the emulator holds this lock with interrupts off, but just in case, never spin on it in here.
+    // if we can't get a sample, repeating the last duty cycle is the least audible thing to do.
+    // not reloading the channel at all would leave the speaker let go for the whole sample, a click.
+    let count = match AUDIO_BUFFER.try_lock() {
+        Some(mut buffer) => {
+            let sample = buffer.deque();
+            // map -32768..32767 onto 1..PWM_PERIOD - 1. a count of 0 would mean a full 65536 count in mode 0.
+            let count = (1 + (((sample as i32 + 32768) as u32 * (PWM_PERIOD - 2)) >> 16)) as u8;
+            LAST_COUNT.store(count, Ordering::Relaxed);
+            count
+        }
+        None => LAST_COUNT.load(Ordering::Relaxed),
+    };
+
+    unsafe {
+        Port::<u8>::new(PIT_CHANNEL_2_DATA_PORT).write(count);
+    }
+}
+
+// the pc speaker as an emulator sound backend. the timer interrupt does all the work.
+pub struct PcSpeaker;
+
+impl PcSpeaker
+{
+    pub fn new() -> PcSpeaker
+    {
+        pwm_start();
+        PcSpeaker
+    }
+}
+
+impl AudioOutput for PcSpeaker
+{
+    fn sample_rate(&self) -> u32
+    {
+        PWM_SAMPLE_RATE
+    }
+}
@@ -0,0 +1,233 @@
+// sound blaster 16, for machines (and "-device sb16" qemu setups) that don't have an ac97.
+// it's an isa card, so no pci probing: it lives at a well known port, and it doesn't master the bus itself.
+// instead the motherboard's 8237 dma controller streams a buffer to it, and the card raises an irq every time it's
+// played a block. we run the dma in auto-init mode over a buffer split in two halves, with the dsp's block size set
+// to one half. every irq means one half just finished, so we refill it while the other one plays.
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use x86_64::instructions::port::Port;
+use x86_64::VirtAddr;
+
+use crate::emulation::{APU_SAMPLE_RATE, AUDIO_BUFFER};
+use crate::memory::virt_to_phys;
+use crate::sound::AudioOutput;
+use crate::{println, serial_println};
+
+const SB_BASE: u16 = 0x220;
+const DSP_RESET: u16 = SB_BASE + 0x6;
+const DSP_READ: u16 = SB_BASE + 0xA;
+const DSP_WRITE: u16 = SB_BASE + 0xC;
+const DSP_READ_STATUS: u16 = SB_BASE + 0xE;
+// reading this acknowledges a 16 bit dma interrupt.
+const DSP_ACK_16: u16 = SB_BASE + 0xF;
+const MIXER_ADDRESS: u16 = SB_BASE + 0x4;
+const MIXER_DATA: u16 = SB_BASE + 0x5;
+
+// dsp commands.
+const DSP_SET_OUTPUT_RATE: u8 = 0x41;
+const DSP_SPEAKER_ON: u8 = 0xD1;
+const DSP_VERSION: u8 = 0xE1;
+// 16 bit output, auto-init, fifo on.
+const DSP_OUTPUT_16_AUTO: u8 = 0xB6;
+// mode byte for the above: signed, mono.
+const DSP_MODE_SIGNED_MONO: u8 = 0x10;
+const DSP_PAUSE_16: u8 = 0xD5;
+
+// mixer register that picks the card's irq line.
+const MIXER_IRQ_SELECT: u8 = 0x80;
+const MIXER_IRQ_5: u8 = 0x02;
+pub const SB16_IRQ: u8 = 5;
+
+// 16 bit transfers go through the second (slave) 8237, channel 5, which counts in words.
+const DMA_MASK: u16 = 0xD4;
+const DMA_MODE: u16 = 0xD6;
+const DMA_CLEAR_FLIP_FLOP: u16 = 0xD8;
+const DMA_CHANNEL_5_ADDRESS: u16 = 0xC4;
+const DMA_CHANNEL_5_COUNT: u16 = 0xC6;
+const DMA_CHANNEL_5_PAGE: u16 = 0x8B;
+// channel 5 is channel 1 on the slave controller.
+const DMA_CHANNEL: u8 = 1;
+// single transfer, auto-init, memory to device.
+const DMA_MODE_PLAYBACK: u8 = 0x58;
+
+// the apu's own rate, the card can do it natively so nothing gets resampled.
+pub const SB16_SAMPLE_RATE: u32 = APU_SAMPLE_RATE;
+
+// a page per half, ~23ms each.
+const HALF_SAMPLES: usize = 2048;
+
+// isa dma can only reach the first 16MiB, and 16 bit transfers can't cross a 128KiB boundary.
+// aligning the whole buffer to its own size takes care of the second one.
+#[repr(C, align(8192))]
+struct DmaBuffer([i16; HALF_SAMPLES * 2]);
+
+static mut BUFFER: DmaBuffer = DmaBuffer([0; HALF_SAMPLES * 2]);
+
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+// the half the card is going to finish next, which is the one we refill on the next irq.
+static PLAYING_HALF: AtomicUsize = AtomicUsize::new(0);
+
+pub struct Sb16;
+
+fn dsp_write(value: u8)
+{
+    unsafe {
+        let mut status = Port::<u8>::new(DSP_WRITE);
+        // bit 7 clear means the dsp is ready for another byte.
+        while status.read() & 0x80 != 0 {}
+        status.write(value);
+    }
+}
+
+fn dsp_read() -> Option<u8>
+{
+    unsafe {
+        let mut status = Port::<u8>::new(DSP_READ_STATUS);
+        for _ in 0..0x10000
+        {
+            if status.read() & 0x80 != 0 {
+                return Some(Port::<u8>::new(DSP_READ).read());
+            }
+        }
+    }
+    None
+}
+
+// pulse the reset line, a dsp that's actually there answers with 0xaa.
+fn dsp_reset() -> bool
+{
+    unsafe {
+        let mut reset = Port::<u8>::new(DSP_RESET);
+        reset.write(1);
+        // needs to be held for 3 microseconds, a few slow port reads is plenty.
+        for _ in 0..8
+        {
+            Port::<u8>::new(0x80).read();
+        }
+        reset.write(0);
+    }
+    dsp_read() == Some(0xAA)
+}
+
+// copy the next half's worth of samples out of the audio buffer.
+fn fill_half(half: usize)
+{
+    let buffer = unsafe { &mut BUFFER.0[half * HALF_SAMPLES..(half + 1) * HALF_SAMPLES] };
+    // we're called from the irq too, so the lock can't be allowed to spin.
+    match AUDIO_BUFFER.try_lock() {
+        Some(mut samples) => {
+            for s in buffer.iter_mut()
+            {
+                *s = samples.deque();
+            }
+        }
+        None => {
+            for s in buffer.iter_mut()
+            {
+                *s = 0;
+            }
+        }
+    }
+}
+
+impl Sb16
+{
+    pub fn init() -> Option<Sb16>
+    {
+        if !dsp_reset() {
+            return None;
+        }
+
+        dsp_write(DSP_VERSION);
+        let major = dsp_read()?;
+        let minor = dsp_read()?;
+        println!("sound blaster found, dsp version {}.{}", major, minor);
+        // 16 bit auto-init output showed up with the dsp 4.x in the sb16.
+        if major < 4 {
+            return None;
+        }
+
+        let phys = virt_to_phys(VirtAddr::from_ptr(unsafe { &BUFFER }))?.as_u64();
+        let phys_end = virt_to_phys(VirtAddr::from_ptr(unsafe { &BUFFER.0[HALF_SAMPLES] }))?.as_u64();
+        // both halves have to be physically next to each other and within isa dma's reach.
+        if phys_end != phys + (HALF_SAMPLES * 2) as u64 || phys + (HALF_SAMPLES * 4) as u64 > 0x100_0000 {
+            serial_println!("sb16: dma buffer at {:#x} isn't usable for isa dma", phys);
+            return None;
+        }
+
+        fill_half(0);
+        fill_half(1);
+
+        unsafe {
+            // route the card to irq 5, then let the pic through for it.
+            Port::<u8>::new(MIXER_ADDRESS).write(MIXER_IRQ_SELECT);
+            Port::<u8>::new(MIXER_DATA).write(MIXER_IRQ_5);
+            crate::interrupts::unmask_irq(SB16_IRQ);
+
+            // program the dma controller: mask the channel while we set it up.
+            Port::<u8>::new(DMA_MASK).write(0x04 | DMA_CHANNEL);
+            Port::<u8>::new(DMA_CLEAR_FLIP_FLOP).write(0);
+            Port::<u8>::new(DMA_MODE).write(DMA_MODE_PLAYBACK | DMA_CHANNEL);
+
+            // the 16 bit controller wants a word address and word count, with the page covering bits 16-23.
+            let words = (phys >> 1) as u16;
+            Port::<u8>::new(DMA_CHANNEL_5_ADDRESS).write(words as u8);
+            Port::<u8>::new(DMA_CHANNEL_5_ADDRESS).write((words >> 8) as u8);
+            Port::<u8>::new(DMA_CHANNEL_5_PAGE).write((phys >> 16) as u8);
+
+            let count = (HALF_SAMPLES * 2 - 1) as u16;
+            Port::<u8>::new(DMA_CHANNEL_5_COUNT).write(count as u8);
+            Port::<u8>::new(DMA_CHANNEL_5_COUNT).write((count >> 8) as u8);
+
+            Port::<u8>::new(DMA_MASK).write(DMA_CHANNEL);
+        }
+
+        dsp_write(DSP_SET_OUTPUT_RATE);
+        dsp_write((SB16_SAMPLE_RATE >> 8) as u8);
+        dsp_write(SB16_SAMPLE_RATE as u8);
+        dsp_write(DSP_SPEAKER_ON);
+
+        PLAYING_HALF.store(0, Ordering::Relaxed);
+        ACTIVE.store(true, Ordering::Release);
+
+        // the block size is one half, so we get an irq at the end of each.
+        let block = (HALF_SAMPLES - 1) as u16;
+        dsp_write(DSP_OUTPUT_16_AUTO);
+        dsp_write(DSP_MODE_SIGNED_MONO);
+        dsp_write(block as u8);
+        dsp_write((block >> 8) as u8);
+
+        Some(Sb16)
+    }
+
+    pub fn stop(&mut self)
+    {
+        ACTIVE.store(false, Ordering::Release);
+        dsp_write(DSP_PAUSE_16);
+    }
+}
+
+impl AudioOutput for Sb16
+{
+    fn sample_rate(&self) -> u32
+    {
+        SB16_SAMPLE_RATE
+    }
+}
+
+// called from the irq 5 handler. the card just finished a half, refill it while it plays the other.
+pub fn handle_interrupt()
+{
+    unsafe {
+        Port::<u8>::new(DSP_ACK_16).read();
+    }
+
+    if !ACTIVE.load(Ordering::Acquire) {
+        return;
+    }
+
+    let finished = PLAYING_HALF.load(Ordering::Relaxed);
+    fill_half(finished);
+    PLAYING_HALF.store(finished ^ 1, Ordering::Relaxed);
+}
@@ -1,32 +1,67 @@
-use core::convert::TryInto;
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
 
-use lazy_static::lazy_static;
+use x86_64::instructions::port::Port;
 
-use crate::{println, print};
+// the PIT's input clock. every channel divides this down to get its own frequency.
+pub const PIT_FREQUENCY: u32 = 1193182;
+
+// what the PIT runs channel 0 at out of the box, with a divisor of 0 (65536). about 18.2 interrupts a second.
+pub const DEFAULT_FREQUENCY: u32 = PIT_FREQUENCY / 65536;
+
+const PIT_CHANNEL_0_DATA_PORT: u16 = 0x40;
+const PIT_COMMAND_PORT: u16 = 0x43;
 
 // variable that holds the ticks counted up by the PIC timer in PIC offset 0
-lazy_static! {
-    pub static ref tick_count: spin::Mutex<u64> = 
-        spin::Mutex::new(0);
-}
+// this is an atomic rather than a mutex, the timer can fire thousands of times a second and it can't be allowed
+// to spin on a lock that the code it interrupted is holding.
+pub static TICK_COUNT: AtomicU64 = AtomicU64::new(0);
+
+// how many times a second tick() is being called right now.
+static FREQUENCY: AtomicU32 = AtomicU32::new(DEFAULT_FREQUENCY);
 
 // called directly from the interrupt.
 pub fn tick()
 {
-    *tick_count.lock() += 1;
-    print!(".");
+    TICK_COUNT.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn ticks() -> u64
+{
+    TICK_COUNT.load(Ordering::Relaxed)
+}
+
+pub fn get_frequency() -> u32
+{
+    FREQUENCY.load(Ordering::Relaxed)
+}
+
+// reprogram PIT channel 0, the one wired to the timer interrupt, to fire at roughly hz times a second.
+// note that everything measured in ticks (like hard_sleep) speeds up with it.
+pub fn set_frequency(hz: u32)
+{
+    let divisor = (PIT_FREQUENCY / hz).max(1).min(65535) as u16;
+
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        unsafe {
+            // channel 0, lobyte/hibyte access, mode 3 (square wave), binary.
+            Port::<u8>::new(PIT_COMMAND_PORT).write(0x36);
+            Port::<u8>::new(PIT_CHANNEL_0_DATA_PORT).write((divisor & 0xFF) as u8);
+            Port::<u8>::new(PIT_CHANNEL_0_DATA_PORT).write((divisor >> 8) as u8);
+        }
+        FREQUENCY.store(PIT_FREQUENCY / divisor as u32, Ordering::Relaxed);
+    });
 }
 
 // block the thread?
 pub fn hard_sleep(ticks: u64)
 {
-    let tick_offset = *tick_count.lock();
+    let tick_offset = TICK_COUNT.load(Ordering::Relaxed);
     let target = ticks + tick_offset;
-    
 
-    loop 
+
+    loop
     {
-        if target < *tick_count.lock()
+        if target < TICK_COUNT.load(Ordering::Relaxed)
         {
             break;
         }
@@ -123,6 +123,20 @@ impl Writer {
         }
     }
 
+    // put bytes straight onto the screen at a spot, in a color, for full screen displays. doesn't move the cursor,
+    // and anything past the end of the row gets dropped.
+    pub fn write_at(&mut self, row: usize, col: usize, bytes: &[u8], color: Color)
+    {
+        if row >= BUFFER_HEIGHT {
+            return;
+        }
+        let color_code = ColorCode::new(color, Color::Black);
+        for (i, &byte) in bytes.iter().enumerate().take(BUFFER_WIDTH.saturating_sub(col))
+        {
+            self.buffer.chars[row][col + i].write(ScreenChar { ascii_character: byte, color_code });
+        }
+    }
+
     fn new_line(&mut self) {
         // it is absolutely crucial that we don't index out of bounds here.
         // it's 1, not 0.
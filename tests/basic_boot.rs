@@ -7,7 +7,7 @@
 // we have to redefine all the exclusions and weird things we did in main, because this is a STANDALONE executable that
 // will be picked up and run by cargo test.
 
-use core::panic::PanicInfo;
+use nesos::println;
 
 #[no_mangle]
 pub extern "C" fn _start() -> !
@@ -17,7 +17,9 @@ pub extern "C" fn _start() -> !
     loop {}
 }
 
-pub fn test_runner(tests: &[&dyn Fn()])
+// the vga buffer works straight after boot, before anything else has been set up.
+#[test_case]
+fn test_println()
 {
-    unimplemented!();
+    println!("test_println output");
 }
@@ -0,0 +1,350 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(nesos::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+// runs test roms through the emulator headless and fails cargo test if any of them do.
+// there's always the little rom built below, which checks the harness itself works. real test suites come off the rom
+// disk: add the drive to test-args in Cargo.toml and put the roms on it (see the README), plus optionally a
+// romtests.txt saying how to run each one, a line per rom:
+//   <file> [frames] [blargg | nestest | <crc32 of the last frame, in hex>]
+// without one, every .nes on the disk gets run as a blargg style test.
+// if the disk has nestest.nes and nestest.log, the cpu also gets traced against the log instruction by instruction.
+// Machine::tick, which the clock runs on, gets checked against runes' bus tick on the game we ship with.
+// blargg's mmc3_test roms get their own test, since scanline irqs are the thing most likely to quietly break, and
+// they have to be on the disk under their usual names: a missing one fails. to run without them on purpose, put
+// skip_mmc3 in the boot options (-fw_cfg name=opt/nesos/cmdline,string=skip_mmc3).
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use nesos::emulation::harness::{run_nestest_trace, run_test_rom, run_trace, Protocol};
+use nesos::emulation::region;
+use nesos::memory::BootInfoFrameAllocator;
+use nesos::{allocator, cmdline, exit_qemu, fw_cfg, romdisk, serial_println, QemuExitCode};
+use x86_64::VirtAddr;
+
+// 30 seconds of emulated time, which is plenty for blargg's longest.
+const DEFAULT_FRAMES: u64 = 1800;
+const NESTEST_FRAMES: u64 = 10;
+
+// mmc3_test_2's singles. 6-MMC3_alt checks the older chip revision's behaviour instead, which we don't do, so it's
+// left out here and skipped when running everything on the disk.
+const MMC3_TESTS: [&str; 5] = [
+    "1-clocking.nes",
+    "2-details.nes",
+    "3-A12_clocking.nes",
+    "4-scanline_timing.nes",
+    "5-MMC3.nes",
+];
+const MMC3_ALT_TEST: &str = "6-MMC3_alt.nes";
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> !
+{
+    nesos::init();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { nesos::memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap init failed");
+
+    test_main();
+
+    loop {}
+}
+
+// ines 1.0 nrom, byte 8 is 0, which means 8k of prg ram. blargg's singles all look like this.
+const INES_NROM: [u8; 8] = [b'N', b'E', b'S', 0x1A, 1, 0, 0, 0];
+// nes 2.0 nrom with 8k of prg ram in byte 10.
+const NES2_NROM: [u8; 11] = [b'N', b'E', b'S', 0x1A, 1, 0, 0, 0x08, 0, 0, 0x07];
+// nes 2.0 nrom saying there's no ram at all, so there's nowhere to report to.
+const NES2_NROM_NO_RAM: [u8; 11] = [b'N', b'E', b'S', 0x1A, 1, 0, 0, 0x08, 0, 0, 0];
+
+// a 16KiB nrom with program at $C000 and the nmi, reset and irq vectors pointing into it.
+fn nrom(header: &[u8], program: &[u8], vectors: [u16; 3]) -> Vec<u8>
+{
+    let mut rom = vec![0u8; 16 + 0x4000];
+    rom[..header.len()].copy_from_slice(header);
+    let prg = &mut rom[16..];
+    prg[..program.len()].copy_from_slice(program);
+    for (i, vector) in vectors.iter().enumerate()
+    {
+        prg[0x3FFA + i * 2..0x3FFC + i * 2].copy_from_slice(&vector.to_le_bytes());
+    }
+    rom
+}
+
+// a 16KiB nrom that reports through $6000 like blargg's roms do: running, signature, a quick adc overflow check,
+// then "ok" and a result of 0. or 1 if the cpu got the overflow wrong.
+fn self_test_rom(header: &[u8]) -> Vec<u8>
+{
+    let program: [u8; 0x3B] = [
+        0x78, //             sei
+        0xD8, //             cld
+        0xA9, 0x80, //       lda #$80
+        0x8D, 0x00, 0x60, // sta $6000
+        0xA9, 0xDE, //       lda #$de
+        0x8D, 0x01, 0x60, // sta $6001
+        0xA9, 0xB0, //       lda #$b0
+        0x8D, 0x02, 0x60, // sta $6002
+        0xA9, 0x61, //       lda #$61
+        0x8D, 0x03, 0x60, // sta $6003
+        0x18, //             clc
+        0xA9, 0x40, //       lda #$40
+        0x69, 0x40, //       adc #$40
+        0x50, 0x15, //       bvc fail
+        0xA9, b'o', //       lda #'o'
+        0x8D, 0x04, 0x60, // sta $6004
+        0xA9, b'k', //       lda #'k'
+        0x8D, 0x05, 0x60, // sta $6005
+        0xA9, 0x00, //       lda #0
+        0x8D, 0x06, 0x60, // sta $6006
+        0x8D, 0x00, 0x60, // sta $6000
+        0x4C, 0x2F, 0xC0, // jmp * ($c02f)
+        0xA9, 0x01, //       fail: lda #1
+        0x8D, 0x00, 0x60, // sta $6000
+        0x4C, 0x37, 0xC0, // jmp * ($c037)
+        0x40, //             rti ($c03a), for nmi and irq
+    ];
+
+    nrom(header, &program, [0xC03A, 0xC000, 0xC03A])
+}
+
+fn fail()
+{
+    serial_println!("[failed]");
+    exit_qemu(QemuExitCode::Failed);
+}
+
+#[test_case]
+fn harness_self_test()
+{
+    for (name, header) in [("self-test", &INES_NROM[..]), ("self-test nes2", &NES2_NROM[..])].iter()
+    {
+        let report = run_test_rom(&self_test_rom(header), Protocol::Blargg, 60);
+        report.print(name);
+        if !report.passed() || report.message.as_str() != "ok" {
+            fail();
+        }
+    }
+    // and when the header says there's no ram, there isn't.
+    let report = run_test_rom(&self_test_rom(&NES2_NROM_NO_RAM), Protocol::Blargg, 60);
+    report.print("self-test nes2 no ram");
+    if report.passed() {
+        fail();
+    }
+}
+
+// Machine::tick copies what the bus tick does. at 3 dots it has to do exactly the same thing.
+#[test_case]
+fn machine_tick_matches_bus_tick()
+{
+    // a few frames, through the title screen's first nmis.
+    const INSTRUCTIONS: usize = 50_000;
+    let smb = include_bytes!("../rom/smb.nes");
+    let by_hand = run_trace(smb, INSTRUCTIONS);
+    region::tick_by_bus(true);
+    let by_bus = run_trace(smb, INSTRUCTIONS);
+    region::tick_by_bus(false);
+
+    let ((bus_lines, bus_hash), (hand_lines, hand_hash)) = match (by_bus, by_hand) {
+        (Some(bus), Some(hand)) => (bus, hand),
+        _ => return fail(),
+    };
+    if let Some(i) = (0..bus_lines.len()).find(|&i| hand_lines.get(i) != Some(&bus_lines[i])) {
+        serial_println!("Machine::tick diverged from the bus tick at instruction {}:", i + 1);
+        serial_println!("  bus  {}", bus_lines[i]);
+        serial_println!("  hand {}", hand_lines.get(i).map(|l| l.as_str()).unwrap_or("(nothing)"));
+        return fail();
+    }
+    if bus_lines.len() != hand_lines.len() || bus_hash != hand_hash {
+        serial_println!("Machine::tick drew a different frame from the bus tick");
+        fail();
+    }
+}
+
+// the trace reads an instruction's operand before it runs, and reading $2002 clears vblank. the nmi handler's
+// lda $2002 has to still see it set afterwards.
+#[test_case]
+fn tracing_leaves_registers_alone()
+{
+    let program = [
+        0x78, //             sei
+        0xD8, //             cld
+        0xA9, 0x80, //       lda #$80
+        0x8D, 0x00, 0x20, // sta $2000, nmi on
+        0x4C, 0x07, 0xC0, // jmp * ($c007)
+        0xAD, 0x02, 0x20, // nmi ($c00a): lda $2002
+        0x40, //             rti
+    ];
+    // a frame and a half of the jmp.
+    let (lines, _) = match run_trace(&nrom(&INES_NROM, &program, [0xC00A, 0xC000, 0xC00D]), 15_000) {
+        Some(trace) => trace,
+        None => return fail(),
+    };
+    let after = lines.iter().position(|l| l.starts_with("C00A")).and_then(|i| lines.get(i + 1));
+    // A:xx, see trace.rs.
+    match after.and_then(|l| l.get(50..52)).and_then(|a| u8::from_str_radix(a, 16).ok()) {
+        Some(a) if a & 0x80 != 0 => {}
+        _ => {
+            serial_println!("lda $2002 didn't see vblank with tracing on: {:?}", after);
+            fail();
+        }
+    }
+}
+
+fn parse_protocol(word: Option<&str>, name: &str) -> Protocol
+{
+    match word {
+        Some("blargg") => Protocol::Blargg,
+        Some("nestest") => Protocol::Nestest,
+        Some(hash) => u32::from_str_radix(hash, 16).map(Protocol::FrameHash).unwrap_or(Protocol::Blargg),
+        None if name.contains("nestest") => Protocol::Nestest,
+        None => Protocol::Blargg,
+    }
+}
+
+fn default_frames(protocol: Protocol) -> u64
+{
+    match protocol {
+        Protocol::Nestest => NESTEST_FRAMES,
+        _ => DEFAULT_FRAMES,
+    }
+}
+
+// (file, frames, protocol) for everything we've been given to run.
+fn attached_tests() -> Vec<(String, u64, Protocol)>
+{
+    let mut tests = Vec::new();
+
+    let manifest = romdisk::read("romtests.txt").and_then(|m| String::from_utf8(m).ok());
+    match manifest {
+        Some(text) => {
+            for line in text.lines().map(|l| l.trim()).filter(|l| !l.is_empty() && !l.starts_with('#'))
+            {
+                let mut words = line.split_whitespace();
+                let name = String::from(words.next().unwrap_or(""));
+                let mut word = words.next();
+                let frames = word.and_then(|w| w.parse().ok());
+                if frames.is_some() {
+                    word = words.next();
+                }
+                let protocol = parse_protocol(word, &name);
+                tests.push((name, frames.unwrap_or(default_frames(protocol)), protocol));
+            }
+        }
+        None => {
+            for (name, _) in romdisk::list().into_iter().filter(|(n, _)| n.ends_with(".nes") && n != MMC3_ALT_TEST)
+            {
+                let protocol = parse_protocol(None, &name);
+                tests.push((name, default_frames(protocol), protocol));
+            }
+        }
+    }
+    tests
+}
+
+#[test_case]
+fn attached_test_roms()
+{
+    let tests = attached_tests();
+    // one rom can also come in through fw_cfg, for running something once without building a disk.
+    let fw_cfg_rom = fw_cfg::read_file("opt/nesos/testrom");
+    if tests.is_empty() && fw_cfg_rom.is_none() {
+        serial_println!("no test roms attached, skipping");
+        return;
+    }
+
+    let mut failed = 0;
+    for (name, frames, protocol) in tests.iter()
+    {
+        let report = match romdisk::read(name) {
+            Some(data) => run_test_rom(&data, *protocol, *frames),
+            None => {
+                serial_println!("ROMTEST FAIL {} missing from the rom disk", name);
+                failed += 1;
+                continue;
+            }
+        };
+        report.print(name);
+        if !report.passed() {
+            failed += 1;
+        }
+    }
+    if let Some(data) = &fw_cfg_rom {
+        let report = run_test_rom(data, Protocol::Blargg, DEFAULT_FRAMES);
+        report.print("opt/nesos/testrom");
+        if !report.passed() {
+            failed += 1;
+        }
+    }
+
+    serial_println!("{} of {} test roms failed", failed, tests.len() + fw_cfg_rom.is_some() as usize);
+    if failed > 0 {
+        fail();
+    }
+}
+
+#[test_case]
+fn nestest_golden_log()
+{
+    let (rom, log) = match (romdisk::read("nestest.nes"), romdisk::read("nestest.log")) {
+        (Some(rom), Some(log)) => (rom, log),
+        _ => {
+            serial_println!("no nestest.nes and nestest.log on the rom disk, skipping");
+            return;
+        }
+    };
+    let log = String::from_utf8_lossy(&log);
+
+    match run_nestest_trace(&rom, &log) {
+        Ok(lines) => serial_println!("nestest trace matched all {} lines", lines),
+        Err(d) => {
+            serial_println!("nestest trace diverged at line {} ({}):", d.line, d.field);
+            serial_println!("  expected {}", d.expected);
+            serial_println!("  got      {}", d.got);
+            fail();
+        }
+    }
+}
+
+#[test_case]
+fn mmc3_irq_tests()
+{
+    if cmdline::flag("skip_mmc3") {
+        serial_println!("ROMTEST SKIP mmc3 tests, skip_mmc3 is set");
+        return;
+    }
+    let mut roms = Vec::new();
+    for &name in MMC3_TESTS.iter()
+    {
+        match romdisk::read(name) {
+            Some(data) => roms.push((name, data)),
+            None => serial_println!("ROMTEST FAIL {} missing from the rom disk", name),
+        }
+    }
+    if roms.len() < MMC3_TESTS.len() {
+        let missing = MMC3_TESTS.len() - roms.len();
+        serial_println!("{} of the mmc3 tests are missing, see the README or boot with skip_mmc3", missing);
+        return fail();
+    }
+
+    let mut failed = 0;
+    for (name, data) in roms.iter()
+    {
+        let report = run_test_rom(data, Protocol::Blargg, DEFAULT_FRAMES);
+        report.print(name);
+        if !report.passed() {
+            failed += 1;
+        }
+    }
+    serial_println!("{} of {} mmc3 tests failed", failed, roms.len());
+    if failed > 0 {
+        fail();
+    }
+}